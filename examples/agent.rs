@@ -0,0 +1,26 @@
+use flatline::agent::Agent;
+use flatline::handshake::Config;
+use flatline::session::Session;
+use flatline::session::Userauth;
+use tokio::net::TcpStream;
+
+include!("./user.conf");
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let socket = TcpStream::connect(HOST).await.unwrap();
+    let config = Config::default_with_behavior();
+    let session = Session::handshake(config, socket).await.unwrap();
+
+    let mut agent = Agent::connect_env().await.unwrap();
+
+    for identity in agent.request_identities().await.unwrap() {
+        println!("{} {}", identity.key_type, identity.comment);
+    }
+
+    let status = session.userauth_agent(USERNAME, &mut agent).await.unwrap();
+
+    assert!(matches!(status, Userauth::Success));
+
+    session.disconnect_default().await.unwrap();
+}
@@ -61,7 +61,7 @@ async fn main() {
                 let mut remote = session
                     .direct_tcpip_default(
                         SocketAddr::new("127.0.0.1".to_string(), 5000),
-                        SocketAddr::new(local_addr.ip().to_string(), local_addr.port() as u32),
+                        SocketAddr::new(local_addr.ip().to_string(), local_addr.port()),
                     )
                     .await
                     .unwrap();
@@ -11,7 +11,6 @@ include!("./user.conf");
 
 /// # add to /etc/ssh/sshd_config and restart ssh-server if you were using openssh
 /// KbdInteractiveAuthentication yes
-
 pub struct Keyboard {}
 
 #[async_trait]
@@ -19,7 +19,7 @@ async fn main() {
 
     assert!(matches!(status, Userauth::Success));
 
-    let mut sftp = session.sftp_open_default().await.unwrap();
+    let sftp = session.sftp_open_default().await.unwrap();
 
     let mut remote_file = sftp
         .open_file(
@@ -1,5 +1,6 @@
 use std::env;
 
+use flatline::agent::AgentRequest;
 use flatline::error::Result;
 use flatline::forward::Stream;
 use flatline::handshake::Behavior;
@@ -8,6 +9,7 @@ use flatline::session::DisconnectReson;
 use flatline::session::Session;
 use flatline::session::Userauth;
 use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio::net::UnixStream;
 use tokio::process::Command;
@@ -30,7 +32,7 @@ impl Behavior for User {
         Ok(())
     }
 
-    async fn useauth_banner(&mut self, _: &str, _: &str) -> Result<()> {
+    async fn userauth_banner(&mut self, _: &str, _: &str) -> Result<()> {
         Ok(())
     }
 
@@ -46,6 +48,10 @@ impl Behavior for User {
         Ok(())
     }
 
+    async fn confirm_agent_forward(&mut self, _: &AgentRequest) -> Result<bool> {
+        Ok(false)
+    }
+
     async fn x11_forward(&mut self, mut stream: Stream) -> Result<()> {
         let display = env::var("DISPLAY").unwrap_or(":0".to_string());
         let screen_number = display
@@ -60,9 +66,27 @@ impl Behavior for User {
         let mut socket = UnixStream::connect(x11).await?;
 
         tokio::spawn(async move {
-            tokio::io::copy_bidirectional(&mut stream, &mut socket)
-                .await
-                .unwrap();
+            let mut buf = vec![0; 4096];
+            loop {
+                tokio::select! {
+                    data = stream.read() => {
+                        let Ok(data) = data else { break };
+                        if socket.write_all(&data).await.is_err() {
+                            break;
+                        }
+                    }
+                    len = socket.read(&mut buf) => {
+                        match len {
+                            Ok(0) | Err(_) => break,
+                            Ok(len) => {
+                                if stream.write(&buf[..len]).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
         });
 
         Ok(())
@@ -128,7 +152,7 @@ async fn system_x11_protocol_cookie() -> (String, String, u32) {
     let mut cookie = "396d4663579aa232088631bcf8b9588b";
     let mut procotol = "MIT-MAGIC-COOKIE-1";
 
-    if let Some(line) = lines.get(0) {
+    if let Some(line) = lines.first() {
         let parts: Vec<_> = line.split_whitespace().collect();
         if parts.len() == 3 {
             procotol = parts[1];
@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::{read_message, write_message, AgentRequest};
+use crate::channel::{BufferChannel, Channel};
+use crate::error::{builder, Error, Result};
+use crate::msg::Request;
+use crate::ssh::buffer::Buffer;
+use crate::ssh::common::{code::*, AGENT_MAXIMUM_SIZE};
+use crate::{o_channel, MSender};
+
+pub trait AgentStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AgentStream for T {}
+
+/// Opens a connection to an agent for every forwarded `auth-agent@openssh.com` channel
+#[async_trait::async_trait]
+pub trait AgentConnector: Send + Sync {
+    async fn connect(&self) -> Result<Box<dyn AgentStream>>;
+}
+
+/// Where the channels opened by the server after `Channel::request_agent_forward` go
+#[derive(Default, Clone)]
+pub enum AgentForward {
+    Refuse,
+    /// The agent listening on `SSH_AUTH_SOCK`
+    #[default]
+    Env,
+    Path(PathBuf),
+    Custom(Arc<dyn AgentConnector>),
+}
+
+impl AgentForward {
+    pub(crate) async fn connect(&self) -> Result<Option<Box<dyn AgentStream>>> {
+        match self {
+            Self::Refuse => Ok(None),
+            #[cfg(unix)]
+            Self::Env => {
+                let agent = super::Agent::connect_env().await?;
+                Ok(Some(Box::new(agent.into_inner())))
+            }
+            #[cfg(unix)]
+            Self::Path(path) => {
+                let agent = super::Agent::connect(path).await?;
+                Ok(Some(Box::new(agent.into_inner())))
+            }
+            #[cfg(not(unix))]
+            Self::Env | Self::Path(_) => builder::AgentFailure {
+                tip: "unix socket is unsupported on this platform",
+            }
+            .fail(),
+            Self::Custom(connector) => Ok(Some(connector.connect().await?)),
+        }
+    }
+}
+
+/// Connect to the agent and pass the requests of `channel` on, the session hears of what went wrong
+pub(crate) async fn relay(channel: Channel, forward: AgentForward, session: MSender<Request>) {
+    let res = match forward.connect().await {
+        Ok(Some(agent)) => serve(channel, agent, &session).await,
+        Ok(None) => channel.close().await,
+        Err(e) => {
+            let _ = channel.close().await;
+            Err(e)
+        }
+    };
+    if let Err(error) = res {
+        let _ = session.send(Request::AgentForwardFailed { error });
+    }
+}
+
+async fn serve(
+    channel: Channel,
+    mut agent: Box<dyn AgentStream>,
+    session: &MSender<Request>,
+) -> Result<()> {
+    let mut channel = BufferChannel::new(channel);
+
+    loop {
+        let len = match channel.fill(4).await {
+            Ok(len) => u32::from_be_bytes(len.try_into().unwrap()) as usize,
+            Err(Error::ChannelEof { .. } | Error::ChannelClosed { .. }) => break,
+            Err(e) => return Err(e),
+        };
+
+        if len > AGENT_MAXIMUM_SIZE {
+            return Err(Error::invalid_format("agent message too long"));
+        }
+
+        let payload = channel.fill(4 + len).await?[4..].to_vec();
+        channel.consume(4 + len);
+
+        let request = AgentRequest::parse(&payload)
+            .unwrap_or(AgentRequest::Unknown(payload.first().copied().unwrap_or(0)));
+
+        let (sender, recver) = o_channel();
+        session
+            .send(Request::AgentForwardConfirm { request, sender })
+            .map_err(|_| builder::Disconnected.build())?;
+
+        let reply = if recver.await?? {
+            write_message(&mut agent, &payload).await?;
+            read_message(&mut agent).await?
+        } else {
+            vec![SSH_AGENT_FAILURE]
+        };
+
+        let buffer = make_buffer! {
+            bytes: reply,
+        };
+        channel.write_all(buffer).await?;
+    }
+
+    channel.into_inner().close().await
+}
@@ -0,0 +1,484 @@
+use std::cell::Cell;
+
+use bitflags::bitflags;
+use derive_new::new;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+use crate::error::{builder, Error, Result};
+use crate::keys::{self, PrivateKey, Signer};
+use crate::ssh::buffer::Buffer;
+use crate::ssh::common::{code::*, AGENT_MAXIMUM_SIZE};
+
+#[cfg(unix)]
+use crate::ssh::common::AGENT_SOCK_ENV;
+
+mod forward;
+mod server;
+
+pub(crate) use forward::relay;
+pub use forward::{AgentConnector, AgentForward, AgentStream};
+pub use server::{AgentServer, Confirm, SessionBind};
+
+bitflags! {
+    // https://datatracker.ietf.org/doc/html/draft-miller-ssh-agent#section-5.3
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+    pub struct SignFlags: u32 {
+        const RSA_SHA2_256 = SSH_AGENT_RSA_SHA2_256;
+        const RSA_SHA2_512 = SSH_AGENT_RSA_SHA2_512;
+    }
+}
+
+impl SignFlags {
+    /// Flags the agent needs to produce a signature of `algo` with the identity.
+    pub fn from_algorithm(algo: &str) -> Self {
+        match algo {
+            "rsa-sha2-256" | "rsa-sha2-256-cert-v01@openssh.com" => Self::RSA_SHA2_256,
+            "rsa-sha2-512" | "rsa-sha2-512-cert-v01@openssh.com" => Self::RSA_SHA2_512,
+            _ => Self::empty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Constraint {
+    /// Seconds after which the key is removed
+    Lifetime(u32),
+    /// Every use of the key must be confirmed
+    Confirm,
+    Extension(String, Vec<u8>),
+}
+
+#[derive(new, Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub key_type: String,
+    pub key: Vec<u8>,
+    pub comment: String,
+}
+
+impl Identity {
+    fn parse(key: &[u8], comment: &[u8]) -> Result<Self> {
+        let blob = Buffer::from_slice(key);
+        let (_, key_type) = blob
+            .take_one()
+            .ok_or(Error::invalid_format("invalid identity key blob"))?;
+
+        Ok(Self::new(
+            std::str::from_utf8(key_type)?.to_string(),
+            key.to_vec(),
+            String::from_utf8_lossy(comment).to_string(),
+        ))
+    }
+}
+
+/// A request sent to an agent, as seen by the side relaying or serving it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentRequest {
+    RequestIdentities,
+    Sign {
+        key: Vec<u8>,
+        data: Vec<u8>,
+        flags: SignFlags,
+    },
+    AddIdentity {
+        key_type: String,
+        constrained: bool,
+    },
+    RemoveIdentity(Vec<u8>),
+    RemoveAllIdentities,
+    Lock,
+    Unlock,
+    Extension(String),
+    Unknown(u8),
+}
+
+impl AgentRequest {
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let payload = Buffer::from_slice(payload);
+
+        let func = || {
+            let request = match payload.take_u8()? {
+                SSH_AGENTC_REQUEST_IDENTITIES => Self::RequestIdentities,
+                SSH_AGENTC_SIGN_REQUEST => Self::Sign {
+                    key: payload.take_one()?.1.to_vec(),
+                    data: payload.take_one()?.1.to_vec(),
+                    flags: SignFlags::from_bits_retain(payload.take_u32().unwrap_or(0)),
+                },
+                code @ (SSH_AGENTC_ADD_IDENTITY | SSH_AGENTC_ADD_ID_CONSTRAINED) => {
+                    Self::AddIdentity {
+                        key_type: String::from_utf8_lossy(payload.take_one()?.1).to_string(),
+                        constrained: code == SSH_AGENTC_ADD_ID_CONSTRAINED,
+                    }
+                }
+                SSH_AGENTC_REMOVE_IDENTITY => Self::RemoveIdentity(payload.take_one()?.1.to_vec()),
+                SSH_AGENTC_REMOVE_ALL_IDENTITIES => Self::RemoveAllIdentities,
+                SSH_AGENTC_LOCK => Self::Lock,
+                SSH_AGENTC_UNLOCK => Self::Unlock,
+                SSH_AGENTC_EXTENSION => {
+                    Self::Extension(String::from_utf8_lossy(payload.take_one()?.1).to_string())
+                }
+                code => Self::Unknown(code),
+            };
+            Some(request)
+        };
+
+        func().ok_or(Error::invalid_format("invalid agent request"))
+    }
+}
+
+/// One identity held by an agent, used as a `Signer`
+pub struct AgentSigner<'a, T> {
+    agent: Mutex<&'a mut Agent<T>>,
+    identity: Identity,
+}
+
+impl<'a, T> AgentSigner<'a, T> {
+    pub fn new(agent: &'a mut Agent<T>, identity: Identity) -> Self {
+        Self {
+            agent: Mutex::new(agent),
+            identity,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Signer for AgentSigner<'_, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn public_key(&self) -> &[u8] {
+        &self.identity.key
+    }
+
+    fn algorithms(&self) -> Vec<String> {
+        keys::algorithms(&self.identity.key_type)
+    }
+
+    async fn sign(&self, data: &[u8], algorithm: &str) -> Result<Vec<u8>> {
+        self.agent
+            .lock()
+            .await
+            .sign(
+                &self.identity.key,
+                data,
+                SignFlags::from_algorithm(algorithm),
+            )
+            .await
+    }
+}
+
+/// A client of the ssh-agent protocol.
+pub struct Agent<T> {
+    stream: T,
+}
+
+#[cfg(unix)]
+impl Agent<UnixStream> {
+    pub async fn connect(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let stream = UnixStream::connect(path).await?;
+        Ok(Self::new(stream))
+    }
+
+    /// Connect to the agent listening on `SSH_AUTH_SOCK`
+    pub async fn connect_env() -> Result<Self> {
+        let path = std::env::var_os(AGENT_SOCK_ENV).ok_or(
+            builder::AgentFailure {
+                tip: format!("{} is not set", AGENT_SOCK_ENV),
+            }
+            .build(),
+        )?;
+        Self::connect(path).await
+    }
+}
+
+impl<T> Agent<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: T) -> Self {
+        Self { stream }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+
+    pub async fn request_identities(&mut self) -> Result<Vec<Identity>> {
+        let reply = self
+            .request(&make_buffer_without_header! {
+                u8: SSH_AGENTC_REQUEST_IDENTITIES,
+            })
+            .await?;
+
+        let reply = Buffer::from_slice(&reply);
+        let code = reply
+            .take_u8()
+            .ok_or(Error::invalid_format("empty reply"))?;
+        if code != SSH_AGENT_IDENTITIES_ANSWER {
+            return builder::AgentFailure {
+                tip: "failed to request identities",
+            }
+            .fail();
+        }
+
+        let nkeys = reply
+            .take_u32()
+            .ok_or(Error::invalid_format("invalid identities answer"))?;
+
+        let mut identities = vec![];
+        for _ in 0..nkeys {
+            let func = || Some((reply.take_one()?.1, reply.take_one()?.1));
+
+            let (key, comment) =
+                func().ok_or(Error::invalid_format("invalid identities answer"))?;
+
+            identities.push(Identity::parse(key, comment)?);
+        }
+
+        Ok(identities)
+    }
+
+    /// Returns a signature blob: `string algorithm, string signature`
+    pub async fn sign(&mut self, key: &[u8], data: &[u8], flags: SignFlags) -> Result<Vec<u8>> {
+        let reply = self
+            .request(&make_buffer_without_header! {
+                u8: SSH_AGENTC_SIGN_REQUEST,
+                one: key,
+                one: data,
+                u32: flags.bits(),
+            })
+            .await?;
+
+        let reply = Buffer::from_slice(&reply);
+        let code = reply
+            .take_u8()
+            .ok_or(Error::invalid_format("empty reply"))?;
+        if code != SSH_AGENT_SIGN_RESPONSE {
+            return builder::AgentFailure {
+                tip: "agent refused to sign",
+            }
+            .fail();
+        }
+
+        let (_, signature) = reply
+            .take_one()
+            .ok_or(Error::invalid_format("invalid sign response"))?;
+
+        Ok(signature.to_vec())
+    }
+
+    pub async fn add_identity(
+        &mut self,
+        key: &PrivateKey,
+        constraints: &[Constraint],
+    ) -> Result<()> {
+        let blob = key.key_blob()?;
+
+        let mut buffer = Buffer::new();
+        if constraints.is_empty() {
+            buffer.put_u8(SSH_AGENTC_ADD_IDENTITY);
+        } else {
+            buffer.put_u8(SSH_AGENTC_ADD_ID_CONSTRAINED);
+        }
+        buffer.put_bytes(blob);
+        buffer.put_one(&key.comment);
+
+        for constraint in constraints {
+            match constraint {
+                Constraint::Lifetime(seconds) => {
+                    buffer.put_u8(SSH_AGENT_CONSTRAIN_LIFETIME);
+                    buffer.put_u32(*seconds);
+                }
+                Constraint::Confirm => buffer.put_u8(SSH_AGENT_CONSTRAIN_CONFIRM),
+                Constraint::Extension(name, content) => {
+                    buffer.put_u8(SSH_AGENT_CONSTRAIN_EXTENSION);
+                    buffer.put_one(name);
+                    buffer.put_bytes(content);
+                }
+            }
+        }
+
+        self.simple_request(&buffer, "failed to add identity").await
+    }
+
+    pub async fn remove_identity(&mut self, key: &[u8]) -> Result<()> {
+        let buffer = make_buffer_without_header! {
+            u8: SSH_AGENTC_REMOVE_IDENTITY,
+            one: key,
+        };
+        self.simple_request(&buffer, "failed to remove identity")
+            .await
+    }
+
+    pub async fn remove_all_identities(&mut self) -> Result<()> {
+        let buffer = make_buffer_without_header! {
+            u8: SSH_AGENTC_REMOVE_ALL_IDENTITIES,
+        };
+        self.simple_request(&buffer, "failed to remove all identities")
+            .await
+    }
+
+    pub async fn lock(&mut self, passphrase: impl AsRef<[u8]>) -> Result<()> {
+        let buffer = make_buffer_without_header! {
+            u8: SSH_AGENTC_LOCK,
+            one: passphrase.as_ref(),
+        };
+        self.simple_request(&buffer, "failed to lock agent").await
+    }
+
+    pub async fn unlock(&mut self, passphrase: impl AsRef<[u8]>) -> Result<()> {
+        let buffer = make_buffer_without_header! {
+            u8: SSH_AGENTC_UNLOCK,
+            one: passphrase.as_ref(),
+        };
+        self.simple_request(&buffer, "failed to unlock agent").await
+    }
+
+    async fn simple_request(&mut self, payload: &[u8], tip: &str) -> Result<()> {
+        let reply = self.request(payload).await?;
+        match reply.first() {
+            Some(&SSH_AGENT_SUCCESS) => Ok(()),
+            _ => builder::AgentFailure { tip }.fail(),
+        }
+    }
+
+    async fn request(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        write_message(&mut self.stream, payload).await?;
+        read_message(&mut self.stream).await
+    }
+}
+
+pub(crate) async fn write_message<T>(stream: &mut T, payload: &[u8]) -> Result<()>
+where
+    T: AsyncWrite + Unpin,
+{
+    let buffer = make_buffer! {
+        bytes: payload,
+    };
+    stream.write_all(&buffer).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+pub(crate) async fn read_message<T>(stream: &mut T) -> Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin,
+{
+    let len = stream.read_u32().await? as usize;
+    if len > AGENT_MAXIMUM_SIZE {
+        return Err(Error::invalid_format("agent message too long"));
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+/// The reverse of `PrivateKey::key_blob`, the comment is left in the buffer
+fn parse_agent_key(buffer: &Buffer<Cell<&[u8]>>) -> Result<PrivateKey> {
+    let invalid_key_format = || Error::invalid_format("invalid agent key format");
+    let take_one = || Result::Ok(buffer.take_one().ok_or_else(invalid_key_format)?.1);
+
+    let key_type = std::str::from_utf8(take_one()?)?.to_string();
+
+    let (public_key, private_key) = match key_type.as_str() {
+        "ssh-ed25519" => {
+            let pk = take_one()?;
+            let secret = take_one()?;
+            if pk.len() != 32 || secret.len() != 64 || &secret[32..] != pk {
+                return Err(invalid_key_format());
+            }
+
+            let public_key = make_buffer_without_header! {
+                one: &key_type,
+                one: pk,
+            };
+            let private_key = make_buffer_without_header! {
+                one: &key_type,
+                one: &secret[..32],
+            };
+            (public_key, private_key)
+        }
+        "ssh-rsa" => {
+            let n = take_one()?;
+            let e = take_one()?;
+            let d = take_one()?;
+            let iqmp = take_one()?;
+            let p = take_one()?;
+            let q = take_one()?;
+
+            let public_key = make_buffer_without_header! {
+                one: &key_type,
+                one: e,
+                one: n,
+            };
+            let private_key = make_buffer_without_header! {
+                one: &key_type,
+                one: n,
+                one: e,
+                one: d,
+                one: iqmp,
+                one: p,
+                one: q,
+            };
+            (public_key, private_key)
+        }
+        "ssh-dss" => {
+            let p = take_one()?;
+            let q = take_one()?;
+            let g = take_one()?;
+            let y = take_one()?;
+            let x = take_one()?;
+
+            let public_key = make_buffer_without_header! {
+                one: &key_type,
+                one: p,
+                one: q,
+                one: g,
+                one: y,
+            };
+            let private_key = make_buffer_without_header! {
+                one: &key_type,
+                one: p,
+                one: q,
+                one: g,
+                one: y,
+                one: x,
+            };
+            (public_key, private_key)
+        }
+        ty if ty.starts_with("ecdsa-sha2-") => {
+            let curve = take_one()?;
+            let point = take_one()?;
+            let e = take_one()?;
+
+            let public_key = make_buffer_without_header! {
+                one: &key_type,
+                one: curve,
+                one: point,
+            };
+            let private_key = make_buffer_without_header! {
+                one: &key_type,
+                one: curve,
+                one: point,
+                one: e,
+            };
+            (public_key, private_key)
+        }
+        ty => {
+            return builder::AgentFailure {
+                tip: format!("unsupported key type: {}", ty),
+            }
+            .fail()
+        }
+    };
+
+    Ok(PrivateKey::new(
+        key_type,
+        public_key.into_vec(),
+        private_key.into_vec(),
+        String::new(),
+    ))
+}
@@ -0,0 +1,479 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use openssl::rand::rand_bytes;
+
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use super::{
+    parse_agent_key, read_message, write_message, AgentConnector, AgentStream, Constraint,
+    Identity, SignFlags,
+};
+use crate::cipher::sign;
+use crate::error::{builder, Error, Result};
+use crate::keys::PrivateKey;
+use crate::ssh::buffer::Buffer;
+use crate::ssh::common::code::*;
+
+const SESSION_BIND: &str = "session-bind@openssh.com";
+const SESSION_BIND_MAXIMUM: usize = 16;
+
+/// Asks whether a key added with `Constraint::Confirm` may be used
+#[async_trait::async_trait]
+pub trait Confirm: Send + Sync {
+    async fn confirm(&self, identity: &Identity, binds: &[SessionBind]) -> bool;
+}
+
+/// A `session-bind@openssh.com` record of the connection a request comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionBind {
+    pub hostkey: Vec<u8>,
+    pub session_id: Vec<u8>,
+    pub forwarding: bool,
+}
+
+struct Key {
+    key: PrivateKey,
+    expire: Option<Instant>,
+    confirm: bool,
+}
+
+impl Key {
+    fn identity(&self) -> Identity {
+        Identity::new(
+            self.key.key_type.clone(),
+            self.key.public_key.clone(),
+            self.key.comment.clone(),
+        )
+    }
+}
+
+#[derive(Default)]
+struct State {
+    keys: Vec<Key>,
+    // salt, bcrypt-pbkdf(passphrase)
+    lock: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl State {
+    fn purge(&mut self) {
+        let now = Instant::now();
+        self.keys
+            .retain(|key| key.expire.map(|expire| expire > now).unwrap_or(true));
+    }
+
+    fn find(&self, public_key: &[u8]) -> Option<&Key> {
+        self.keys.iter().find(|v| v.key.public_key == public_key)
+    }
+}
+
+/// An ssh-agent serving keys held in memory
+#[derive(Clone, Default)]
+pub struct AgentServer {
+    state: Arc<Mutex<State>>,
+    confirm: Option<Arc<dyn Confirm>>,
+}
+
+impl AgentServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Without a confirm callback, keys constrained with `Constraint::Confirm` can't be used
+    pub fn with_confirm(mut self, confirm: impl Confirm + 'static) -> Self {
+        self.confirm = Some(Arc::new(confirm));
+        self
+    }
+
+    pub fn add_identity(&self, key: PrivateKey, constraints: &[Constraint]) -> Result<()> {
+        let mut expire = None;
+        let mut confirm = false;
+        for constraint in constraints {
+            match constraint {
+                Constraint::Lifetime(seconds) => {
+                    expire = Some(Instant::now() + Duration::from_secs(*seconds as u64))
+                }
+                Constraint::Confirm => confirm = true,
+                Constraint::Extension(name, _) => {
+                    return builder::AgentFailure {
+                        tip: format!("unsupported constraint: {}", name),
+                    }
+                    .fail()
+                }
+            }
+        }
+
+        let mut state = self.lock_state();
+        state.keys.retain(|v| v.key.public_key != key.public_key);
+        state.keys.push(Key {
+            key,
+            expire,
+            confirm,
+        });
+        Ok(())
+    }
+
+    pub fn remove_identity(&self, public_key: &[u8]) -> bool {
+        let mut state = self.lock_state();
+        let len = state.keys.len();
+        state.keys.retain(|v| v.key.public_key != public_key);
+        len != state.keys.len()
+    }
+
+    pub fn remove_all_identities(&self) {
+        self.lock_state().keys.clear();
+    }
+
+    pub fn identities(&self) -> Vec<Identity> {
+        let mut state = self.lock_state();
+        state.purge();
+        if state.lock.is_some() {
+            return vec![];
+        }
+        state.keys.iter().map(Key::identity).collect()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock_state().lock.is_some()
+    }
+
+    pub fn lock(&self, passphrase: &[u8]) -> Result<()> {
+        let mut state = self.lock_state();
+        if state.lock.is_some() {
+            return builder::AgentFailure {
+                tip: "agent is already locked",
+            }
+            .fail();
+        }
+
+        let mut salt = vec![0; 16];
+        rand_bytes(&mut salt)?;
+        let hash = hash_passphrase(passphrase, &salt)?;
+        state.lock = Some((salt, hash));
+        Ok(())
+    }
+
+    pub fn unlock(&self, passphrase: &[u8]) -> Result<()> {
+        let mut state = self.lock_state();
+        let Some((ref salt, ref hash)) = state.lock else {
+            return builder::AgentFailure {
+                tip: "agent is not locked",
+            }
+            .fail();
+        };
+
+        if !openssl::memcmp::eq(&hash_passphrase(passphrase, salt)?, hash) {
+            return builder::AgentFailure {
+                tip: "incorrect passphrase",
+            }
+            .fail();
+        }
+
+        state.lock = None;
+        Ok(())
+    }
+
+    /// Bind `path` and serve every incoming connection until an error occurs
+    #[cfg(unix)]
+    pub async fn listen(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let listener = UnixListener::bind(path)?;
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move { server.serve(stream).await });
+        }
+    }
+
+    /// Serve one client connection until it's closed
+    pub async fn serve<T: AgentStream>(&self, mut stream: T) -> Result<()> {
+        let mut binds = vec![];
+        loop {
+            let request = match read_message(&mut stream).await {
+                Ok(request) => request,
+                Err(Error::IOError { source, .. })
+                    if source.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            };
+
+            let reply = self
+                .handle(&request, &mut binds)
+                .await
+                .unwrap_or_else(|_| vec![SSH_AGENT_FAILURE]);
+
+            write_message(&mut stream, &reply).await?;
+        }
+    }
+
+    async fn handle(&self, request: &[u8], binds: &mut Vec<SessionBind>) -> Result<Vec<u8>> {
+        let invalid_format = || Error::invalid_format("invalid agent request");
+        let request = Buffer::from_slice(request);
+        let code = request.take_u8().ok_or_else(invalid_format)?;
+
+        let success = vec![SSH_AGENT_SUCCESS];
+        match code {
+            SSH_AGENTC_REQUEST_IDENTITIES => {
+                let identities = self.identities();
+
+                let mut buffer = Buffer::new();
+                buffer.put_u8(SSH_AGENT_IDENTITIES_ANSWER);
+                buffer.put_u32(identities.len() as u32);
+                for identity in identities {
+                    buffer.put_one(identity.key);
+                    buffer.put_one(identity.comment);
+                }
+                Ok(buffer.into_vec())
+            }
+            SSH_AGENTC_SIGN_REQUEST => {
+                let (_, key) = request.take_one().ok_or_else(invalid_format)?;
+                let (_, data) = request.take_one().ok_or_else(invalid_format)?;
+                let flags = SignFlags::from_bits_retain(request.take_u32().unwrap_or(0));
+
+                let signature = self.sign(key, data, flags, binds).await?;
+
+                let buffer = make_buffer_without_header! {
+                    u8: SSH_AGENT_SIGN_RESPONSE,
+                    one: signature,
+                };
+                Ok(buffer.into_vec())
+            }
+            SSH_AGENTC_ADD_IDENTITY | SSH_AGENTC_ADD_ID_CONSTRAINED => {
+                self.ensure_unlocked()?;
+
+                let mut key = parse_agent_key(&request)?;
+                let (_, comment) = request.take_one().ok_or_else(invalid_format)?;
+                key.comment = String::from_utf8_lossy(comment).to_string();
+
+                let mut constraints = vec![];
+                while code == SSH_AGENTC_ADD_ID_CONSTRAINED && request.len() > 0 {
+                    let constraint = match request.take_u8().ok_or_else(invalid_format)? {
+                        SSH_AGENT_CONSTRAIN_LIFETIME => {
+                            Constraint::Lifetime(request.take_u32().ok_or_else(invalid_format)?)
+                        }
+                        SSH_AGENT_CONSTRAIN_CONFIRM => Constraint::Confirm,
+                        SSH_AGENT_CONSTRAIN_EXTENSION => {
+                            let (_, name) = request.take_one().ok_or_else(invalid_format)?;
+                            Constraint::Extension(
+                                String::from_utf8_lossy(name).to_string(),
+                                request.to_vec(),
+                            )
+                        }
+                        _ => return Err(invalid_format()),
+                    };
+                    constraints.push(constraint);
+                }
+
+                self.add_identity(key, &constraints)?;
+                Ok(success)
+            }
+            SSH_AGENTC_REMOVE_IDENTITY => {
+                self.ensure_unlocked()?;
+                let (_, key) = request.take_one().ok_or_else(invalid_format)?;
+                if self.remove_identity(key) {
+                    Ok(success)
+                } else {
+                    builder::AgentFailure {
+                        tip: "identity not found",
+                    }
+                    .fail()
+                }
+            }
+            SSH_AGENTC_REMOVE_ALL_IDENTITIES => {
+                self.ensure_unlocked()?;
+                self.remove_all_identities();
+                Ok(success)
+            }
+            SSH_AGENTC_LOCK => {
+                let (_, passphrase) = request.take_one().ok_or_else(invalid_format)?;
+                self.lock(passphrase)?;
+                Ok(success)
+            }
+            SSH_AGENTC_UNLOCK => {
+                let (_, passphrase) = request.take_one().ok_or_else(invalid_format)?;
+                self.unlock(passphrase)?;
+                Ok(success)
+            }
+            SSH_AGENTC_EXTENSION => {
+                let (_, name) = request.take_one().ok_or_else(invalid_format)?;
+                if name != SESSION_BIND.as_bytes() {
+                    return builder::AgentFailure {
+                        tip: "unsupported extension",
+                    }
+                    .fail();
+                }
+
+                match session_bind(&request, binds) {
+                    Ok(()) => Ok(success),
+                    Err(_) => Ok(vec![SSH_AGENT_EXTENSION_FAILURE]),
+                }
+            }
+            _ => builder::AgentFailure {
+                tip: "unsupported request",
+            }
+            .fail(),
+        }
+    }
+
+    async fn sign(
+        &self,
+        public_key: &[u8],
+        data: &[u8],
+        flags: SignFlags,
+        binds: &[SessionBind],
+    ) -> Result<Vec<u8>> {
+        self.ensure_unlocked()?;
+
+        let (identity, confirm) = {
+            let mut state = self.lock_state();
+            state.purge();
+            let key = state.find(public_key).ok_or(
+                builder::AgentFailure {
+                    tip: "identity not found",
+                }
+                .build(),
+            )?;
+            (key.identity(), key.confirm)
+        };
+
+        if confirm {
+            let confirmed = match self.confirm {
+                Some(ref confirm) => confirm.confirm(&identity, binds).await,
+                None => false,
+            };
+
+            snafu::ensure!(
+                confirmed,
+                builder::AgentFailure {
+                    tip: "use of key was not confirmed"
+                }
+            );
+        }
+
+        let method = match identity.key_type.as_str() {
+            "ssh-rsa" if flags.contains(SignFlags::RSA_SHA2_512) => "rsa-sha2-512",
+            "ssh-rsa" if flags.contains(SignFlags::RSA_SHA2_256) => "rsa-sha2-256",
+            method => method,
+        };
+
+        let mut algo = sign::new_signature_by_name(method).ok_or(
+            builder::AgentFailure {
+                tip: format!("unsupported key type: {}", method),
+            }
+            .build(),
+        )?();
+
+        let signature = {
+            let state = self.lock_state();
+            // the key may have been removed while waiting for the confirmation
+            let key = state.find(public_key).ok_or(
+                builder::AgentFailure {
+                    tip: "identity not found",
+                }
+                .build(),
+            )?;
+            algo.initialize(&key.key.private_key)?;
+            algo.signature(data)?
+        };
+
+        let buffer = make_buffer_without_header! {
+            one: method,
+            one: signature,
+        };
+        Ok(buffer.into_vec())
+    }
+
+    fn ensure_unlocked(&self) -> Result<()> {
+        snafu::ensure!(
+            !self.is_locked(),
+            builder::AgentFailure {
+                tip: "agent is locked"
+            }
+        );
+        Ok(())
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait::async_trait]
+impl AgentConnector for AgentServer {
+    async fn connect(&self) -> Result<Box<dyn AgentStream>> {
+        let (client, server) = tokio::io::duplex(4096);
+        let agent = self.clone();
+        tokio::spawn(async move { agent.serve(server).await });
+        Ok(Box::new(client))
+    }
+}
+
+// https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.agent
+fn session_bind(
+    request: &Buffer<std::cell::Cell<&[u8]>>,
+    binds: &mut Vec<SessionBind>,
+) -> Result<()> {
+    let invalid_format = || Error::invalid_format("invalid session-bind request");
+
+    let (_, hostkey) = request.take_one().ok_or_else(invalid_format)?;
+    let (_, session_id) = request.take_one().ok_or_else(invalid_format)?;
+    let (_, signature) = request.take_one().ok_or_else(invalid_format)?;
+    let forwarding = request.take_u8().ok_or_else(invalid_format)? != 0;
+
+    let (_, method) = Buffer::from_slice(signature)
+        .take_one()
+        .ok_or_else(invalid_format)?;
+
+    let mut algo = sign::new_verify_by_name(std::str::from_utf8(method)?)
+        .ok_or(Error::invalid_format("unsupported hostkey type"))?();
+    algo.initialize(hostkey)?;
+    snafu::ensure!(
+        algo.verify(signature, session_id)?,
+        builder::AgentFailure {
+            tip: "invalid session-bind signature"
+        }
+    );
+
+    if let Some(bind) = binds.iter().find(|v| v.session_id == session_id) {
+        // the same session may be bound again by a forwarded connection
+        snafu::ensure!(
+            bind.hostkey == hostkey,
+            builder::AgentFailure {
+                tip: "session already bound to a different hostkey"
+            }
+        );
+        return Ok(());
+    }
+
+    // a connection used for authentication can't be bound any further
+    snafu::ensure!(
+        binds.last().map(|v| v.forwarding).unwrap_or(true),
+        builder::AgentFailure {
+            tip: "connection already bound for authentication"
+        }
+    );
+
+    snafu::ensure!(
+        binds.len() < SESSION_BIND_MAXIMUM,
+        builder::AgentFailure {
+            tip: "too many session binds"
+        }
+    );
+
+    binds.push(SessionBind {
+        hostkey: hostkey.to_vec(),
+        session_id: session_id.to_vec(),
+        forwarding,
+    });
+
+    Ok(())
+}
+
+fn hash_passphrase(passphrase: &[u8], salt: &[u8]) -> Result<Vec<u8>> {
+    let mut hash = vec![0; 32];
+    bcrypt_pbkdf::bcrypt_pbkdf(passphrase, salt, 1, &mut hash)
+        .map_err(|e| Error::invalid_format(e.to_string()))?;
+    Ok(hash)
+}
@@ -0,0 +1,181 @@
+//! Drive the whole user authentication from the methods the server advertises
+
+use crate::error::{Error, Result};
+use crate::keys::{Fingerprint, Signer};
+use crate::session::{Interactive, Session, Userauth};
+
+const PUBLICKEY: &str = "publickey";
+const PASSWORD: &str = "password";
+const KEYBOARD_INTERACTIVE: &str = "keyboard-interactive";
+
+/// The credentials `Session::authenticate` may use, each is tried at most once and in the order added
+#[derive(Default)]
+pub struct AuthPlan<'a> {
+    identities: Vec<Box<dyn Signer + 'a>>,
+    passwords: Vec<String>,
+    new_password: Option<String>,
+    interactive: Vec<(Vec<String>, Box<dyn Interactive>)>,
+}
+
+impl<'a> AuthPlan<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A key (or certificate) for `publickey`, e.g. a `PrivateKey` or an `AgentSigner`
+    pub fn identity(mut self, signer: impl Signer + 'a) -> Self {
+        self.identities.push(Box::new(signer));
+        self
+    }
+
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.passwords.push(password.into());
+        self
+    }
+
+    /// Set when the server reports a `password` as expired
+    pub fn new_password(mut self, password: impl Into<String>) -> Self {
+        self.new_password = Some(password.into());
+        self
+    }
+
+    /// A responder for `keyboard-interactive`, `submethods` is passed to the server as is
+    pub fn interactive(
+        mut self,
+        submethods: impl IntoIterator<Item = impl Into<String>>,
+        responder: impl Interactive + 'static,
+    ) -> Self {
+        self.interactive.push((
+            submethods.into_iter().map(|v| v.into()).collect(),
+            Box::new(responder),
+        ));
+        self
+    }
+
+    fn has(&self, method: &str) -> bool {
+        match method {
+            PUBLICKEY => !self.identities.is_empty(),
+            PASSWORD => !self.passwords.is_empty(),
+            KEYBOARD_INTERACTIVE => !self.interactive.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthAttempt {
+    /// e.g. `none`, `publickey`, `password`
+    pub method: String,
+    /// The key fingerprint for `publickey`, the position in the plan for the others
+    pub credential: String,
+    pub result: Userauth,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthReport {
+    /// `Userauth::Success` or the last answer of the server
+    pub status: Userauth,
+    pub attempts: Vec<AuthAttempt>,
+}
+
+impl AuthReport {
+    pub fn is_success(&self) -> bool {
+        self.status == Userauth::Success
+    }
+}
+
+impl Session {
+    /// Start with `none`, then keep trying the credentials of `plan` for the methods the server
+    /// still allows, in the order the server lists them, until it lets us in or nothing is left.
+    /// A partial success (e.g. `publickey` then `keyboard-interactive`) moves on to the next factor.
+    pub async fn authenticate(
+        &self,
+        username: impl Into<String>,
+        mut plan: AuthPlan<'_>,
+    ) -> Result<AuthReport> {
+        let username = username.into();
+        let mut attempts = vec![];
+
+        let mut status = self.userauth_none(&username).await?;
+        attempts.push(AuthAttempt {
+            method: "none".to_string(),
+            credential: String::new(),
+            result: status.clone(),
+        });
+
+        let (mut passwords, mut interactive) = (0, 0);
+
+        while let Userauth::Failure(methods, _) = &status {
+            let methods = methods.clone();
+
+            let Some(method) = methods.iter().find(|m| plan.has(m)).cloned() else {
+                break;
+            };
+
+            let (credential, result) = match method.as_str() {
+                PUBLICKEY => {
+                    let signer = plan.identities.remove(0);
+                    let credential = Fingerprint::sha256(signer.public_key())?.to_string();
+                    let result = match self
+                        .userauth_publickey_with_signer(&username, &*signer)
+                        .await
+                    {
+                        Ok(result) => result,
+                        // e.g. the user declined an agent confirmation
+                        Err(Error::AgentFailure { .. }) => {
+                            Userauth::Failure(methods.clone(), false)
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    (credential, result)
+                }
+                PASSWORD => {
+                    let password = plan.passwords.remove(0);
+                    passwords += 1;
+                    let mut result = self.userauth_password(&username, &password).await?;
+
+                    if let (Userauth::Expired(_), Some(new)) = (&result, &plan.new_password) {
+                        attempts.push(AuthAttempt {
+                            method: method.clone(),
+                            credential: format!("password #{passwords}"),
+                            result,
+                        });
+                        result = self
+                            .userauth_password_change(&username, password, new)
+                            .await?;
+                    }
+                    (format!("password #{passwords}"), result)
+                }
+                _ => {
+                    let (submethods, responder) = plan.interactive.remove(0);
+                    interactive += 1;
+                    let result = self
+                        .userauth_keyboard_interactive_status(
+                            username.clone(),
+                            submethods,
+                            responder,
+                        )
+                        .await?;
+                    (format!("responder #{interactive}"), result)
+                }
+            };
+
+            // the server doesn't always repeat the method list, e.g. after an expired password
+            status = match result {
+                Userauth::Failure(ref list, partial) if list.is_empty() => {
+                    Userauth::Failure(methods, partial)
+                }
+                Userauth::Expired(_) => Userauth::Failure(methods, false),
+                ref result => result.clone(),
+            };
+
+            attempts.push(AuthAttempt {
+                method,
+                credential,
+                result,
+            });
+        }
+
+        Ok(AuthReport { status, attempts })
+    }
+}
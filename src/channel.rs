@@ -13,6 +13,7 @@ use tokio::sync::mpsc::error::TryRecvError;
 
 use super::error::Result;
 use super::msg::Request;
+use super::rate::RateLimiter;
 use super::{o_channel, MReceiver, MSender};
 
 #[repr(transparent)]
@@ -244,19 +245,6 @@ impl BufferChannel {
         self.stdout.advance(len);
     }
 
-    pub(crate) async fn write(&mut self, data: impl AsRef<[u8]>) -> Result<()> {
-        if self.closed {
-            // return Err(Error::ChannelClosed);
-            return builder::ChannelClosed.fail();
-        }
-        self.stdin.put(data.as_ref());
-
-        let len = self.channel.write(self.stdin.to_vec()).await?;
-
-        drop(self.stdin.split_to(len));
-        Ok(())
-    }
-
     pub(crate) async fn write_all(&mut self, data: impl AsRef<[u8]>) -> Result<()> {
         if self.closed {
             // return Err(Error::ChannelClosed);
@@ -271,21 +259,15 @@ impl BufferChannel {
         }
         Ok(())
     }
-
-    pub(crate) async fn flush(&mut self) -> Result<()> {
-        while !self.stdin.is_empty() {
-            let len = self.channel.write(self.stdin.to_vec()).await?;
-
-            self.stdin.advance(len);
-        }
-        Ok(())
-    }
 }
 
 pub struct Channel {
     pub(crate) id: u32,
     recver: Option<MReceiver<Message>>,
     session: Option<MSender<Request>>,
+    limiter: Option<RateLimiter>,
+    // the one in the `Config`, shared by every channel of the session
+    session_limiter: Option<RateLimiter>,
 }
 
 impl Debug for Channel {
@@ -335,9 +317,21 @@ impl Channel {
             id,
             recver: channel.into(),
             session: session.into(),
+            limiter: None,
+            session_limiter: None,
         }
     }
 
+    pub(crate) fn with_session_limiter(mut self, limiter: Option<RateLimiter>) -> Self {
+        self.session_limiter = limiter;
+        self
+    }
+
+    /// Limit the writes of this channel, on top of the limit of the session
+    pub fn set_rate_limiter(&mut self, limiter: Option<RateLimiter>) {
+        self.limiter = limiter;
+    }
+
     // fn manually_drop(&mut self) {
     //     unsafe {
     //         ManuallyDrop::drop(&mut self.recver);
@@ -453,6 +447,9 @@ impl Channel {
 
     pub async fn write(&self, data: impl Into<Vec<u8>>) -> Result<usize> {
         let data: Vec<u8> = data.into();
+        for limiter in [&self.limiter, &self.session_limiter].into_iter().flatten() {
+            limiter.acquire(data.len()).await;
+        }
         let (sender, recver) = o_channel();
         let request = Request::ChannelWriteStdout {
             id: self.id,
@@ -558,6 +555,20 @@ impl Channel {
         recver.await?
     }
 
+    /// Ask the server to forward `auth-agent@openssh.com` channels back to the agent configured in `Config::agent_forward`
+    pub async fn request_agent_forward(&self) -> Result<()> {
+        let (sender, recver) = o_channel();
+
+        let request = Request::ChannelAgentForward {
+            id: self.id,
+            sender,
+        };
+
+        self.send_request(request)?;
+
+        recver.await?
+    }
+
     pub async fn xon_xoff(&self, allow: bool) -> Result<()> {
         let (sender, recvr) = o_channel();
         let request = Request::XonXoff {
@@ -163,7 +163,7 @@ impl<A: KexAlgorithm> KeyExChange for StandardKexProtocol<A> {
         let shared_secret = self.algorithm.compute_shared_secret(&server_pubkey)?;
 
         let exchange_hash = compute_standard_exchange_hash(
-            &mut self.algorithm, &config, &hostkey, &client_pubkey, &server_pubkey, &shared_secret,
+            &self.algorithm, &config, &hostkey, &client_pubkey, &server_pubkey, &shared_secret,
         )?;
 
         let hash = self.algorithm.create_hash();
@@ -17,6 +17,7 @@ use openssl::{
 
 use crate::{
     error::{Error, Result},
+    keys::{self, Certificate, SkSignature},
     ssh::buffer::Buffer,
     BigNumExt,
 };
@@ -56,6 +57,56 @@ algo_list!(
     "ecdsa-sha2-nistp521" => Ecdsa::ecdsa_sha2_nistp521(),
     "ecdsa-sha2-nistp256" => Ecdsa::ecdsa_sha2_nistp256(),
     "ecdsa-sha2-nistp384" => Ecdsa::ecdsa_sha2_nistp384(),
+    "ssh-ed25519-cert-v01@openssh.com" => CertVerify::new(
+        "ssh-ed25519-cert-v01@openssh.com",
+        Box::new(Ed25519::new()),
+    ),
+    "rsa-sha2-512-cert-v01@openssh.com" => CertVerify::new(
+        "rsa-sha2-512-cert-v01@openssh.com",
+        Box::new(Rsa::rsa_sha2_512()),
+    ),
+    "rsa-sha2-256-cert-v01@openssh.com" => CertVerify::new(
+        "rsa-sha2-256-cert-v01@openssh.com",
+        Box::new(Rsa::rsa_sha2_256()),
+    ),
+    "ssh-rsa-cert-v01@openssh.com" => CertVerify::new(
+        "ssh-rsa-cert-v01@openssh.com",
+        Box::new(Rsa::ssh_rsa()),
+    ),
+    "ecdsa-sha2-nistp521-cert-v01@openssh.com" => CertVerify::new(
+        "ecdsa-sha2-nistp521-cert-v01@openssh.com",
+        Box::new(Ecdsa::ecdsa_sha2_nistp521()),
+    ),
+    "ecdsa-sha2-nistp256-cert-v01@openssh.com" => CertVerify::new(
+        "ecdsa-sha2-nistp256-cert-v01@openssh.com",
+        Box::new(Ecdsa::ecdsa_sha2_nistp256()),
+    ),
+    "ecdsa-sha2-nistp384-cert-v01@openssh.com" => CertVerify::new(
+        "ecdsa-sha2-nistp384-cert-v01@openssh.com",
+        Box::new(Ecdsa::ecdsa_sha2_nistp384()),
+    ),
+    "sk-ssh-ed25519@openssh.com" => SkVerify::new(
+        "sk-ssh-ed25519@openssh.com",
+        Box::new(Ed25519::new()),
+    ),
+    "sk-ecdsa-sha2-nistp256@openssh.com" => SkVerify::new(
+        "sk-ecdsa-sha2-nistp256@openssh.com",
+        Box::new(Ecdsa::ecdsa_sha2_nistp256()),
+    ),
+    "sk-ssh-ed25519-cert-v01@openssh.com" => CertVerify::new(
+        "sk-ssh-ed25519-cert-v01@openssh.com",
+        Box::new(SkVerify::new(
+            "sk-ssh-ed25519@openssh.com",
+            Box::new(Ed25519::new()),
+        )),
+    ),
+    "sk-ecdsa-sha2-nistp256-cert-v01@openssh.com" => CertVerify::new(
+        "sk-ecdsa-sha2-nistp256-cert-v01@openssh.com",
+        Box::new(SkVerify::new(
+            "sk-ecdsa-sha2-nistp256@openssh.com",
+            Box::new(Ecdsa::ecdsa_sha2_nistp256()),
+        )),
+    ),
 );
 
 pub trait Signature {
@@ -122,6 +173,71 @@ pub trait Verify {
     fn verify(&mut self, signature: &[u8], data: &[u8]) -> Result<bool>;
 }
 
+/// Verifies with the key certified by an openssh certificate,
+/// the certificate itself is checked by `Certificate::verify_host`
+#[derive(new)]
+struct CertVerify {
+    name: &'static str,
+    inner: Box<dyn Verify + Send>,
+}
+
+impl Verify for CertVerify {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn initialize(&mut self, key: &[u8]) -> Result<()> {
+        let certificate = Certificate::parse(key)?;
+        self.inner.initialize(&certificate.public_key)
+    }
+
+    fn verify(&mut self, signature: &[u8], data: &[u8]) -> Result<bool> {
+        self.inner.verify(signature, data)
+    }
+}
+
+/// Verifies the signature of a FIDO security key, the authenticator signs
+/// `sha256(application) || flags || counter || sha256(data)` with the plain key,
+/// https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.u2f.
+/// As OpenSSH does without `no-touch-required`, the key must have been touched
+#[derive(new)]
+struct SkVerify {
+    name: &'static str,
+    inner: Box<dyn Verify + Send>,
+    #[new(default)]
+    application: Vec<u8>,
+}
+
+impl Verify for SkVerify {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn initialize(&mut self, key: &[u8]) -> Result<()> {
+        if Buffer::from_slice(key).take_one().map(|(_, t)| t) != Some(self.name.as_bytes()) {
+            return Err(invalid_key_format!());
+        }
+        let (key, application) = keys::split_public_key(key)?;
+        self.application = application;
+        self.inner.initialize(&key)
+    }
+
+    fn verify(&mut self, signature: &[u8], data: &[u8]) -> Result<bool> {
+        let (algorithm, signature) = SkSignature::parse(signature)?;
+        if algorithm != self.name || signature.flags & keys::SK_USER_PRESENCE == 0 {
+            return Ok(false);
+        }
+
+        let inner = make_buffer_without_header! {
+            one: self.inner.name(),
+            one: &signature.signature,
+        };
+        let signed =
+            keys::signed_data(&self.application, signature.flags, signature.counter, data)?;
+        self.inner.verify(&inner, &signed)
+    }
+}
+
 impl Verify for Ed25519 {
     fn verify(&mut self, signature: &[u8], data: &[u8]) -> Result<bool> {
         let signature = Buffer::from_slice(signature);
@@ -645,7 +761,8 @@ impl Verify for Ecdsa<Public> {
         let mut ctx = PkeyCtx::new(key)?;
 
         ctx.verify_init()?;
-        Ok(ctx.verify(&hash, &signature)?)
+        // openssl 3 reports a mismatched ecdsa signature as an error
+        Ok(ctx.verify(&hash, &signature).unwrap_or(false))
     }
 
     fn name(&self) -> &str {
@@ -126,9 +126,19 @@ pub enum Error {
     #[snafu(display("SFtp: {tip}"))]
     OpUnsupported { backtrace: Backtrace, tip: String },
 
+    #[snafu(display("SFtp status {code}: {tip}"))]
+    SFtpStatus {
+        backtrace: Backtrace,
+        code: u32,
+        tip: String,
+    },
+
     #[snafu(display("Failed to Request: {tip}"))]
     RequestFailure { backtrace: Backtrace, tip: String },
 
+    #[snafu(display("Agent: {tip}"))]
+    AgentFailure { backtrace: Backtrace, tip: String },
+
     #[snafu(display("Calling recv on a channel with an None receiver"))]
     ChannelReceiverIsNone { backtrace: Backtrace },
 
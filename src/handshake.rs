@@ -1,10 +1,13 @@
 use std::fmt::Debug;
 
+use super::agent::{AgentForward, AgentRequest};
 use super::cipher::hash::Hash;
 use super::cipher::kex::Summary as DHSumary;
 use super::cipher::AlgoFactory;
 use super::error::{Error, Result};
 use super::forward::Stream as ForwardStream;
+use super::keys::Certificate;
+use super::rate::RateLimiter;
 use super::session::DisconnectReson;
 use super::ssh::common::*;
 use super::ssh::stream::{BufferStream, Stream};
@@ -20,6 +23,7 @@ use crate::ssh::buffer::Buffer;
 use derive_new::new;
 use indexmap::IndexMap;
 use openssl::rand::rand_bytes;
+use snafu::OptionExt;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 pub struct Config<B> {
@@ -34,6 +38,11 @@ pub struct Config<B> {
     pub compress_server_to_client: IndexMap<String, AlgoFactory<dyn Decode + Send>>,
     pub key_strict: bool,
     pub behavior: Option<B>,
+    pub agent_forward: AgentForward,
+    /// The name the server was reached by, matched against the principals of a host certificate
+    pub hostname: Option<String>,
+    /// Shared by the writes of every channel of the session
+    pub rate_limiter: Option<RateLimiter>,
     pub(crate) ext: bool,
 }
 
@@ -83,6 +92,24 @@ pub trait Behavior {
     async fn verify_server_hostkey(&mut self, keytype: &str, hostkeys: &[u8]) -> Result<bool>;
     async fn server_signature_algorithms(&mut self, algorithms: &[&str]) -> Result<()>;
     async fn x11_forward(&mut self, stream: ForwardStream) -> Result<()>;
+    /// Called for every request arriving on a forwarded agent channel, return false to refuse it.
+    /// Channels only come through when forwarding was requested, so all requests pass by default
+    async fn confirm_agent_forward(&mut self, _: &AgentRequest) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Called when a forwarded agent channel couldn't reach the agent or broke off, the channel
+    /// is closed already. Nothing is done by default
+    async fn agent_forward_failed(&mut self, _: &Error) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called instead of `verify_server_hostkey` once a host certificate passed `Certificate::verify_host`,
+    /// check `certificate.signature_key` against the trusted CAs, e.g. with `HostAuthorities::trusts`.
+    /// No CA is trusted by default
+    async fn verify_host_certificate(&mut self, _: &Certificate) -> Result<bool> {
+        Ok(false)
+    }
 }
 
 impl Config<DefaultBehavior> {
@@ -179,7 +206,10 @@ impl<B> Default for Config<B> {
             compress_server_to_client: convert(compress::new_decode_all()),
             key_strict: true,
             behavior: None,
-            ext: false,
+            agent_forward: AgentForward::default(),
+            hostname: None,
+            rate_limiter: None,
+            ext: true,
         }
     }
 }
@@ -217,7 +247,10 @@ impl<B> Config<B> {
             compress_server_to_client: convert(compress::new_decode_all()),
             key_strict: true,
             behavior: Some(behaviour),
-            ext: false,
+            agent_forward: AgentForward::default(),
+            hostname: None,
+            rate_limiter: None,
+            ext: true,
         }
     }
 
@@ -331,12 +364,89 @@ impl<B> ConfigBuilder<B> {
         self
     }
 
+    /// 设置 agent 转发通道的去向，`AgentForward::Refuse` 拒绝所有转发。
+    pub fn agent_forward(mut self, forward: AgentForward) -> Self {
+        self.config.agent_forward = forward;
+        self
+    }
+
+    /// 设置服务器的主机名，用于校验主机证书的 principals，未设置时主机证书会被拒绝。
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.config.hostname = Some(hostname.into());
+        self
+    }
+
+    /// 将主机证书算法移到列表头部，优先协商证书。
+    pub fn prefer_host_certificates(mut self) -> Self {
+        self.config.hostkey.sort_by(|a, _, b, _| {
+            Certificate::is_certificate(b).cmp(&Certificate::is_certificate(a))
+        });
+        self
+    }
+
+    /// 是否在首次密钥交换中声明 `ext-info-c`，默认开启，
+    /// 服务器因此会发送 `server-sig-algs` 用于选择 RSA 签名算法。
+    pub fn ext_info(mut self, enable: bool) -> Self {
+        self.config.ext = enable;
+        self
+    }
+
+    /// 限制会话中所有通道的写入速率，克隆的 `RateLimiter` 可在运行时调整速率。
+    pub fn rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.config.rate_limiter = Some(limiter);
+        self
+    }
+
     /// 构建最终 Config。
     pub fn build(self) -> Config<B> {
         self.config
     }
 }
 
+/// Check a host certificate, then ask the behavior whether the hostkey or the certificate is trusted
+pub(crate) async fn verify_hostkey<B: Behavior + Send>(
+    config: &mut Config<B>,
+    keytype: &str,
+    hostkey: &[u8],
+) -> Result<()> {
+    let certificate = match Certificate::is_certificate(keytype) {
+        true => {
+            // without a name to match the principals, any host the CA certified would pass
+            let hostname = config
+                .hostname
+                .as_deref()
+                .context(builder::InvalidArgument {
+                    tip: "Config::hostname is needed to verify a host certificate",
+                })?;
+            let certificate = Certificate::parse(hostkey)?;
+            snafu::ensure!(
+                certificate.verify_host(hostname)?,
+                builder::HostKeyVerifyFailed
+            );
+            Some(certificate)
+        }
+        false => None,
+    };
+
+    let trusted = match (&mut config.behavior, certificate) {
+        (Some(behavior), Some(certificate)) => {
+            behavior.verify_host_certificate(&certificate).await?
+        }
+        (Some(behavior), None) => behavior.verify_server_hostkey(keytype, hostkey).await?,
+        // no CA is trusted without a behavior to name one
+        (None, Some(_)) => false,
+        (None, None) => true,
+    };
+
+    if !trusted {
+        return builder::RejectByUser {
+            tip: "Server public key rejected by user",
+        }
+        .fail();
+    }
+    Ok(())
+}
+
 pub(crate) async fn banner_exchange<T: AsyncWrite + AsyncRead + Unpin>(
     stream: &mut BufferStream<T>,
     banner: &str,
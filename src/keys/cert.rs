@@ -0,0 +1,302 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use num_enum::TryFromPrimitive;
+
+use super::{KeyParser, Signer};
+use crate::cipher::sign;
+use crate::error::{Error, Result};
+use crate::ssh::buffer::Buffer;
+
+const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+const OPENSSH_SUFFIX: &str = "@openssh.com";
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+pub enum CertType {
+    User = 1,
+    Host = 2,
+}
+
+/// An openssh certificate, see
+/// https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.certkeys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    /// e.g. `ssh-ed25519-cert-v01@openssh.com`
+    pub key_type: String,
+    pub nonce: Vec<u8>,
+    /// The certified public key blob, e.g. `string "ssh-ed25519", string key`
+    pub public_key: Vec<u8>,
+    pub serial: u64,
+    pub cert_type: CertType,
+    pub key_id: String,
+    /// An empty list means the certificate is valid for any principal
+    pub principals: Vec<String>,
+    /// Seconds since the unix epoch
+    pub valid_after: u64,
+    /// Seconds since the unix epoch
+    pub valid_before: u64,
+    /// Option names with their still encoded data
+    pub critical_options: Vec<(String, Vec<u8>)>,
+    /// Extension names with their still encoded data
+    pub extensions: Vec<(String, Vec<u8>)>,
+    /// The public key blob of the signing CA
+    pub signature_key: Vec<u8>,
+    /// The signature blob made by the CA over every field above
+    pub signature: Vec<u8>,
+    /// The whole certificate blob
+    pub blob: Vec<u8>,
+}
+
+impl Certificate {
+    pub fn is_certificate(key_type: &str) -> bool {
+        key_type.ends_with(CERT_SUFFIX)
+    }
+
+    /// Parse a certificate blob, the decoded base64 part of a `*-cert.pub` file
+    pub fn parse(blob: &[u8]) -> Result<Self> {
+        let invalid = || Error::invalid_format("invalid certificate");
+        let buffer = Buffer::from_slice(blob);
+
+        let key_type = std::str::from_utf8(buffer.take_one().ok_or_else(invalid)?.1)?;
+        let base_type = base_algorithm(key_type).ok_or_else(invalid)?;
+        let base_type = base_type.as_str();
+        let nonce = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+
+        let fields = match base_type {
+            "ssh-rsa" => 2,
+            "ssh-dss" => 4,
+            "ssh-ed25519" => 1,
+            "sk-ssh-ed25519@openssh.com" => 2,
+            "sk-ecdsa-sha2-nistp256@openssh.com" => 3,
+            t if t.starts_with("ecdsa-sha2-") => 2,
+            _ => {
+                return Err(Error::invalid_format(format!(
+                    "unsupported certificate type {key_type}"
+                )))
+            }
+        };
+
+        let mut public_key = Buffer::new();
+        public_key.put_one(base_type);
+        for _ in 0..fields {
+            public_key.put_one(buffer.take_one().ok_or_else(invalid)?.1);
+        }
+
+        let serial = buffer.take_u64().ok_or_else(invalid)?;
+        let cert_type = buffer.take_u32().ok_or_else(invalid)?;
+        let cert_type = CertType::try_from(cert_type)
+            .map_err(|_| Error::invalid_format(format!("unknown certificate type {cert_type}")))?;
+        let key_id = std::str::from_utf8(buffer.take_one().ok_or_else(invalid)?.1)?.to_string();
+
+        let principals = Buffer::from_slice(buffer.take_one().ok_or_else(invalid)?.1);
+        let mut names = vec![];
+        while principals.len() > 0 {
+            let name = principals.take_one().ok_or_else(invalid)?.1;
+            names.push(std::str::from_utf8(name)?.to_string());
+        }
+
+        let valid_after = buffer.take_u64().ok_or_else(invalid)?;
+        let valid_before = buffer.take_u64().ok_or_else(invalid)?;
+        let critical_options = parse_options(buffer.take_one().ok_or_else(invalid)?.1)?;
+        let extensions = parse_options(buffer.take_one().ok_or_else(invalid)?.1)?;
+        // reserved
+        buffer.take_one().ok_or_else(invalid)?;
+        let signature_key = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+        let signature = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+        if buffer.len() != 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            key_type: key_type.to_string(),
+            nonce,
+            public_key: public_key.into_vec(),
+            serial,
+            cert_type,
+            key_id,
+            principals: names,
+            valid_after,
+            valid_before,
+            critical_options,
+            extensions,
+            signature_key,
+            signature,
+            blob: blob.to_vec(),
+        })
+    }
+
+    /// `time` is in seconds since the unix epoch
+    pub fn is_valid_at(&self, time: u64) -> bool {
+        self.valid_after <= time && time < self.valid_before
+    }
+
+    pub fn is_valid_now(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.is_valid_at(now)
+    }
+
+    pub fn has_principal(&self, name: &str) -> bool {
+        self.principals.is_empty() || self.principals.iter().any(|p| p == name)
+    }
+
+    /// Check the signature made by `signature_key` over the certificate,
+    /// whether that key is trusted is up to the caller
+    pub fn verify_signature(&self) -> Result<bool> {
+        let (_, algorithm) = Buffer::from_slice(&self.signature)
+            .take_one()
+            .ok_or(Error::invalid_format("invalid certificate signature"))?;
+        let algorithm = std::str::from_utf8(algorithm)?;
+
+        // a certificate can't be signed by another certificate
+        if Certificate::is_certificate(algorithm) {
+            return Ok(false);
+        }
+
+        let mut verify = sign::new_verify_by_name(algorithm).ok_or_else(|| {
+            Error::invalid_format(format!("unsupported signature algorithm {algorithm}"))
+        })?();
+        verify.initialize(&self.signature_key)?;
+
+        let signed = &self.blob[..self.blob.len() - 4 - self.signature.len()];
+        verify.verify(&self.signature, signed)
+    }
+
+    /// A host certificate is acceptable when it's a `CertType::Host`, is valid now,
+    /// lists `hostname` among its principals and carries a valid CA signature.
+    /// No critical option is defined for hosts, one that can't be understood means a refusal
+    pub fn verify_host(&self, hostname: &str) -> Result<bool> {
+        if self.cert_type != CertType::Host
+            || !self.critical_options.is_empty()
+            || !self.is_valid_now()
+            || !self.has_principal(hostname)
+        {
+            return Ok(false);
+        }
+
+        self.verify_signature()
+    }
+
+    pub fn critical_option(&self, name: &str) -> Option<&[u8]> {
+        self.critical_options
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    pub fn extension(&self, name: &str) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+    }
+}
+
+fn parse_options(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let buffer = Buffer::from_slice(data);
+    let mut options = vec![];
+    while buffer.len() > 0 {
+        let func = || Some((buffer.take_one()?.1, buffer.take_one()?.1));
+        let (name, value) = func().ok_or(Error::invalid_format("invalid certificate options"))?;
+        options.push((std::str::from_utf8(name)?.to_string(), value.to_vec()));
+    }
+    Ok(options)
+}
+
+/// The CAs trusted to certify hosts, like the `@cert-authority` keys of known_hosts,
+/// `Behavior::verify_host_certificate` can answer with `trusts`
+#[derive(Debug, Default, Clone)]
+pub struct HostAuthorities {
+    keys: Vec<Vec<u8>>,
+}
+
+impl HostAuthorities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the CA with the public key blob `key`
+    pub fn trust(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+
+    /// Trust the CA of an openssh public key line, e.g. the content of `ca.pub`
+    pub fn trust_openssh(self, line: impl AsRef<[u8]>) -> Result<Self> {
+        let key = KeyParser::default().parse_publickey(line.as_ref())?;
+        Ok(self.trust(key.key))
+    }
+
+    /// Whether `certificate` was signed by one of the CAs, the rest is checked by `Certificate::verify_host`
+    pub fn trusts(&self, certificate: &Certificate) -> bool {
+        self.keys.contains(&certificate.signature_key)
+    }
+}
+
+/// `ssh-ed25519-cert-v01@openssh.com` -> `ssh-ed25519`,
+/// `sk-ssh-ed25519-cert-v01@openssh.com` -> `sk-ssh-ed25519@openssh.com`
+pub fn base_algorithm(algorithm: &str) -> Option<String> {
+    let base = algorithm.strip_suffix(CERT_SUFFIX)?;
+    if base.starts_with("sk-") {
+        Some(format!("{base}{OPENSSH_SUFFIX}"))
+    } else {
+        Some(base.to_string())
+    }
+}
+
+/// `ssh-ed25519` -> `ssh-ed25519-cert-v01@openssh.com`
+pub fn cert_algorithm(algorithm: &str) -> String {
+    let base = algorithm.strip_suffix(OPENSSH_SUFFIX).unwrap_or(algorithm);
+    format!("{base}{CERT_SUFFIX}")
+}
+
+/// Presents `certificate` and signs with the key it certifies
+pub struct Certified<S> {
+    signer: S,
+    certificate: Certificate,
+}
+
+impl<S: Signer> Certified<S> {
+    pub fn new(signer: S, certificate: Certificate) -> Result<Self> {
+        if signer.public_key() != certificate.public_key {
+            return Err(Error::invalid_format(
+                "The certificate does't match the private key",
+            ));
+        }
+        Ok(Self {
+            signer,
+            certificate,
+        })
+    }
+
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+
+    pub fn into_inner(self) -> S {
+        self.signer
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Signer> Signer for Certified<S> {
+    // the certificate is presented in place of the bare key
+    #[allow(clippy::misnamed_getters)]
+    fn public_key(&self) -> &[u8] {
+        &self.certificate.blob
+    }
+
+    fn algorithms(&self) -> Vec<String> {
+        self.signer
+            .algorithms()
+            .iter()
+            .map(|algo| cert_algorithm(algo))
+            .collect()
+    }
+
+    async fn sign(&self, data: &[u8], algorithm: &str) -> Result<Vec<u8>> {
+        let algorithm = base_algorithm(algorithm).unwrap_or(algorithm.to_string());
+        self.signer.sign(data, &algorithm).await
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use openssl::base64::{decode_block, encode_block};
+use openssl::bn::BigNum;
+use openssl::hash::{hash, MessageDigest};
+use openssl::memcmp;
+
+use super::{base_algorithm, Certificate, PublicKey};
+use crate::error::{Error, Result};
+use crate::ssh::buffer::Buffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerprintHash {
+    Md5,
+    Sha256,
+}
+
+impl FingerprintHash {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Sha256 => "SHA256",
+        }
+    }
+
+    fn digest(&self) -> MessageDigest {
+        match self {
+            Self::Md5 => MessageDigest::md5(),
+            Self::Sha256 => MessageDigest::sha256(),
+        }
+    }
+}
+
+/// A public key fingerprint as printed by openssh,
+/// `SHA256:` followed by unpadded base64 or `MD5:` followed by colon separated hex
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub hash: FingerprintHash,
+    pub digest: Vec<u8>,
+}
+
+impl Fingerprint {
+    /// Fingerprint of a public key blob, e.g. the hostkey given to `Behavior::verify_server_hostkey`
+    pub fn new(algorithm: FingerprintHash, blob: &[u8]) -> Result<Self> {
+        Ok(Self {
+            hash: algorithm,
+            digest: hash(algorithm.digest(), blob)?.to_vec(),
+        })
+    }
+
+    pub fn sha256(blob: &[u8]) -> Result<Self> {
+        Self::new(FingerprintHash::Sha256, blob)
+    }
+
+    pub fn md5(blob: &[u8]) -> Result<Self> {
+        Self::new(FingerprintHash::Md5, blob)
+    }
+
+    /// Whether `blob` hashes to this fingerprint, for pinning a host
+    pub fn matches(&self, blob: &[u8]) -> bool {
+        hash(self.hash.digest(), blob)
+            .map(|digest| memcmp::eq(&digest, &self.digest))
+            .unwrap_or(false)
+    }
+
+    /// The "drunken bishop" picture `ssh-keygen -lv` draws for `blob`
+    pub fn randomart(&self, blob: &[u8]) -> String {
+        let title = key_description(blob).unwrap_or_default();
+        randomart(&self.digest, &title, self.hash.name())
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.hash {
+            FingerprintHash::Sha256 => {
+                let encoded = encode_block(&self.digest);
+                write!(f, "SHA256:{}", encoded.trim_end_matches('='))
+            }
+            FingerprintHash::Md5 => {
+                let hex: Vec<_> = self.digest.iter().map(|b| format!("{b:02x}")).collect();
+                write!(f, "MD5:{}", hex.join(":"))
+            }
+        }
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = Error;
+
+    /// Accepts `SHA256:...`, `MD5:aa:bb:...` and bare colon separated md5 hex
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::invalid_format("invalid fingerprint");
+        let s = s.trim();
+
+        if let Some(encoded) = s.strip_prefix("SHA256:") {
+            let mut encoded = encoded.to_string();
+            while encoded.len() % 4 != 0 {
+                encoded.push('=');
+            }
+            let digest = decode_block(&encoded).map_err(|_| invalid())?;
+            if digest.len() != 32 {
+                return Err(invalid());
+            }
+            return Ok(Self {
+                hash: FingerprintHash::Sha256,
+                digest,
+            });
+        }
+
+        let hex = s.strip_prefix("MD5:").unwrap_or(s);
+        let digest = hex
+            .split(':')
+            .map(|b| match b.len() {
+                2 => u8::from_str_radix(b, 16).map_err(|_| invalid()),
+                _ => Err(invalid()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if digest.len() != 16 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            hash: FingerprintHash::Md5,
+            digest,
+        })
+    }
+}
+
+impl PublicKey {
+    pub fn fingerprint(&self, hash: FingerprintHash) -> Result<Fingerprint> {
+        Fingerprint::new(hash, &self.key)
+    }
+
+    pub fn randomart(&self, hash: FingerprintHash) -> Result<String> {
+        Ok(self.fingerprint(hash)?.randomart(&self.key))
+    }
+}
+
+/// e.g. `ED25519 256`, the title of the randomart box
+fn key_description(blob: &[u8]) -> Result<String> {
+    let invalid = || Error::invalid_format("invalid public key blob");
+    let buffer = Buffer::from_slice(blob);
+    let key_type = std::str::from_utf8(buffer.take_one().ok_or_else(invalid)?.1)?;
+
+    let (suffix, public) = match Certificate::is_certificate(key_type) {
+        true => ("-CERT", Certificate::parse(blob)?.public_key),
+        false => ("", blob.to_vec()),
+    };
+    let base_type = base_algorithm(key_type).unwrap_or(key_type.to_string());
+
+    let buffer = Buffer::from_slice(&public);
+    buffer.take_one().ok_or_else(invalid)?;
+    let bits = |n: usize| -> Result<i32> {
+        for _ in 1..n {
+            buffer.take_one().ok_or_else(invalid)?;
+        }
+        Ok(BigNum::from_slice(buffer.take_one().ok_or_else(invalid)?.1)?.num_bits())
+    };
+
+    let (name, bits) = match base_type.as_str() {
+        "ssh-rsa" => ("RSA", bits(2)?),
+        "ssh-dss" => ("DSA", bits(1)?),
+        "ssh-ed25519" => ("ED25519", 256),
+        "sk-ssh-ed25519@openssh.com" => ("ED25519-SK", 256),
+        "sk-ecdsa-sha2-nistp256@openssh.com" => ("ECDSA-SK", 256),
+        "ecdsa-sha2-nistp256" => ("ECDSA", 256),
+        "ecdsa-sha2-nistp384" => ("ECDSA", 384),
+        "ecdsa-sha2-nistp521" => ("ECDSA", 521),
+        _ => return Err(invalid()),
+    };
+
+    Ok(format!("{name}{suffix} {bits}"))
+}
+
+// https://github.com/openssh/openssh-portable/blob/master/sshkey.c fingerprint_randomart
+fn randomart(digest: &[u8], title: &str, hash: &str) -> String {
+    const WIDTH: usize = 17;
+    const HEIGHT: usize = 9;
+    const SYMBOLS: &[u8] = b" .o+=*BOX@%&#/^SE";
+    let len = SYMBOLS.len() - 1;
+
+    let mut field = [[0usize; HEIGHT]; WIDTH];
+    let (mut x, mut y) = (WIDTH / 2, HEIGHT / 2);
+
+    for byte in digest {
+        let mut input = *byte;
+        for _ in 0..4 {
+            x = match input & 0x1 {
+                0 => x.saturating_sub(1),
+                _ => (x + 1).min(WIDTH - 1),
+            };
+            y = match input & 0x2 {
+                0 => y.saturating_sub(1),
+                _ => (y + 1).min(HEIGHT - 1),
+            };
+            if field[x][y] < len - 2 {
+                field[x][y] += 1;
+            }
+            input >>= 2;
+        }
+    }
+
+    field[WIDTH / 2][HEIGHT / 2] = len - 1;
+    field[x][y] = len;
+
+    let border = |label: &str| {
+        let label: String = format!("[{label}]").chars().take(WIDTH).collect();
+        let left = (WIDTH - label.len()) / 2;
+        format!(
+            "+{}{}{}+",
+            "-".repeat(left),
+            label,
+            "-".repeat(WIDTH - left - label.len())
+        )
+    };
+
+    let mut art = border(title);
+    art.push('\n');
+    for y in 0..HEIGHT {
+        art.push('|');
+        for column in &field {
+            art.push(SYMBOLS[column[y].min(len)] as char);
+        }
+        art.push_str("|\n");
+    }
+    art.push_str(&border(hash));
+    art
+}
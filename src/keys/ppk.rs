@@ -0,0 +1,247 @@
+//! PuTTY private key files, see
+//! https://the.earth.li/~sgtatham/putty/0.78/htmldoc/AppendixC.html
+
+use std::collections::HashMap;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use openssl::base64::decode_block;
+use openssl::hash::{hash, MessageDigest};
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{Cipher, Crypter, Mode};
+
+use super::PrivateKey;
+use crate::error::{Error, Result};
+use crate::ssh::buffer::Buffer;
+
+pub(crate) const MAGIC: &str = "PuTTY-User-Key-File-";
+
+const CIPHER_KEY_LEN: usize = 32;
+const CIPHER_IV_LEN: usize = 16;
+const MAC_KEY_LEN: usize = 32;
+
+pub(crate) fn parse(content: &str, passphrase: Option<&[u8]>) -> Result<PrivateKey> {
+    let invalid = || Error::invalid_format("invalid ppk format");
+
+    let mut lines = content.lines().map(|line| line.trim_end());
+    let mut headers = HashMap::new();
+    let mut public = vec![];
+    let mut private = vec![];
+
+    while let Some(line) = lines.next() {
+        if line.is_empty() {
+            continue;
+        }
+        let (name, value) = line.split_once(": ").ok_or_else(invalid)?;
+
+        let body = match name {
+            "Public-Lines" => &mut public,
+            "Private-Lines" => &mut private,
+            _ => {
+                headers.insert(name, value);
+                continue;
+            }
+        };
+
+        let count: usize = value.parse().map_err(|_| invalid())?;
+        let mut encoded = String::new();
+        for _ in 0..count {
+            encoded.push_str(lines.next().ok_or_else(invalid)?);
+        }
+        *body = decode_block(&encoded)?;
+    }
+
+    let header = |name: &str| headers.get(name).copied().ok_or_else(invalid);
+
+    let (version, key_type) = headers
+        .iter()
+        .find_map(|(name, value)| Some((name.strip_prefix(MAGIC)?, *value)))
+        .ok_or_else(invalid)?;
+    let version: u32 = version.parse().map_err(|_| invalid())?;
+
+    let encryption = header("Encryption")?;
+    let comment = header("Comment")?;
+    let mac = hex_decode(header("Private-MAC")?)?;
+
+    let (cipher_key, cipher_iv, mac_key) = match (version, encryption) {
+        (2, "none") => (vec![], vec![], v2_mac_key(b"")?),
+        (3, "none") => (vec![], vec![], vec![]),
+        (_, "aes256-cbc") => {
+            let passphrase = passphrase.ok_or(Error::invalid_format("passphrase was required"))?;
+            match version {
+                2 => v2_keys(passphrase)?,
+                3 => v3_keys(passphrase, &header)?,
+                _ => return Err(Error::invalid_format("unsupported ppk version")),
+            }
+        }
+        (2 | 3, encryption) => {
+            return Err(Error::invalid_format(format!(
+                "unsupported ppk encryption {encryption}"
+            )))
+        }
+        _ => return Err(Error::invalid_format("unsupported ppk version")),
+    };
+
+    if !cipher_key.is_empty() {
+        if !private.len().is_multiple_of(CIPHER_IV_LEN) {
+            return Err(invalid());
+        }
+        let mut crypter = Crypter::new(
+            Cipher::aes_256_cbc(),
+            Mode::Decrypt,
+            &cipher_key,
+            Some(&cipher_iv),
+        )?;
+        crypter.pad(false);
+        let mut plain = vec![0; private.len() + CIPHER_IV_LEN];
+        let mut len = crypter.update(&private, &mut plain)?;
+        len += crypter.finalize(&mut plain[len..])?;
+        plain.truncate(len);
+        private = plain;
+    }
+
+    let data = make_buffer_without_header! {
+        one: key_type,
+        one: encryption,
+        one: comment,
+        one: &public,
+        one: &private,
+    };
+    let digest = match version {
+        2 => MessageDigest::sha1(),
+        _ => MessageDigest::sha256(),
+    };
+    let mac_key = PKey::hmac(&mac_key)?;
+    let mut signer = Signer::new(digest, &mac_key)?;
+    signer.update(&data)?;
+    if !memcmp::eq(&signer.sign_to_vec()?, &mac) {
+        return Err(Error::invalid_format("ppk mac mismatch, wrong passphrase?"));
+    }
+
+    let (public_key, private_key) = key_blobs(key_type, &public, &private)?;
+
+    Ok(PrivateKey::new(
+        key_type.to_string(),
+        public_key,
+        private_key,
+        comment.to_string(),
+    ))
+}
+
+/// Rebuild the private blob `PrivateKey` carries from the split public and private parts
+fn key_blobs(key_type: &str, public: &[u8], private: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let invalid_key_format = || Error::invalid_format("invalid ppk key format");
+    let public_blob = Buffer::from_slice(public);
+    let private_blob = Buffer::from_slice(private);
+    let public_one = || Result::Ok(public_blob.take_one().ok_or_else(invalid_key_format)?.1);
+    let private_one = || Result::Ok(private_blob.take_one().ok_or_else(invalid_key_format)?.1);
+
+    if public_one()? != key_type.as_bytes() {
+        return Err(invalid_key_format());
+    }
+
+    let private_key = match key_type {
+        "ssh-ed25519" => {
+            let seed = private_one()?;
+            make_buffer_without_header! {
+                one: key_type,
+                one: seed,
+            }
+        }
+        "ssh-rsa" => {
+            let e = public_one()?;
+            let n = public_one()?;
+            let d = private_one()?;
+            let p = private_one()?;
+            let q = private_one()?;
+            let iqmp = private_one()?;
+
+            make_buffer_without_header! {
+                one: key_type,
+                one: n,
+                one: e,
+                one: d,
+                one: iqmp,
+                one: p,
+                one: q,
+            }
+        }
+        // the public fields followed by the private scalar
+        ty if ty == "ssh-dss" || ty.starts_with("ecdsa-sha2-") => {
+            let mut blob = Buffer::from_vec(public.to_vec());
+            blob.put_one(private_one()?);
+            blob
+        }
+        ty => {
+            return Err(Error::invalid_format(format!(
+                "unsupported ppk key type {ty}"
+            )))
+        }
+    };
+
+    Ok((public.to_vec(), private_key.into_vec()))
+}
+
+fn v2_mac_key(passphrase: &[u8]) -> Result<Vec<u8>> {
+    let mut data = b"putty-private-key-file-mac-key".to_vec();
+    data.extend(passphrase);
+    Ok(hash(MessageDigest::sha1(), &data)?.to_vec())
+}
+
+fn v2_keys(passphrase: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let mut key = vec![];
+    for i in 0u32..2 {
+        let mut data = i.to_be_bytes().to_vec();
+        data.extend(passphrase);
+        key.extend(hash(MessageDigest::sha1(), &data)?.to_vec());
+    }
+    key.truncate(CIPHER_KEY_LEN);
+
+    Ok((key, vec![0; CIPHER_IV_LEN], v2_mac_key(passphrase)?))
+}
+
+fn v3_keys<'a>(
+    passphrase: &[u8],
+    header: &impl Fn(&str) -> Result<&'a str>,
+) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let invalid = || Error::invalid_format("invalid ppk key derivation");
+    let number = |name: &str| header(name)?.parse::<u32>().map_err(|_| invalid());
+
+    let algorithm = match header("Key-Derivation")? {
+        "Argon2id" => Algorithm::Argon2id,
+        "Argon2i" => Algorithm::Argon2i,
+        "Argon2d" => Algorithm::Argon2d,
+        _ => return Err(invalid()),
+    };
+    let salt = hex_decode(header("Argon2-Salt")?)?;
+    let params = Params::new(
+        number("Argon2-Memory")?,
+        number("Argon2-Passes")?,
+        number("Argon2-Parallelism")?,
+        Some(CIPHER_KEY_LEN + CIPHER_IV_LEN + MAC_KEY_LEN),
+    )
+    .map_err(|e| Error::invalid_format(e.to_string()))?;
+
+    let mut out = vec![0; CIPHER_KEY_LEN + CIPHER_IV_LEN + MAC_KEY_LEN];
+    Argon2::new(algorithm, Version::V0x13, params)
+        .hash_password_into(passphrase, &salt, &mut out)
+        .map_err(|e| Error::invalid_format(e.to_string()))?;
+
+    let mac_key = out.split_off(CIPHER_KEY_LEN + CIPHER_IV_LEN);
+    let iv = out.split_off(CIPHER_KEY_LEN);
+    Ok((out, iv, mac_key))
+}
+
+fn hex_decode(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(Error::invalid_format("invalid hex string"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(hex.get(i..i + 2).unwrap_or_default(), 16)
+                .map_err(|_| Error::invalid_format("invalid hex string"))
+        })
+        .collect()
+}
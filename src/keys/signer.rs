@@ -0,0 +1,55 @@
+use super::PrivateKey;
+use crate::cipher::sign;
+use crate::error::{Error, Result};
+use crate::ssh::buffer::Buffer;
+
+/// Something that holds a private key and can sign with it,
+/// the key itself never has to be handed to the session
+#[async_trait::async_trait]
+pub trait Signer: Send + Sync {
+    /// The public key blob, e.g. `string "ssh-ed25519", string key`
+    fn public_key(&self) -> &[u8];
+
+    /// Signature algorithms usable with this key, the most preferred first
+    fn algorithms(&self) -> Vec<String>;
+
+    /// Sign `data` with `algorithm`, returns the signature blob `string algorithm, string signature`
+    async fn sign(&self, data: &[u8], algorithm: &str) -> Result<Vec<u8>>;
+}
+
+#[async_trait::async_trait]
+impl Signer for PrivateKey {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn algorithms(&self) -> Vec<String> {
+        algorithms(&self.key_type)
+    }
+
+    async fn sign(&self, data: &[u8], algorithm: &str) -> Result<Vec<u8>> {
+        let mut algo =
+            sign::new_signature_by_name(algorithm).ok_or(Error::ub("Unable to create cipher"))?();
+
+        algo.initialize(&self.private_key)?;
+        let sign = algo.signature(data)?;
+
+        Ok(make_buffer_without_header! {
+            one: algorithm,
+            one: &sign,
+        }
+        .into_vec())
+    }
+}
+
+/// The signature algorithms a key of `key_type` can be used with, preferred first as in OpenSSH
+pub(crate) fn algorithms(key_type: &str) -> Vec<String> {
+    match key_type {
+        "ssh-rsa" => vec![
+            "rsa-sha2-512".to_string(),
+            "rsa-sha2-256".to_string(),
+            "ssh-rsa".to_string(),
+        ],
+        key_type => vec![key_type.to_string()],
+    }
+}
@@ -0,0 +1,198 @@
+//! FIDO security keys, `sk-ssh-ed25519@openssh.com` and `sk-ecdsa-sha2-nistp256@openssh.com`, see
+//! https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.u2f
+
+use openssl::hash::{hash, MessageDigest};
+
+use super::{PrivateKey, Signer};
+use crate::error::{Error, Result};
+use crate::ssh::buffer::Buffer;
+
+pub(crate) const SK_ED25519: &str = "sk-ssh-ed25519@openssh.com";
+pub(crate) const SK_ECDSA_NISTP256: &str = "sk-ecdsa-sha2-nistp256@openssh.com";
+
+/// The user touched the authenticator
+pub const SK_USER_PRESENCE: u8 = 0x01;
+/// The user was verified by the authenticator, e.g. with a PIN
+pub const SK_USER_VERIFICATION: u8 = 0x04;
+
+/// The response of an authenticator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkSignature {
+    /// The raw ed25519 signature, or `mpint r, mpint s` for ecdsa
+    pub signature: Vec<u8>,
+    pub flags: u8,
+    pub counter: u32,
+}
+
+impl SkSignature {
+    /// Parse the signature blob `string algorithm, string signature, byte flags, uint32 counter`
+    pub fn parse(blob: &[u8]) -> Result<(String, Self)> {
+        let invalid = || Error::invalid_format("invalid security key signature");
+        let buffer = Buffer::from_slice(blob);
+
+        let algorithm = std::str::from_utf8(buffer.take_one().ok_or_else(invalid)?.1)?;
+        let signature = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+        let flags = buffer.take_u8().ok_or_else(invalid)?;
+        let counter = buffer.take_u32().ok_or_else(invalid)?;
+
+        Ok((
+            algorithm.to_string(),
+            Self {
+                signature,
+                flags,
+                counter,
+            },
+        ))
+    }
+
+    pub fn to_blob(&self, algorithm: &str) -> Vec<u8> {
+        make_buffer_without_header! {
+            one: algorithm,
+            one: &self.signature,
+            u8: self.flags,
+            u32: self.counter,
+        }
+        .into_vec()
+    }
+}
+
+pub fn is_security_key(key_type: &str) -> bool {
+    key_type == SK_ED25519 || key_type == SK_ECDSA_NISTP256
+}
+
+/// The key type the authenticator really signs with, e.g. `sk-ssh-ed25519@openssh.com` -> `ssh-ed25519`
+pub(crate) fn base_key_type(key_type: &str) -> Option<&'static str> {
+    match key_type {
+        SK_ED25519 => Some("ssh-ed25519"),
+        SK_ECDSA_NISTP256 => Some("ecdsa-sha2-nistp256"),
+        _ => None,
+    }
+}
+
+/// Split a security key public blob into the plain key blob and the application, e.g. `ssh:`
+pub(crate) fn split_public_key(blob: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let invalid = || Error::invalid_format("invalid security key");
+    let buffer = Buffer::from_slice(blob);
+
+    let key_type = std::str::from_utf8(buffer.take_one().ok_or_else(invalid)?.1)?;
+    let base = base_key_type(key_type).ok_or_else(invalid)?;
+
+    let mut key = Buffer::from_one(base);
+    let fields = match key_type {
+        SK_ED25519 => 1,
+        _ => 2,
+    };
+    for _ in 0..fields {
+        key.put_one(buffer.take_one().ok_or_else(invalid)?.1);
+    }
+    let application = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+
+    Ok((key.into_vec(), application))
+}
+
+/// What the authenticator signs: `sha256(application) || flags || counter || sha256(data)`
+pub(crate) fn signed_data(
+    application: &[u8],
+    flags: u8,
+    counter: u32,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let mut signed = hash(MessageDigest::sha256(), application)?.to_vec();
+    signed.push(flags);
+    signed.extend(counter.to_be_bytes());
+    signed.extend(hash(MessageDigest::sha256(), data)?.to_vec());
+    Ok(signed)
+}
+
+/// A FIDO authenticator or the middleware talking to one, e.g. libfido2
+#[async_trait::async_trait]
+pub trait Authenticator: Send + Sync {
+    /// Sign `data` with the credential `key_handle` registered for `application`,
+    /// `flags` are the ones the key was enrolled with, e.g. whether a touch is required
+    async fn sign(
+        &self,
+        key_type: &str,
+        application: &str,
+        key_handle: &[u8],
+        flags: u8,
+        data: &[u8],
+    ) -> Result<SkSignature>;
+}
+
+/// Signs with a security key loaded from an openssh private key file,
+/// the file only holds a handle, the authenticator produces the signature
+pub struct SkSigner<A> {
+    key_type: String,
+    public_key: Vec<u8>,
+    application: String,
+    flags: u8,
+    key_handle: Vec<u8>,
+    authenticator: A,
+}
+
+impl<A: Authenticator> SkSigner<A> {
+    pub fn new(key: &PrivateKey, authenticator: A) -> Result<Self> {
+        let invalid = || Error::invalid_format("invalid security key");
+        if !is_security_key(&key.key_type) {
+            return Err(invalid());
+        }
+
+        // the private section of the key file, see `KeyParser::parse_privatekey`
+        let (_, application) = split_public_key(&key.private_key)?;
+        let buffer = Buffer::from_slice(&key.private_key);
+        let fields = match key.key_type.as_str() {
+            SK_ED25519 => 3,
+            _ => 4,
+        };
+        for _ in 0..fields {
+            buffer.take_one().ok_or_else(invalid)?;
+        }
+        let flags = buffer.take_u8().ok_or_else(invalid)?;
+        let key_handle = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+
+        Ok(Self {
+            key_type: key.key_type.clone(),
+            public_key: key.public_key.clone(),
+            application: String::from_utf8(application).map_err(|e| e.utf8_error())?,
+            flags,
+            key_handle,
+            authenticator,
+        })
+    }
+
+    pub fn application(&self) -> &str {
+        &self.application
+    }
+}
+
+#[async_trait::async_trait]
+impl<A: Authenticator> Signer for SkSigner<A> {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn algorithms(&self) -> Vec<String> {
+        vec![self.key_type.clone()]
+    }
+
+    async fn sign(&self, data: &[u8], algorithm: &str) -> Result<Vec<u8>> {
+        if algorithm != self.key_type {
+            return Err(Error::invalid_format(format!(
+                "security key can't sign with {algorithm}"
+            )));
+        }
+
+        let signature = self
+            .authenticator
+            .sign(
+                &self.key_type,
+                &self.application,
+                &self.key_handle,
+                self.flags,
+                data,
+            )
+            .await?;
+
+        Ok(signature.to_blob(algorithm))
+    }
+}
@@ -1,5 +1,7 @@
 #[macro_use]
 mod ssh;
+pub mod agent;
+pub mod auth;
 pub mod channel;
 pub mod cipher;
 pub mod error;
@@ -8,9 +10,11 @@ pub mod handshake;
 pub mod keys;
 mod msg;
 mod project;
+pub mod rate;
 pub mod scp;
 pub mod session;
 pub mod sftp;
+pub mod sshsig;
 
 #[cfg(test)]
 mod test;
@@ -20,7 +24,6 @@ type MReceiver<T> = mpsc::UnboundedReceiver<T>;
 type OSender<T> = oneshot::Sender<T>;
 type OReceiver<T> = oneshot::Receiver<T>;
 type MWSender<T> = mpsc::WeakUnboundedSender<T>;
-type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + Sync + 'a>>;
 
 trait BigNumExt {
     fn to_ssh_bytes(&self) -> Vec<u8>;
@@ -62,8 +65,6 @@ fn o_channel<T>() -> (OSender<T>, OReceiver<T>) {
     oneshot::channel()
 }
 
-use std::{future::Future, pin::Pin};
-
 pub use async_trait::async_trait;
 pub use cipher::{
     compress::{Decode, Encode},
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
+use super::agent::AgentRequest;
 use super::channel::{Channel, ChannelOpenFailureReson, Signal};
-use super::error::Result;
-use super::session::{DisconnectReson, Userauth};
+use super::error::{Error, Result};
+use super::session::{DisconnectReson, Hostbased, PublickeyQuery, Userauth};
 use super::sftp::SFtp;
 use super::ssh::common::code::*;
 use super::OSender;
@@ -25,14 +26,47 @@ pub(crate) enum Request {
         #[debug(skip)]
         sender: OSender<Result<Userauth>>,
     },
-    UserauthPublickey {
+    UserauthPasswordChange {
+        username: String,
+        #[debug(skip)]
+        old: String,
+        #[debug(skip)]
+        new: String,
+        #[debug(skip)]
+        sender: OSender<Result<Userauth>>,
+    },
+    UserauthPublickeyQuery {
         username: String,
         method: String,
         publickey: Vec<u8>,
-        privatekey: Vec<u8>,
+        #[debug(skip)]
+        sender: OSender<Result<PublickeyQuery>>,
+    },
+    UserauthPublickeySigned {
+        username: String,
+        method: String,
+        publickey: Vec<u8>,
+        #[debug(skip)]
+        signature: Vec<u8>,
         #[debug(skip)]
         sender: OSender<Result<Userauth>>,
     },
+    UserauthHostbasedData {
+        hostbased: Hostbased,
+        #[debug(skip)]
+        sender: OSender<Result<Vec<u8>>>,
+    },
+    UserauthHostbased {
+        hostbased: Hostbased,
+        #[debug(skip)]
+        signature: Vec<u8>,
+        #[debug(skip)]
+        sender: OSender<Result<Userauth>>,
+    },
+    ServerSigAlgs {
+        #[debug(skip)]
+        sender: OSender<Option<Vec<String>>>,
+    },
     UserauthNone {
         username: String,
         #[debug(skip)]
@@ -44,7 +78,7 @@ pub(crate) enum Request {
         #[debug(skip)]
         cb: Box<dyn Interactive>,
         #[debug(skip)]
-        sender: OSender<Result<bool>>,
+        sender: OSender<Result<Userauth>>,
     },
     ChannelOpenSession {
         initial: u32,
@@ -165,6 +199,19 @@ pub(crate) enum Request {
         #[debug(skip)]
         sender: OSender<Result<()>>,
     },
+    ChannelAgentForward {
+        id: u32,
+        #[debug(skip)]
+        sender: OSender<Result<()>>,
+    },
+    AgentForwardConfirm {
+        request: AgentRequest,
+        #[debug(skip)]
+        sender: OSender<Result<bool>>,
+    },
+    AgentForwardFailed {
+        error: Error,
+    },
     XonXoff {
         id: u32,
         allow: bool,
@@ -181,7 +228,9 @@ pub(crate) enum Message {
         methods: Vec<String>,
         partial: bool,
     },
-    UserauthChangeReq,
+    UserauthChangeReq {
+        prompt: String,
+    },
     UserauthBanner {
         msg: String,
         tag: String,
@@ -268,6 +317,11 @@ pub(crate) enum Message {
         address: String,
         port: u32,
     },
+    AgentForward {
+        sender: u32,
+        initial: u32,
+        maximum: u32,
+    },
     ExtInfo(HashMap<String, Vec<u8>>),
     KexIntial(Vec<u8>),
 }
@@ -318,7 +372,13 @@ impl Message {
 
                     Some(Self::UserauthFailure { methods, partial })
                 }
-                SSH_MSG_USERAUTH_PASSWD_CHANGEREQ => Some(Self::UserauthChangeReq),
+                SSH_MSG_USERAUTH_PASSWD_CHANGEREQ => {
+                    let (_, prompt) = buffer.take_one()?;
+
+                    Some(Self::UserauthChangeReq {
+                        prompt: utf8(prompt)?,
+                    })
+                }
                 SSH_MSG_CHANNEL_OPEN_CONFIRMATION => {
                     let recipient = buffer.take_u32()?;
 
@@ -535,6 +595,16 @@ impl Message {
                             address,
                             port,
                         })
+                    } else if cmd == b"auth-agent@openssh.com" {
+                        let sender = buffer.take_u32()?;
+                        let initial = buffer.take_u32()?;
+                        let maximum = buffer.take_u32()?;
+
+                        Some(Self::AgentForward {
+                            sender,
+                            initial,
+                            maximum,
+                        })
                     } else {
                         detail = "Unimplemented".to_string();
                         None
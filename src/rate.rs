@@ -0,0 +1,97 @@
+//! Bandwidth limiting with a token bucket
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// A token bucket refilled with `rate` bytes a second, holding at most `burst` bytes.
+///
+/// Clones share the bucket, so one limiter can cap several channels or transfers together,
+/// and the rate can be changed while they run
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+struct Bucket {
+    rate: u64,
+    burst: u64,
+    // negative after a write larger than what was left
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst as f64);
+    }
+}
+
+impl Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bucket = self.bucket.lock().unwrap();
+        f.debug_struct("RateLimiter")
+            .field("rate", &bucket.rate)
+            .field("burst", &bucket.burst)
+            .finish()
+    }
+}
+
+impl RateLimiter {
+    /// `rate` bytes a second with bursts of up to a second, 0 means no limit
+    pub fn new(rate: u64) -> Self {
+        Self::with_burst(rate, rate)
+    }
+
+    pub fn with_burst(rate: u64, burst: u64) -> Self {
+        let burst = burst.max(1);
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                rate,
+                burst,
+                tokens: burst as f64,
+                last: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap().rate
+    }
+
+    /// 0 means no limit
+    pub fn set_rate(&self, rate: u64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.rate = rate;
+    }
+
+    /// Wait until `len` bytes may be sent.
+    ///
+    /// More than `burst` bytes are let through once the bucket is full, the ones after wait longer
+    pub async fn acquire(&self, len: usize) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                bucket.refill();
+                if bucket.rate == 0 {
+                    return;
+                }
+
+                let need = (len as f64).min(bucket.burst as f64);
+                if bucket.tokens >= need {
+                    bucket.tokens -= len as f64;
+                    return;
+                }
+                (need - bucket.tokens) / bucket.rate as f64
+            };
+
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use std::cmp::min;
 use std::collections::HashMap;
 
+use super::agent::{self, Agent, AgentForward, AgentSigner};
 use super::channel::ChannelOpenFailureReson;
 use super::handshake;
-use super::keys::KeyParser;
+use super::keys::{base_algorithm, Certificate, Certified, KeyParser, PrivateKey, Signer};
 use super::ssh::stream::CipherStream;
 
 use crate::channel::ChannelInner;
@@ -11,8 +12,6 @@ use crate::channel::Endpoint as ChannelEndpoint;
 use crate::channel::TerminalMode;
 use crate::cipher::kex::Dependency;
 
-use crate::cipher::sign;
-
 use crate::error::builder;
 use crate::error::Error;
 use crate::error::Result;
@@ -68,7 +67,49 @@ struct Endpoint {
 pub enum Userauth {
     Success,
     Failure(Vec<String>, bool),
-    Expired,
+    /// The password has expired, holds the prompt of the server,
+    /// a new one can be set with `Session::userauth_password_change`
+    Expired(String),
+}
+
+pub(crate) enum PublickeyQuery {
+    /// The server will accept a signature, holds the data to be signed
+    Accepted(Vec<u8>),
+    Rejected(Userauth),
+}
+
+/// The fields of a hostbased request, in wire order
+#[derive(Debug)]
+pub(crate) struct Hostbased {
+    username: String,
+    method: String,
+    publickey: Vec<u8>,
+    client_hostname: String,
+    local_username: String,
+}
+
+impl Hostbased {
+    // https://www.rfc-editor.org/rfc/rfc4252#section-9
+    fn request(&self) -> Buffer<Vec<u8>> {
+        make_buffer_without_header! {
+            u8: SSH_MSG_USERAUTH_REQUEST,
+            one: &self.username,
+            one: "ssh-connection",
+            one: "hostbased",
+            one: &self.method,
+            one: &self.publickey,
+            one: &self.client_hostname,
+            one: &self.local_username,
+        }
+    }
+}
+
+/// Whether `signature` was made with `algorithm`, a certificate signs with the algorithm of its key
+fn signed_with(signature: &[u8], algorithm: &str) -> bool {
+    let algorithm = base_algorithm(algorithm).unwrap_or(algorithm.to_string());
+    Buffer::from_slice(signature)
+        .take_one()
+        .is_some_and(|(_, signed)| signed == algorithm.as_bytes())
 }
 
 #[repr(transparent)]
@@ -324,11 +365,19 @@ impl Session {
     ) -> Result<Userauth> {
         let openssh = KeyParser::default();
 
-        let mut private = openssh.parse_privatekey(privatekey.as_ref(), passphrase)?;
+        let private = openssh.parse_privatekey(privatekey.as_ref(), passphrase)?;
 
         if let Some(pb) = publickey {
             let public = openssh.parse_publickey(pb)?;
 
+            // a certificate is presented instead of the bare public key
+            if public.is_certificate() {
+                let certified = Certified::new(private, public.certificate()?)?;
+                return self
+                    .userauth_publickey_with_signer(username, &certified)
+                    .await;
+            }
+
             if public.key_type != private.key_type {
                 return Err(Error::invalid_format("Cipher doest't match"));
             }
@@ -337,18 +386,12 @@ impl Session {
                 return Err(Error::invalid_format("Public key does't match"));
             }
         }
-        if private.key_type == "ssh-rsa" {
-            private.key_type = "rsa-sha2-256".to_string();
-        }
-        self.userauth_publickey(
-            username,
-            private.key_type,
-            private.public_key,
-            private.private_key,
-        )
-        .await
+
+        self.userauth_publickey_with_signer(username, &private)
+            .await
     }
 
+    /// Authenticate with an already parsed key pair, signing with `method`
     pub async fn userauth_publickey(
         &self,
         username: impl Into<String>,
@@ -356,12 +399,183 @@ impl Session {
         publickey: impl Into<Vec<u8>>,
         privatekey: impl Into<Vec<u8>>,
     ) -> Result<Userauth> {
+        let publickey = publickey.into();
+        let key_type = Buffer::from_slice(&publickey)
+            .take_one()
+            .ok_or(Error::invalid_format("invalid public key blob"))?
+            .1
+            .to_vec();
+
+        let key_type = String::from_utf8(key_type).map_err(|e| e.utf8_error())?;
+
+        if Certificate::is_certificate(&key_type) {
+            let certificate = Certificate::parse(&publickey)?;
+            let private = PrivateKey::new(
+                base_algorithm(&key_type).unwrap_or_default(),
+                certificate.public_key.clone(),
+                privatekey.into(),
+                String::new(),
+            );
+            let certified = Certified::new(private, certificate)?;
+            return self
+                .userauth_publickey_signed_by(&username.into(), &method.into(), &certified)
+                .await;
+        }
+
+        let private = PrivateKey::new(key_type, publickey, privatekey.into(), String::new());
+
+        self.userauth_publickey_signed_by(&username.into(), &method.into(), &private)
+            .await
+    }
+
+    /// The signature algorithms the server accepts for user authentication,
+    /// None if it didn't send the `server-sig-algs` extension
+    pub async fn server_sig_algs(&self) -> Result<Option<Vec<String>>> {
         let (sender, recver) = o_channel();
-        let request = Request::UserauthPublickey {
-            username: username.into(),
-            method: method.into(),
-            publickey: publickey.into(),
-            privatekey: privatekey.into(),
+
+        self.send_request(Request::ServerSigAlgs { sender })?;
+
+        Ok(recver.await?)
+    }
+
+    /// The algorithms to try `signer` with, in order. When `server-sig-algs` is known only the one the signer
+    /// prefers among those it lists is used, e.g. `rsa-sha2-512` rather than `ssh-rsa` for an RSA key. Otherwise,
+    /// or when it lists none of them, the one named after the key goes first as OpenSSH does, a server that
+    /// doesn't send it may not know the others
+    async fn select_algorithms<S>(&self, signer: &S) -> Result<Vec<String>>
+    where
+        S: Signer + ?Sized,
+    {
+        let mut algorithms = signer.algorithms();
+
+        let key_type = Buffer::from_slice(signer.public_key())
+            .take_one()
+            .map(|(_, key_type)| key_type);
+        if let Some(pos) = algorithms
+            .iter()
+            .position(|algo| Some(algo.as_bytes()) == key_type)
+        {
+            let algorithm = algorithms.remove(pos);
+            algorithms.insert(0, algorithm);
+        }
+
+        let Some(accepted) = self.server_sig_algs().await? else {
+            return Ok(algorithms);
+        };
+
+        let preferred = signer.algorithms().into_iter().find(|algo| {
+            let base = base_algorithm(algo).unwrap_or(algo.to_string());
+            accepted.contains(&base)
+        });
+
+        match preferred {
+            Some(algorithm) => Ok(vec![algorithm]),
+            None => Ok(algorithms),
+        }
+    }
+
+    /// Authenticate with the first algorithm the server takes the key for, the private key is only
+    /// ever touched by the signer. Every query counts against the attempts the server allows, so a key
+    /// it rejects isn't queried again, the next algorithm is only tried when the signer used another one
+    pub async fn userauth_publickey_with_signer<S>(
+        &self,
+        username: impl Into<String>,
+        signer: &S,
+    ) -> Result<Userauth>
+    where
+        S: Signer + ?Sized,
+    {
+        let username = username.into();
+        let publickey = signer.public_key();
+
+        for algorithm in self.select_algorithms(signer).await? {
+            let data = match self
+                .userauth_publickey_query(&username, &algorithm, publickey)
+                .await?
+            {
+                PublickeyQuery::Accepted(data) => data,
+                PublickeyQuery::Rejected(status) => return Ok(status),
+            };
+
+            let signature = signer.sign(&data, &algorithm).await?;
+
+            // e.g. an agent unaware of the rsa-sha2 flags signs with ssh-rsa, the server would refuse it
+            if !signed_with(&signature, &algorithm) {
+                continue;
+            }
+
+            return self
+                .userauth_publickey_signed(&username, &algorithm, publickey, &signature)
+                .await;
+        }
+
+        Ok(Userauth::Failure(vec![], false))
+    }
+
+    /// Try every identity held by the agent in turn, the private keys never leave the agent
+    pub async fn userauth_agent<T>(
+        &self,
+        username: impl Into<String>,
+        agent: &mut Agent<T>,
+    ) -> Result<Userauth>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let username = username.into();
+        let mut status = Userauth::Failure(vec![], false);
+
+        for identity in agent.request_identities().await? {
+            let signer = AgentSigner::new(agent, identity);
+
+            match self
+                .userauth_publickey_with_signer(&username, &signer)
+                .await
+            {
+                Ok(res @ Userauth::Failure(_, false)) => status = res,
+                Ok(res) => return Ok(res),
+                // e.g. the user declined a confirmation prompt
+                Err(Error::AgentFailure { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(status)
+    }
+
+    async fn userauth_publickey_signed_by<S>(
+        &self,
+        username: &str,
+        algorithm: &str,
+        signer: &S,
+    ) -> Result<Userauth>
+    where
+        S: Signer + ?Sized,
+    {
+        let data = match self
+            .userauth_publickey_query(username, algorithm, signer.public_key())
+            .await?
+        {
+            PublickeyQuery::Accepted(data) => data,
+            PublickeyQuery::Rejected(status) => return Ok(status),
+        };
+
+        let signature = signer.sign(&data, algorithm).await?;
+
+        self.userauth_publickey_signed(username, algorithm, signer.public_key(), &signature)
+            .await
+    }
+
+    async fn userauth_publickey_query(
+        &self,
+        username: &str,
+        method: &str,
+        publickey: &[u8],
+    ) -> Result<PublickeyQuery> {
+        let (sender, recver) = o_channel();
+        let request = Request::UserauthPublickeyQuery {
+            username: username.to_string(),
+            method: method.to_string(),
+            publickey: publickey.to_vec(),
             sender,
         };
         self.send_request(request)?;
@@ -369,6 +583,78 @@ impl Session {
         recver.await?
     }
 
+    async fn userauth_publickey_signed(
+        &self,
+        username: &str,
+        method: &str,
+        publickey: &[u8],
+        signature: &[u8],
+    ) -> Result<Userauth> {
+        let (sender, recver) = o_channel();
+        let request = Request::UserauthPublickeySigned {
+            username: username.to_string(),
+            method: method.to_string(),
+            publickey: publickey.to_vec(),
+            signature: signature.to_vec(),
+            sender,
+        };
+        self.send_request(request)?;
+
+        recver.await?
+    }
+
+    /// Authenticate `username` as `local_username` of the client host `client_hostname`,
+    /// the signer holds the private key of the client host, e.g. `/etc/ssh/ssh_host_ed25519_key`.
+    /// A single signed request is sent, with the first algorithm the signer really signs with.
+    pub async fn userauth_hostbased<S>(
+        &self,
+        username: impl Into<String>,
+        local_username: impl Into<String>,
+        client_hostname: impl Into<String>,
+        signer: &S,
+    ) -> Result<Userauth>
+    where
+        S: Signer + ?Sized,
+    {
+        let username = username.into();
+        let local_username = local_username.into();
+        let client_hostname = client_hostname.into();
+
+        for method in self.select_algorithms(signer).await? {
+            let hostbased = || Hostbased {
+                username: username.clone(),
+                method: method.clone(),
+                publickey: signer.public_key().to_vec(),
+                client_hostname: client_hostname.clone(),
+                local_username: local_username.clone(),
+            };
+
+            let (sender, recver) = o_channel();
+            self.send_request(Request::UserauthHostbasedData {
+                hostbased: hostbased(),
+                sender,
+            })?;
+            let data = recver.await??;
+
+            let signature = signer.sign(&data, &method).await?;
+            if !signed_with(&signature, &method) {
+                continue;
+            }
+
+            // nothing tells a rejected key from a rejected algorithm, so there is no retry
+            let (sender, recver) = o_channel();
+            self.send_request(Request::UserauthHostbased {
+                hostbased: hostbased(),
+                signature,
+                sender,
+            })?;
+
+            return recver.await?;
+        }
+
+        Ok(Userauth::Failure(vec![], false))
+    }
+
     pub async fn userauth_password(
         &self,
         username: impl Into<String>,
@@ -395,17 +681,57 @@ impl Session {
         recver.await?
     }
 
+    /// Answer a `Userauth::Expired` by replacing the expired password `old` with `new`,
+    /// if the server rejects `new` it answers `Userauth::Expired` again with a new prompt
+    pub async fn userauth_password_change(
+        &self,
+        username: impl Into<String>,
+        old: impl Into<String>,
+        new: impl Into<String>,
+    ) -> Result<Userauth> {
+        let (sender, recver) = o_channel();
+
+        let request = Request::UserauthPasswordChange {
+            username: username.into(),
+            old: old.into(),
+            new: new.into(),
+            sender,
+        };
+
+        self.send_request(request)?;
+
+        recver.await?
+    }
+
     pub async fn userauth_keyboard_interactive<T: Interactive + 'static>(
         &self,
         username: impl Into<String>,
         prefer: impl IntoIterator<Item = impl Into<String>>,
         cb: T,
     ) -> Result<bool> {
+        let status = self
+            .userauth_keyboard_interactive_status(
+                username.into(),
+                prefer.into_iter().map(|v| v.into()).collect(),
+                Box::new(cb),
+            )
+            .await?;
+
+        Ok(status == Userauth::Success)
+    }
+
+    /// Like `userauth_keyboard_interactive` but keeps the methods and partial success the server sent back
+    pub(crate) async fn userauth_keyboard_interactive_status(
+        &self,
+        username: String,
+        submethods: Vec<String>,
+        cb: Box<dyn Interactive>,
+    ) -> Result<Userauth> {
         let (sender, recver) = o_channel();
         let request = Request::UserauthKeyboardInteractive {
-            username: username.into(),
-            submethods: prefer.into_iter().map(|v| v.into()).collect(),
-            cb: Box::new(cb),
+            username,
+            submethods,
+            cb,
             sender,
         };
 
@@ -458,36 +784,41 @@ impl Session {
 
         snafu::ensure!(res, builder::HostKeyVerifyFailed);
 
-        if let Some(ref mut behavior) = config.behavior {
-            if !behavior
-                .verify_server_hostkey(algo.hostkey.name(), &result.server_hostkey)
-                .await?
-            {
-                // return Err(Error::RejectByUser(
-                //     "Server public key rejected by user".to_string(),
-                // ));
-                return builder::RejectByUser {
-                    tip: "Server public key rejected by user",
-                }
-                .fail();
-            }
-        }
+        handshake::verify_hostkey(&mut config, algo.hostkey.name(), &result.server_hostkey).await?;
 
         handshake::new_keys(&mut plain_stream).await?;
 
         algo.initialize(&mut result)?;
 
-        let (sender, recver) = m_channel();
-
-        let weak_sender = sender.downgrade();
-        let session = Session::new(sender);
+        // ext-info-c only belongs in the first key exchange, https://www.rfc-editor.org/rfc/rfc8308#section-2.1
+        config.ext = false;
 
         let stream = plain_stream.encrypt(
             (algo.client_crypt, algo.client_mac, algo.client_compress),
             (algo.server_crypt, algo.server_mac, algo.server_compress),
         );
+
+        Self::start(result.session_id, stream, banner, config).await
+    }
+
+    /// Ask for the userauth service over a stream the keys are exchanged on
+    pub(crate) async fn start<T, B>(
+        session_id: Vec<u8>,
+        stream: CipherStream<T>,
+        banner: String,
+        config: handshake::Config<B>,
+    ) -> Result<Self>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        B: Behavior + Send + Sync + 'static,
+    {
+        let (sender, recver) = m_channel();
+
+        let weak_sender = sender.downgrade();
+        let session = Session::new(sender);
+
         let mut inner = SessionInner::new(
-            result.session_id,
+            session_id,
             stream,
             Endpoint::new(config.banner.clone()),
             Endpoint::new(banner),
@@ -525,6 +856,12 @@ where
 
     #[new(default)]
     listeners: HashMap<SocketAddr, ListenerInner>,
+
+    #[new(default)]
+    agent_forward_requested: bool,
+    /// The `server-sig-algs` extension, None if the server didn't send it
+    #[new(default)]
+    server_sig_algs: Option<Vec<String>>,
     weak_sender: MWSender<Request>,
     // behaivor: Option<B>,
     config: handshake::Config<B>,
@@ -732,13 +1069,14 @@ where
                 self.handle_global_keep_alive(want_reply).await?;
             }
             Message::ExtInfo(ext) => {
-                if let Some(behavior) = self.behaviour() {
-                    for (name, value) in ext {
-                        if name == "server-sig-algs" {
-                            let value = std::str::from_utf8(&value)?;
-                            let algos = value.split(',').collect::<Vec<_>>();
+                for (name, value) in ext {
+                    if name == "server-sig-algs" {
+                        let value = std::str::from_utf8(&value)?;
+                        let algos = value.split(',').collect::<Vec<_>>();
+                        if let Some(behavior) = self.behaviour() {
                             behavior.server_signature_algorithms(&algos).await?;
                         }
+                        self.server_sig_algs = Some(algos.iter().map(|v| v.to_string()).collect());
                     }
                 }
             }
@@ -752,6 +1090,13 @@ where
                 self.handle_x11_forward(sender, initial, maximum, address, port)
                     .await?;
             }
+            Message::AgentForward {
+                sender,
+                initial,
+                maximum,
+            } => {
+                self.handle_agent_forward(sender, initial, maximum).await?;
+            }
             Message::ChannelKeepAliveOpenSSH {
                 recipient,
                 want_reply,
@@ -802,20 +1147,12 @@ where
         // }
         snafu::ensure!(res, builder::HostKeyVerifyFailed);
 
-        if let Some(behavior) = self.behaviour() {
-            if !behavior
-                .verify_server_hostkey(algo.hostkey.name(), &result.server_hostkey)
-                .await?
-            {
-                // return Err(Error::RejectByUser(
-                //     "Server public key rejected by user".to_string(),
-                // ));
-                return builder::RejectByUser {
-                    tip: "Server public key rejected by user",
-                }
-                .fail();
-            }
-        }
+        handshake::verify_hostkey(
+            &mut self.config,
+            algo.hostkey.name(),
+            &result.server_hostkey,
+        )
+        .await?;
 
         handshake::new_keys(&mut self.stream).await?;
 
@@ -893,7 +1230,8 @@ where
 
             self.stream.send_payload(buffer).await?;
 
-            let channel = Channel::new(client_id, rx, session);
+            let channel = Channel::new(client_id, rx, session)
+                .with_session_limiter(self.config.rate_limiter.clone());
             use super::channel::Endpoint as ChannelEp;
 
             let inner = ChannelInner::new(
@@ -930,6 +1268,61 @@ where
         Ok(())
     }
 
+    async fn handle_agent_forward(
+        &mut self,
+        sender: u32,
+        initial: u32,
+        maximum: u32,
+    ) -> Result<()> {
+        // only accept what we asked for
+        if !self.agent_forward_requested
+            || matches!(self.config.agent_forward, AgentForward::Refuse)
+        {
+            let buffer = make_buffer_without_header! {
+                u8: SSH_MSG_CHANNEL_OPEN_FAILURE,
+                u32: sender,
+                u32: ChannelOpenFailureReson::ADMINISTRATIVELY_PROHIBITED.0,
+                one: "agent forwarding refused",
+                u32: 0
+            };
+
+            return self.stream.send_payload(buffer).await;
+        }
+
+        let session = self.upgrade_sender()?;
+        let client_id = self.genarate_channel_id();
+
+        let buffer = make_buffer_without_header! {
+            u8: SSH_MSG_CHANNEL_OPEN_CONFIRMATION,
+            u32: sender,
+            u32: client_id,
+            u32: 1024 * 1024,
+            u32: 32768
+        };
+
+        self.stream.send_payload(buffer).await?;
+
+        let (tx, rx) = m_channel();
+
+        let channel = Channel::new(client_id, rx, session.clone())
+            .with_session_limiter(self.config.rate_limiter.clone());
+        use super::channel::Endpoint as ChannelEp;
+
+        let inner = ChannelInner::new(
+            ChannelEp::new(client_id, 1024 * 1024, 32768),
+            ChannelEp::new(sender, initial, maximum),
+            tx,
+        );
+
+        self.channels.insert(client_id, inner);
+
+        // the agent may take a while to answer, the session doesn't wait for it
+        let forward = self.config.agent_forward.clone();
+        tokio::spawn(agent::relay(channel, forward, session));
+
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     async fn accpet_tcpip_forward(
         &mut self,
@@ -965,7 +1358,8 @@ where
 
                 let (tx, rx) = m_channel();
 
-                let channel = Channel::new(client_id, rx, session);
+                let channel = Channel::new(client_id, rx, session)
+                    .with_session_limiter(self.config.rate_limiter.clone());
                 use super::channel::Endpoint as ChannelEp;
 
                 let inner = ChannelInner::new(
@@ -1139,19 +1533,56 @@ where
 
                 let _ = sender.send(res);
             }
+            Request::UserauthPasswordChange {
+                username,
+                old,
+                new,
+                sender,
+            } => {
+                let res = self.userauth_password_change(&username, &old, &new).await;
+                let _ = sender.send(res);
+            }
+            Request::UserauthHostbasedData { hostbased, sender } => {
+                let mut data = Buffer::new();
+                data.put_one(&self.session_id);
+                data.put_bytes(hostbased.request());
+                let _ = sender.send(Ok(data.into_vec()));
+            }
+            Request::UserauthHostbased {
+                hostbased,
+                signature,
+                sender,
+            } => {
+                let res = self.userauth_hostbased(&hostbased, &signature).await;
+                let _ = sender.send(res);
+            }
+            Request::ServerSigAlgs { sender } => {
+                let _ = sender.send(self.server_sig_algs.clone());
+            }
             Request::UserauthNone { username, sender } => {
                 let res = self.userauth_none(&username).await;
                 let _ = sender.send(res);
             }
-            Request::UserauthPublickey {
+            Request::UserauthPublickeyQuery {
                 username,
                 method,
                 publickey,
-                privatekey,
                 sender,
             } => {
                 let res = self
-                    .userauth_publickey(&username, &method, &publickey, &privatekey)
+                    .userauth_publickey_query(&username, &method, &publickey)
+                    .await;
+                let _ = sender.send(res);
+            }
+            Request::UserauthPublickeySigned {
+                username,
+                method,
+                publickey,
+                signature,
+                sender,
+            } => {
+                let res = self
+                    .userauth_publickey_signed(&username, &method, &publickey, &signature)
                     .await;
                 let _ = sender.send(res);
             }
@@ -1296,6 +1727,22 @@ where
                     .await;
                 let _ = sender.send(res);
             }
+            Request::ChannelAgentForward { id, sender } => {
+                let res = self.channel_agent_forward(id).await;
+                let _ = sender.send(res);
+            }
+            Request::AgentForwardConfirm { request, sender } => {
+                let res = match self.behaviour() {
+                    Some(behavior) => behavior.confirm_agent_forward(&request).await,
+                    None => Ok(true),
+                };
+                let _ = sender.send(res);
+            }
+            Request::AgentForwardFailed { error } => {
+                if let Some(behavior) = self.behaviour() {
+                    let _ = behavior.agent_forward_failed(&error).await;
+                }
+            }
             Request::XonXoff { id, allow, sender } => {
                 let res = self.channel_xon_xoff(id, allow).await;
 
@@ -1341,20 +1788,12 @@ where
         // }
         snafu::ensure!(res, builder::HostKeyVerifyFailed);
 
-        if let Some(behavior) = self.behaviour() {
-            if !behavior
-                .verify_server_hostkey(algo.hostkey.name(), &result.server_hostkey)
-                .await?
-            {
-                // return Err(Error::RejectByUser(
-                //     "Server public key rejected by user".to_string(),
-                // ));
-                return builder::RejectByUser {
-                    tip: "Server public key rejected by user",
-                }
-                .fail();
-            }
-        }
+        handshake::verify_hostkey(
+            &mut self.config,
+            algo.hostkey.name(),
+            &result.server_hostkey,
+        )
+        .await?;
 
         handshake::new_keys(&mut self.stream).await?;
 
@@ -1386,6 +1825,31 @@ where
         self.stream.send_payload(buffer).await
     }
 
+    async fn channel_agent_forward(&mut self, id: u32) -> Result<()> {
+        let recipient = self.get_server_channel_id(id)?;
+
+        let buffer = make_buffer_without_header! {
+            u8: SSH_MSG_CHANNEL_REQUEST,
+            u32: recipient,
+            one: "auth-agent-req@openssh.com",
+            u8: 1,
+        };
+
+        self.stream.send_payload(buffer).await?;
+
+        channel_loop!(
+            self,
+            id,
+            Message::ChannelSuccess(recipient) if recipient == id => {
+                self.agent_forward_requested = true;
+                return Ok(());
+            },
+            Message::ChannelFailure(recipient) if recipient == id => {
+                return builder::ChannelFailure.fail();
+            },
+        );
+    }
+
     async fn channel_x11_forward(
         &mut self,
         id: u32,
@@ -1425,7 +1889,7 @@ where
         username: &str,
         submethods: &[String],
         cb: &mut Box<dyn Interactive>,
-    ) -> Result<bool> {
+    ) -> Result<Userauth> {
         let submethods = submethods.join(",");
 
         let buffer = make_buffer_without_header! {
@@ -1470,11 +1934,17 @@ where
             }
             match packet.payload[0] {
                 SSH_MSG_USERAUTH_SUCCESS => {
-                    return Ok(true);
+                    self.stream.authed = true;
+                    return Ok(Userauth::Success);
                 }
 
                 SSH_MSG_USERAUTH_FAILURE => {
-                    return Ok(false);
+                    let Ok(Message::UserauthFailure { methods, partial }) =
+                        Message::parse(&packet.payload)
+                    else {
+                        return Err(Error::invalid_format("Invalid ssh packet"));
+                    };
+                    return Ok(Userauth::Failure(methods, partial));
                 }
                 SSH_MSG_USERAUTH_INFO_REQUEST => {
                     let Some((name, instruction, prompts)) = parse(&packet.payload[1..]) else {
@@ -1651,7 +2121,8 @@ where
                     let server = ChannelEndpoint::new(sender, server_initial, server_maximum);
                     let (sender, recver) = m_channel();
 
-                    let channel = Channel::new(client.id, recver, session);
+                    let channel = Channel::new(client.id, recver, session)
+                        .with_session_limiter(self.config.rate_limiter.clone());
 
                     let inner = ChannelInner::new(
                         client, server, // Box::new(NormalChannel::new(stdout.0, stderr.0)),
@@ -2152,7 +2623,8 @@ where
 
         let (sender, recver) = m_channel();
 
-        let channel = Channel::new(client.id, recver, session);
+        let channel = Channel::new(client.id, recver, session)
+            .with_session_limiter(self.config.rate_limiter.clone());
 
         let inner = ChannelInner::new(
             client, server, // Box::new(NormalChannel::new(stdout.0, stderr.0)),
@@ -2195,7 +2667,7 @@ where
                 Message::UserauthFailure { methods, partial } => {
                     Ok(Userauth::Failure(methods, partial))
                 }
-                Message::UserauthChangeReq => Ok(Userauth::Failure(vec![], false)),
+                Message::UserauthChangeReq { .. } => Ok(Userauth::Failure(vec![], false)),
 
                 msg => {
                     self.handle_msg(msg).await?;
@@ -2225,6 +2697,32 @@ where
 
         self.stream.send_payload(buffer).await?;
 
+        self.userauth_password_reply().await
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc4252#section-8
+    async fn userauth_password_change(
+        &mut self,
+        username: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<Userauth> {
+        let buffer = make_buffer_without_header! {
+            u8: SSH_MSG_USERAUTH_REQUEST,
+            one: username,
+            one: "ssh-connection",
+            one: "password",
+            u8: 1,
+            one: old,
+            one: new,
+        };
+
+        self.stream.send_payload(buffer).await?;
+
+        self.userauth_password_reply().await
+    }
+
+    async fn userauth_password_reply(&mut self) -> Result<Userauth> {
         loop {
             return match self.recv_msg().await? {
                 Message::UserauthSuccess => {
@@ -2234,7 +2732,7 @@ where
                 Message::UserauthFailure { methods, partial } => {
                     Ok(Userauth::Failure(methods, partial))
                 }
-                Message::UserauthChangeReq => Ok(Userauth::Expired),
+                Message::UserauthChangeReq { prompt } => Ok(Userauth::Expired(prompt)),
 
                 msg => {
                     self.handle_msg(msg).await?;
@@ -2244,13 +2742,40 @@ where
         }
     }
 
-    async fn userauth_publickey(
+    async fn userauth_hostbased(
+        &mut self,
+        hostbased: &Hostbased,
+        signature: &[u8],
+    ) -> Result<Userauth> {
+        let mut buffer = hostbased.request();
+        buffer.put_one(signature);
+
+        self.stream.send_payload(buffer).await?;
+
+        loop {
+            return match self.recv_msg().await? {
+                Message::UserauthSuccess => {
+                    self.stream.authed = true;
+                    Ok(Userauth::Success)
+                }
+                Message::UserauthFailure { methods, partial } => {
+                    Ok(Userauth::Failure(methods, partial))
+                }
+
+                msg => {
+                    self.handle_msg(msg).await?;
+                    continue;
+                }
+            };
+        }
+    }
+
+    async fn userauth_publickey_query(
         &mut self,
         username: &str,
         method: &str,
         publickey: &[u8],
-        privatekey: &[u8],
-    ) -> Result<Userauth> {
+    ) -> Result<PublickeyQuery> {
         // let mut buffer = Buffer::new();
         // buffer.put_u8(SSH_MSG_USERAUTH_REQUEST);
         // buffer.put_one(username);
@@ -2283,7 +2808,7 @@ where
                     let (_, methods) = payload
                         .take_one()
                         .ok_or(Error::invalid_format("Invalid ssh packet"))?;
-                    return Ok(Userauth::Failure(
+                    return Ok(PublickeyQuery::Rejected(Userauth::Failure(
                         std::str::from_utf8(methods)?
                             .split(',')
                             .map(|v| v.to_string())
@@ -2292,9 +2817,12 @@ where
                             .take_u8()
                             .ok_or(Error::invalid_format("Invalid ssh packet"))?
                             != 0,
-                    ));
+                    )));
+                }
+                SSH_MSG_USERAUTH_SUCCESS => {
+                    self.stream.authed = true;
+                    return Ok(PublickeyQuery::Rejected(Userauth::Success));
                 }
-                SSH_MSG_USERAUTH_SUCCESS => return Ok(Userauth::Success),
                 SSH_MSG_USERAUTH_PK_OK => break,
                 _ => {
                     self.handle_msg(
@@ -2326,12 +2854,16 @@ where
             one: publickey,
         };
 
-        let mut algo =
-            sign::new_signature_by_name(method).ok_or(Error::ub("Unable to create cipher"))?();
-
-        algo.initialize(privatekey.as_ref())?;
-        let sign = algo.signature(buffer.as_ref())?;
+        Ok(PublickeyQuery::Accepted(buffer.into_vec()))
+    }
 
+    async fn userauth_publickey_signed(
+        &mut self,
+        username: &str,
+        method: &str,
+        publickey: &[u8],
+        signature: &[u8],
+    ) -> Result<Userauth> {
         // let mut buffer = Buffer::new();
         // buffer.put_u8(SSH_MSG_USERAUTH_REQUEST);
         // buffer.put_one(username);
@@ -2348,8 +2880,6 @@ where
         // buffer.put_one(method);
         // buffer.put_one(sign);
 
-        let len = 4 + method.len() + 4 + sign.len();
-        let len = len as u32;
         let buffer = make_buffer_without_header! {
             u8: SSH_MSG_USERAUTH_REQUEST,
             one: username,
@@ -2358,9 +2888,7 @@ where
             u8: 1,
             one: method,
             one: publickey,
-            u32: len,
-            one: method,
-            one: &sign,
+            one: signature,
         };
         self.stream.send_payload(buffer).await?;
 
@@ -0,0 +1,132 @@
+//! Replacing a file so that readers see the old content or the new one, never a part
+
+use openssl::rand::rand_bytes;
+use tokio::io::AsyncRead;
+
+use super::{Attributes, RenameFlags, SFtp};
+use crate::error::{Error, Result};
+
+impl SFtp {
+    /// Replace `path` with `data`, see `write_atomic_from`
+    pub async fn write_atomic(&self, path: &str, data: impl AsRef<[u8]>) -> Result<()> {
+        self.write_atomic_from(path, &mut data.as_ref()).await
+    }
+
+    /// Replace `path` with everything `reader` has.
+    ///
+    /// The data goes to a temporary file next to `path`, flushed to the disk when the
+    /// server supports `fsync@openssh.com`, which then takes its place. An existing file
+    /// passes on its permissions, and its owner where the server lets it. The temporary
+    /// file is removed if anything fails
+    pub async fn write_atomic_from<R>(&self, path: &str, reader: &mut R) -> Result<()>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let existing = match self.stat(path).await {
+            Ok(attrs) => Some(attrs),
+            Err(Error::NoSuchFile { .. }) => None,
+            Err(e) => return Err(e),
+        };
+
+        let temp = sibling(path, "tmp")?;
+        let res = match self.write_temp(&temp, reader, existing.as_ref()).await {
+            Ok(()) => self.replace(&temp, path, existing.is_some()).await,
+            Err(e) => Err(e),
+        };
+        if res.is_err() {
+            let _ = self.remove_file(&temp).await;
+        }
+        res
+    }
+
+    async fn write_temp<R>(
+        &self,
+        temp: &str,
+        reader: &mut R,
+        existing: Option<&Attributes>,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let permissions = existing
+            .and_then(|attrs| attrs.property)
+            .map(|property| property.permissions);
+
+        let mut options = self.open_options().write(true).create_new(true);
+        if let Some(permissions) = permissions {
+            options = options.permissions(permissions);
+        }
+        let mut file = options.open(temp).await?;
+
+        let res = async {
+            // flushed at the end, every write is answered
+            tokio::io::copy(reader, &mut file).await?;
+            if self.support_fsync() {
+                file.sync_all().await?;
+            }
+
+            // created with the umask of the server applied
+            if let Some(permissions) = permissions {
+                file.set_permissions(permissions).await?;
+            }
+            if let Some(existing) = existing {
+                let created = file.metadata().await?;
+                let created = created.attributes();
+                if existing.user != created.user || existing.owner_group != created.owner_group {
+                    let owner = Attributes {
+                        user: existing.user,
+                        owner_group: existing.owner_group.clone(),
+                        ..Default::default()
+                    };
+                    // only root may give a file away
+                    let _ = self.setfstat(file.file(), &owner).await;
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        let close = file.close().await;
+        res.and(close)
+    }
+
+    async fn replace(&self, temp: &str, path: &str, exists: bool) -> Result<()> {
+        if self.support_posix_rename() {
+            return self.posix_rename(temp, path).await;
+        }
+        if self.version >= 5 {
+            let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC;
+            match self.rename_with_flags(temp, path, flags).await {
+                Err(Error::OpUnsupported { .. }) => {}
+                res => return res,
+            }
+        }
+        if !exists {
+            return self.rename_file_or_dir(temp, path).await;
+        }
+
+        // a plain rename doesn't replace, the old file steps aside and comes back
+        // if the new one can't take its place
+        let old = sibling(path, "old")?;
+        self.rename_file_or_dir(path, &old).await?;
+        if let Err(e) = self.rename_file_or_dir(temp, path).await {
+            let _ = self.rename_file_or_dir(&old, path).await;
+            return Err(e);
+        }
+        // the new content is in place, a leftover is no reason to fail
+        let _ = self.remove_file(&old).await;
+        Ok(())
+    }
+}
+
+/// A hidden name next to `path` nobody else picks, `dir/.name.1a2b3c4d5e6f.tag`
+fn sibling(path: &str, tag: &str) -> Result<String> {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{dir}/"), name),
+        None => (String::new(), path),
+    };
+    let mut random = [0; 6];
+    rand_bytes(&mut random)?;
+    let random: String = random.iter().map(|byte| format!("{byte:02x}")).collect();
+    Ok(format!("{dir}.{name}.{random}.{tag}"))
+}
@@ -0,0 +1,731 @@
+//! A layer in the manner of `std::fs`, files are read and written through tokio's io
+//! traits and closed when dropped
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use super::{
+    Attributes, Dir, File, FileInfo, FileTime, FileType, Message, OpenFlags, Packet, Permissions,
+    Property, SFtp, Status,
+};
+use crate::error::{builder, Error, Result};
+use crate::ssh::buffer::Buffer;
+use crate::ssh::common::code::*;
+use crate::OReceiver;
+
+/// The most data asked for or sent by one request
+const CHUNK: usize = SFtp::MAX_SFTP_PACKET;
+
+/// Writes sent before waiting for the first of them to be answered
+const WRITE_WINDOW: usize = 16;
+
+type Reply = OReceiver<Result<Packet>>;
+
+/// How to open a file, see `SFtp::open_options`
+#[derive(Debug, Clone)]
+pub struct OpenOptions<'a> {
+    sftp: &'a SFtp,
+    flags: OpenFlags,
+    permissions: Option<Permissions>,
+}
+
+impl<'a> OpenOptions<'a> {
+    pub fn read(mut self, read: bool) -> Self {
+        self.flags.set(OpenFlags::READ, read);
+        self
+    }
+
+    pub fn write(mut self, write: bool) -> Self {
+        self.flags.set(OpenFlags::WRITE, write);
+        self
+    }
+
+    /// Writes go to the end of the file, implies `write`
+    pub fn append(mut self, append: bool) -> Self {
+        self.flags.set(OpenFlags::APPEND, append);
+        self
+    }
+
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.flags.set(OpenFlags::TRUNC, truncate);
+        self
+    }
+
+    pub fn create(mut self, create: bool) -> Self {
+        self.flags.set(OpenFlags::CREAT, create);
+        self
+    }
+
+    /// Fail if the file exists, `create` and `truncate` are ignored then
+    pub fn create_new(mut self, create_new: bool) -> Self {
+        self.flags.set(OpenFlags::EXCL, create_new);
+        self
+    }
+
+    /// The permissions of a created file, the server picks them if not set
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
+
+    pub async fn open(&self, path: &str) -> Result<RemoteFile> {
+        let mut flags = self.flags;
+        if flags.contains(OpenFlags::APPEND) {
+            flags |= OpenFlags::WRITE;
+        }
+        if flags.contains(OpenFlags::EXCL) {
+            flags = (flags - OpenFlags::TRUNC) | OpenFlags::CREAT;
+        }
+        if !flags.contains(OpenFlags::WRITE)
+            && flags.intersects(OpenFlags::CREAT | OpenFlags::TRUNC)
+        {
+            return builder::InvalidArgument {
+                tip: "Creating or truncating a file needs write access",
+            }
+            .fail();
+        }
+        if !flags.intersects(OpenFlags::READ | OpenFlags::WRITE) {
+            return builder::InvalidArgument {
+                tip: "A file is opened for reading, writing or both",
+            }
+            .fail();
+        }
+
+        let file = self.sftp.open_file(path, flags, self.permissions).await?;
+        let mut file = RemoteFile::new(self.sftp.clone(), file);
+        // the server writes at the end whatever the offset, keep the position honest
+        if flags.contains(OpenFlags::APPEND) {
+            file.pos = file.metadata().await?.len();
+        }
+        Ok(file)
+    }
+}
+
+/// An open file on the server, closed when dropped.
+///
+/// Writes are answered in the background, an error shows up on the next write, a
+/// flush or `close`. Drop only sends the close request, use `close` to see errors
+pub struct RemoteFile {
+    sftp: SFtp,
+    // `None` once closed
+    file: Option<File>,
+    pos: u64,
+    // data read past what the caller had room for, it starts at `pos`
+    left: Vec<u8>,
+    read: Option<Reply>,
+    writes: VecDeque<Reply>,
+    seek: Option<Seek>,
+}
+
+enum Seek {
+    To(u64),
+    // waiting for the size
+    End(Reply, i64),
+}
+
+impl RemoteFile {
+    fn new(sftp: SFtp, file: File) -> Self {
+        Self {
+            sftp,
+            file: Some(file),
+            pos: 0,
+            left: vec![],
+            read: None,
+            writes: VecDeque::new(),
+            seek: None,
+        }
+    }
+
+    pub(super) fn file(&self) -> &File {
+        self.file.as_ref().expect("closed only when dropped")
+    }
+
+    pub async fn metadata(&self) -> Result<Metadata> {
+        Ok(self.sftp.fstat(self.file()).await?.into())
+    }
+
+    pub async fn set_len(&mut self, size: u64) -> Result<()> {
+        let attrs = Attributes {
+            size: Some(size),
+            ..Default::default()
+        };
+        self.sftp.setfstat(self.file(), &attrs).await
+    }
+
+    pub async fn set_permissions(&self, permissions: Permissions) -> Result<()> {
+        self.sftp
+            .setfstat(self.file(), &permissions_attrs(permissions))
+            .await
+    }
+
+    /// Wait for the writes and flush them to the disk, needs `fsync@openssh.com`
+    pub async fn sync_all(&mut self) -> Result<()> {
+        self.written().await?;
+        if !self.sftp.support_fsync() {
+            return builder::OpUnsupported {
+                tip: "The server doesn't support fsync",
+            }
+            .fail();
+        }
+        self.sftp.fsync(self.file()).await
+    }
+
+    /// Wait for the writes and close the handle
+    pub async fn close(mut self) -> Result<()> {
+        let written = self.written().await;
+        let file = self.file.take().expect("closed only when dropped");
+        let close = self.sftp.close_file(file).await;
+        written?;
+        close
+    }
+
+    async fn written(&mut self) -> Result<()> {
+        let mut res = Ok(());
+        for reply in self.writes.drain(..) {
+            let status = SFtp::wait_for_packet(reply).await.and_then(check_status);
+            res = res.and(status);
+        }
+        res
+    }
+
+    /// Wait until at most `max` writes are in flight
+    fn poll_writes(&mut self, cx: &mut Context<'_>, max: usize) -> Poll<io::Result<()>> {
+        while self.writes.len() > max {
+            let reply = self.writes.front_mut().unwrap();
+            let res = ready!(poll_reply(reply, cx));
+            self.writes.pop_front();
+            res.and_then(check_status).map_err(io::Error::other)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    // the next read starts somewhere else
+    fn forget_read(&mut self) {
+        self.read = None;
+        self.left.clear();
+    }
+
+    fn seek_from(base: u64, offset: i64) -> io::Result<u64> {
+        base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek to a negative or overflowing position",
+            )
+        })
+    }
+}
+
+impl Drop for RemoteFile {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            self.sftp.send_close(file.handle);
+        }
+    }
+}
+
+impl AsyncRead for RemoteFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_writes(cx, 0))?;
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        if this.left.is_empty() {
+            if this.read.is_none() {
+                let len = buf.remaining().min(CHUNK) as u32;
+                let reply = this.sftp.read_at(this.file(), this.pos, len);
+                this.read = Some(reply.map_err(io::Error::other)?);
+            }
+            let res = ready!(poll_reply(this.read.as_mut().unwrap(), cx));
+            this.read = None;
+            match res.map_err(io::Error::other)?.msg {
+                Message::Data(data) => this.left = data,
+                Message::Status {
+                    status: Status::Eof,
+                    ..
+                } => return Poll::Ready(Ok(())),
+                Message::Status { status, msg, .. } => {
+                    return Poll::Ready(Err(io::Error::other(status.to_error(msg))))
+                }
+                _ => {
+                    let e = builder::Protocol { tip: "Unknown msg" }.build();
+                    return Poll::Ready(Err(io::Error::other(e)));
+                }
+            }
+        }
+
+        let len = this.left.len().min(buf.remaining());
+        buf.put_slice(&this.left[..len]);
+        this.left.drain(..len);
+        this.pos += len as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for RemoteFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_writes(cx, WRITE_WINDOW - 1))?;
+
+        this.forget_read();
+        let len = buf.len().min(CHUNK);
+        let reply = this.sftp.write_at(this.file(), this.pos, &buf[..len]);
+        this.writes.push_back(reply.map_err(io::Error::other)?);
+        this.pos += len as u64;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_writes(cx, 0)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_writes(cx, 0)
+    }
+}
+
+impl AsyncSeek for RemoteFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        if this.seek.is_some() {
+            return Err(io::Error::other("Another seek is in progress"));
+        }
+
+        let pos = match position {
+            SeekFrom::Start(pos) => pos,
+            // the data read ahead is where the caller already is
+            SeekFrom::Current(0) => {
+                this.seek = Some(Seek::To(this.pos));
+                return Ok(());
+            }
+            SeekFrom::Current(offset) => Self::seek_from(this.pos, offset)?,
+            SeekFrom::End(offset) => {
+                let flags = this.sftp.attr_flags();
+                let buffer = make_buffer! {
+                    u8: SSH_FXP_FSTAT,
+                    u32: SFtp::UNASSIGNED_ID,
+                    one: &this.file().handle,
+                    bytes: flags,
+                };
+                let reply = this.sftp.send(buffer).map_err(io::Error::other)?;
+                this.forget_read();
+                this.seek = Some(Seek::End(reply, offset));
+                return Ok(());
+            }
+        };
+        this.forget_read();
+        this.seek = Some(Seek::To(pos));
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        ready!(this.poll_writes(cx, 0))?;
+
+        this.pos = match this.seek.as_mut() {
+            None => return Poll::Ready(Ok(this.pos)),
+            Some(Seek::To(pos)) => *pos,
+            Some(Seek::End(reply, offset)) => {
+                let offset = *offset;
+                let res = ready!(poll_reply(reply, cx));
+                this.seek = None;
+                let size = match res.map_err(io::Error::other)?.msg {
+                    Message::Attributes(attrs) => attrs.size.unwrap_or_default(),
+                    Message::Status { status, msg, .. } => {
+                        return Poll::Ready(Err(io::Error::other(status.to_error(msg))))
+                    }
+                    _ => {
+                        let e = builder::Protocol { tip: "Unknown msg" }.build();
+                        return Poll::Ready(Err(io::Error::other(e)));
+                    }
+                };
+                Self::seek_from(size, offset)?
+            }
+        };
+        this.seek = None;
+        Poll::Ready(Ok(this.pos))
+    }
+}
+
+/// The entries of a directory without `.` and `..`, see `SFtp::list_dir`.
+///
+/// READDIR is sent batch by batch as the entries are taken, the handle is closed at
+/// the end of the directory, after an error or when dropped
+pub struct ReadDir {
+    sftp: SFtp,
+    // `None` once closed
+    dir: Option<Dir>,
+    batch: VecDeque<FileInfo>,
+    reply: Option<Reply>,
+}
+
+impl ReadDir {
+    /// `None` at the end of the directory
+    pub async fn next_entry(&mut self) -> Result<Option<FileInfo>> {
+        std::future::poll_fn(|cx| self.poll_next_entry(cx)).await
+    }
+
+    /// The same as `next_entry`, for implementing a `Stream` on top of it
+    pub fn poll_next_entry(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<FileInfo>>> {
+        loop {
+            if let Some(info) = self.batch.pop_front() {
+                if info.filename == "." || info.filename == ".." {
+                    continue;
+                }
+                return Poll::Ready(Ok(Some(info)));
+            }
+
+            let Some(dir) = &self.dir else {
+                return Poll::Ready(Ok(None));
+            };
+            if self.reply.is_none() {
+                let buffer = make_buffer! {
+                    u8: SSH_FXP_READDIR,
+                    u32: SFtp::UNASSIGNED_ID,
+                    one: &dir.handle,
+                };
+                match self.sftp.send(buffer) {
+                    Ok(reply) => self.reply = Some(reply),
+                    Err(e) => {
+                        self.finish();
+                        return Poll::Ready(Err(e));
+                    }
+                }
+            }
+
+            let res = ready!(poll_reply(self.reply.as_mut().unwrap(), cx));
+            self.reply = None;
+            let res = match res.map(|packet| packet.msg) {
+                Ok(Message::Name(infos)) if !infos.is_empty() => {
+                    self.batch.extend(infos);
+                    continue;
+                }
+                Ok(Message::Name(_)) => Ok(None),
+                Ok(Message::Status {
+                    status: Status::Eof,
+                    ..
+                }) => Ok(None),
+                Ok(Message::Status { status, msg, .. }) => Err(status.to_error(msg)),
+                Ok(_) => builder::Protocol { tip: "Unknown msg" }.fail(),
+                Err(e) => Err(e),
+            };
+            self.finish();
+            return Poll::Ready(res);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(dir) = self.dir.take() {
+            self.sftp.send_close(dir.handle);
+        }
+    }
+}
+
+impl Drop for ReadDir {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+fn poll_reply(reply: &mut Reply, cx: &mut Context<'_>) -> Poll<Result<Packet>> {
+    Pin::new(reply)
+        .poll(cx)
+        .map(|res| res.map_err(|_| builder::ChannelClosed.build())?)
+}
+
+fn check_status(packet: Packet) -> Result<()> {
+    match packet.msg {
+        Message::Status { status, msg, .. } => status.no_eof(msg),
+        _ => builder::Protocol { tip: "Unknown msg" }.fail(),
+    }
+}
+
+fn permissions_attrs(permissions: Permissions) -> Attributes {
+    Attributes {
+        property: Some(Property::new(permissions, FileType::RegularFile)),
+        ..Default::default()
+    }
+}
+
+/// The attributes of a file in the manner of `std::fs::Metadata`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    attrs: Attributes,
+}
+
+impl Metadata {
+    pub fn attributes(&self) -> &Attributes {
+        &self.attrs
+    }
+
+    /// 0 if the server didn't send the size
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.attrs.size.unwrap_or_default()
+    }
+
+    pub fn file_type(&self) -> Option<FileType> {
+        self.attrs.file_type
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_some_and(|t| t.is_directory())
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_some_and(|t| t.is_regular_file())
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type().is_some_and(|t| t.is_symbolic_link())
+    }
+
+    pub fn permissions(&self) -> Option<Permissions> {
+        self.attrs.property.map(|p| p.permissions)
+    }
+
+    /// The type and permission bits as in `st_mode`
+    pub fn mode(&self) -> Option<u32> {
+        let permissions = self.permissions()?.bits();
+        Some(self.file_type().map_or(0, |t| t as u32) | permissions)
+    }
+
+    pub fn modified(&self) -> Option<SystemTime> {
+        let seconds = self.attrs.time.map(|t| t.mtime);
+        self.attrs.modify_time.map(Into::into).or(seconds.map(unix))
+    }
+
+    pub fn accessed(&self) -> Option<SystemTime> {
+        let seconds = self.attrs.time.map(|t| t.atime);
+        self.attrs.access_time.map(Into::into).or(seconds.map(unix))
+    }
+
+    /// Version 4 on
+    pub fn created(&self) -> Option<SystemTime> {
+        self.attrs.create_time.map(Into::into)
+    }
+}
+
+impl From<Attributes> for Metadata {
+    fn from(attrs: Attributes) -> Self {
+        Self { attrs }
+    }
+}
+
+impl From<Metadata> for Attributes {
+    fn from(metadata: Metadata) -> Self {
+        metadata.attrs
+    }
+}
+
+impl From<FileTime> for SystemTime {
+    fn from(time: FileTime) -> Self {
+        let since = Duration::new(time.seconds.unsigned_abs(), time.nanoseconds);
+        match time.seconds < 0 {
+            true => SystemTime::UNIX_EPOCH - since,
+            false => SystemTime::UNIX_EPOCH + since,
+        }
+    }
+}
+
+fn unix(seconds: u32) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(seconds as u64)
+}
+
+impl SFtp {
+    pub fn open_options(&self) -> OpenOptions<'_> {
+        OpenOptions {
+            sftp: self,
+            flags: OpenFlags::empty(),
+            permissions: None,
+        }
+    }
+
+    /// Open a file for reading
+    pub async fn open(&self, path: &str) -> Result<RemoteFile> {
+        self.open_options().read(true).open(path).await
+    }
+
+    /// Open a file for writing, it's created or truncated
+    pub async fn create(&self, path: &str) -> Result<RemoteFile> {
+        self.open_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await
+    }
+
+    /// Follows symbolic links
+    pub async fn metadata(&self, path: &str) -> Result<Metadata> {
+        Ok(self.stat(path).await?.into())
+    }
+
+    pub async fn symlink_metadata(&self, path: &str) -> Result<Metadata> {
+        Ok(self.lstat(path).await?.into())
+    }
+
+    /// The whole content of a file
+    pub async fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        self.download_to(path, &mut data).await?;
+        Ok(data)
+    }
+
+    /// Replace the content of a file, it's created if missing
+    pub async fn write(&self, path: &str, data: impl AsRef<[u8]>) -> Result<()> {
+        self.upload_from(&mut data.as_ref(), path).await?;
+        Ok(())
+    }
+
+    /// Create a directory and the missing ones above it
+    pub async fn create_dir_all(&self, path: &str) -> Result<()> {
+        // deepest first
+        let mut missing = vec![];
+        let mut current = path.trim_end_matches('/');
+        while !current.is_empty() {
+            match self.stat(current).await {
+                Ok(attrs) => match attrs.file_type {
+                    Some(file_type) if !file_type.is_directory() => {
+                        return builder::SFtpFailure {
+                            tip: format!("{current} exists and isn't a directory"),
+                        }
+                        .fail()
+                    }
+                    _ => break,
+                },
+                Err(Error::NoSuchFile { .. }) => missing.push(current),
+                Err(e) => return Err(e),
+            }
+            current = current.rsplit_once('/').map_or("", |(parent, _)| parent);
+        }
+
+        for dir in missing.into_iter().rev() {
+            if let Err(e) = self.mkdir(dir, Permissions::p0755()).await {
+                // created by someone else meanwhile
+                if !self.metadata(dir).await.is_ok_and(|m| m.is_dir()) {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a directory with everything in it, symbolic links aren't followed
+    pub async fn remove_dir_all(&self, path: &str) -> Result<()> {
+        // depth first, a directory is removed after everything in it
+        let mut dirs = vec![];
+        let mut pending = vec![path.to_string()];
+        while let Some(dir) = pending.pop() {
+            for info in self.list(&dir).await? {
+                let path = format!("{}/{}", dir.trim_end_matches('/'), info.filename);
+                match info.attrs.file_type {
+                    Some(FileType::Directory) => pending.push(path),
+                    _ => self.remove_file(&path).await?,
+                }
+            }
+            dirs.push(dir);
+        }
+
+        for dir in dirs.iter().rev() {
+            self.rmdir(dir).await?;
+        }
+        Ok(())
+    }
+
+    /// Copy the content and permissions of a file, on the server with `copy-data`
+    /// or else through the client. Returns the bytes copied
+    pub async fn copy(&self, from: &str, to: &str) -> Result<u64> {
+        let permissions = self.metadata(from).await?.permissions();
+
+        let mut options = self.open_options().write(true).create(true).truncate(true);
+        if let Some(permissions) = permissions {
+            options = options.permissions(permissions);
+        }
+        let mut writer = options.open(to).await?;
+
+        let res: Result<u64> = async {
+            let total = match self.support_copy_data() {
+                true => {
+                    let mut reader = self.open_file(from, OpenFlags::READ, None).await?;
+                    // 0 is until the end of the file
+                    let res = self
+                        .copy_data(&mut reader, 0, writer.file.as_mut().unwrap())
+                        .await;
+                    let close = self.close_file(reader).await;
+                    res.and(close)?;
+                    writer.metadata().await?.len()
+                }
+                false => self.download_to(from, &mut writer).await?,
+            };
+            // an existing file keeps its permissions on open
+            if let Some(permissions) = permissions {
+                writer.set_permissions(permissions).await?;
+            }
+            Ok(total)
+        }
+        .await;
+
+        let close = writer.close().await;
+        let total = res?;
+        close?;
+        Ok(total)
+    }
+
+    /// The entries of a directory read as they're taken, see `ReadDir`
+    pub async fn list_dir(&self, path: &str) -> Result<ReadDir> {
+        let dir = self.open_dir(path).await?;
+        Ok(ReadDir {
+            sftp: self.clone(),
+            dir: Some(dir),
+            batch: VecDeque::new(),
+            reply: None,
+        })
+    }
+
+    // the reply isn't waited for, the dispatcher drops it
+    fn send_close(&self, handle: Vec<u8>) {
+        let buffer = make_buffer! {
+            u8: SSH_FXP_CLOSE,
+            u32: Self::UNASSIGNED_ID,
+            one: handle,
+        };
+        let _ = self.send(buffer);
+    }
+
+    /// The entries of a directory without `.` and `..`
+    pub(super) async fn list(&self, dir: &str) -> Result<Vec<FileInfo>> {
+        let handle = self.open_dir(dir).await?;
+
+        let mut entries = vec![];
+        let res = loop {
+            match self.read_dir(&handle).await {
+                Ok(infos) if infos.is_empty() => break Ok(()),
+                Ok(infos) => entries.extend(
+                    infos
+                        .into_iter()
+                        .filter(|info| info.filename != "." && info.filename != ".."),
+                ),
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.close_dir(handle).await?;
+        res.map(|_| entries)
+    }
+}
@@ -0,0 +1,2329 @@
+use std::cell::Cell;
+use std::cmp::min;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use derive_new::new;
+use num_enum::TryFromPrimitive;
+use snafu::OptionExt;
+use tokio::sync::mpsc;
+
+use crate::channel::{BufferChannel, Channel};
+use crate::error::{builder, Result};
+use crate::msg::Request;
+use crate::ssh::common::*;
+use crate::{
+    error::Error,
+    ssh::{buffer::Buffer, common::code::*},
+};
+
+use super::{o_channel, MReceiver, MSender, OReceiver, OSender};
+use bitflags::bitflags;
+
+mod atomic;
+mod fs;
+mod progress;
+mod server;
+mod transfer;
+mod tree;
+mod walk;
+
+pub use fs::{Metadata, OpenOptions, ReadDir, RemoteFile};
+pub use progress::{Progress, ProgressStatus};
+pub use server::{FileSystem, LocalFs, MemoryFs, SFtpServer, ServerFile};
+pub use transfer::Transfer;
+pub use tree::{SymlinkPolicy, TreeAction, TreeEntry, TreeReport};
+pub use walk::{Walk, WalkEntry};
+
+bitflags! {
+    // https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-01#section-7.3
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct OpenFlags: u32 {
+        // Open the file for reading
+        const READ                        = SSH_FXF_READ;
+        // Open the file for writing.  If both this and SSH_FXF_READ are specified,
+        // the file is opened for both reading and writing.
+        const WRITE                       = SSH_FXF_WRITE;
+        // Force all writes to append data at the end of the file.
+        const APPEND                      = SSH_FXF_APPEND;
+        // If this flag is specified, then a new file will be created if one
+        // does not alread exist (if O_TRUNC is specified, the new file will
+        // be truncated to zero length if it previously exists)
+        const CREAT                       = SSH_FXF_CREAT;
+        // Forces an existing file with the same name to be truncated to zero
+        // length when creating a file by specifying SSH_FXF_CREAT.
+        // SSH_FXF_CREAT MUST also be specified if this flag is used.
+        const TRUNC                       = SSH_FXF_TRUNC;
+        // Causes the request to fail if the named file already exists.
+        // SSH_FXF_CREAT MUST also be specified if this flag is used.
+        const EXCL                        = SSH_FXF_EXCL;
+        // Open the file in text mode, line endings are converted to the ones of the
+        // server. Version 4 on, left out on version 3.
+        const TEXT                        = SSH_FXF_TEXT;
+    }
+}
+
+bitflags! {
+    // https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-13#section-8.3, version 5 on
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct RenameFlags: u32 {
+        // Replace the new path if it exists
+        const OVERWRITE                   = SSH_FXF_RENAME_OVERWRITE;
+        // Nobody sees the new path missing while it's replaced
+        const ATOMIC                      = SSH_FXF_RENAME_ATOMIC;
+        // The server may rename the way its filesystem does, ignoring the other flags
+        const NATIVE                      = SSH_FXF_RENAME_NATIVE;
+    }
+}
+
+bitflags! {
+    // https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-13#section-8.1.1.3, version 6
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct LockFlags: u32 {
+        // Others can't read the range
+        const READ                        = SSH_FXF_BLOCK_READ;
+        // Others can't write the range
+        const WRITE                       = SSH_FXF_BLOCK_WRITE;
+        // Others can't delete the file
+        const DELETE                      = SSH_FXF_BLOCK_DELETE;
+        // Only other locks are blocked, reads and writes aren't
+        const ADVISORY                    = SSH_FXF_BLOCK_ADVISORY;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Statvfs {
+    pub bsize: u64,
+    pub frsize: u64,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub favail: u64,
+    pub fsid: u64,
+    pub flag: u64,
+    pub namemax: u64,
+}
+
+impl Statvfs {
+    pub const FLAG_RDONLY: u64 = 0x1;
+    pub const FLAG_NOSUID: u64 = 0x2;
+    fn parse(data: &[u8]) -> Option<Self> {
+        let buffer = Buffer::from_slice(data);
+
+        Some(Self {
+            bsize: buffer.take_u64()?,
+            frsize: buffer.take_u64()?,
+            blocks: buffer.take_u64()?,
+            bfree: buffer.take_u64()?,
+            bavail: buffer.take_u64()?,
+            files: buffer.take_u64()?,
+            ffree: buffer.take_u64()?,
+            favail: buffer.take_u64()?,
+            fsid: buffer.take_u64()?,
+            flag: buffer.take_u64()?,
+            namemax: buffer.take_u64()?,
+        })
+    }
+    fn to_bytes(self, buffer: &mut Buffer<Vec<u8>>) {
+        for value in [
+            self.bsize,
+            self.frsize,
+            self.blocks,
+            self.bfree,
+            self.bavail,
+            self.files,
+            self.ffree,
+            self.favail,
+            self.fsid,
+            self.flag,
+            self.namemax,
+        ] {
+            buffer.put_u64(value);
+        }
+    }
+}
+
+/// A handle to a sftp subsystem, cheap to clone, every clone shares the channel.
+///
+/// The channel is owned by a dispatcher task, requests from all clones are written
+/// in the order they are made and the replies are routed back by request id,
+/// so many requests can be in flight at the same time, see `set_max_outstanding`
+#[derive(Clone)]
+pub struct SFtp {
+    sender: MSender<Command>,
+    max_outstanding: Arc<AtomicUsize>,
+    version: u32,
+    ext: Arc<HashMap<String, Vec<u8>>>,
+}
+
+impl Debug for SFtp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SFtp")
+            .field("max_outstanding", &self.max_outstanding())
+            .field("version", &self.version)
+            .field("ext", &self.ext)
+            .finish()
+    }
+}
+
+impl SFtp {
+    const DEFAULT_MAX_OUTSTANDING: usize = 64;
+
+    pub(crate) fn new(channel: Channel, version: u32, ext: HashMap<String, Vec<u8>>) -> Self {
+        let (sender, recver) = mpsc::unbounded_channel();
+        let max_outstanding = Arc::new(AtomicUsize::new(Self::DEFAULT_MAX_OUTSTANDING));
+
+        let dispatcher = Dispatcher {
+            channel: BufferChannel::new(channel),
+            recver,
+            request_id: 0,
+            pending: HashMap::new(),
+            waiting: VecDeque::new(),
+            max_outstanding: max_outstanding.clone(),
+            version,
+        };
+        tokio::spawn(dispatcher.run());
+
+        Self {
+            sender,
+            max_outstanding,
+            version,
+            ext: Arc::new(ext),
+        }
+    }
+
+    /// The most requests waiting for a reply at the same time, 64 by default,
+    /// requests beyond it are queued until earlier ones are answered
+    pub fn set_max_outstanding(&self, max: usize) {
+        self.max_outstanding.store(max.max(1), Ordering::Relaxed);
+    }
+
+    pub fn max_outstanding(&self) -> usize {
+        self.max_outstanding.load(Ordering::Relaxed)
+    }
+}
+
+enum Command {
+    Request {
+        packet: Vec<u8>,
+        sender: OSender<Result<Packet>>,
+    },
+    Close {
+        sender: OSender<Result<()>>,
+    },
+}
+
+/// Owns the channel, writes the requests and routes the replies
+struct Dispatcher {
+    channel: BufferChannel,
+    recver: MReceiver<Command>,
+    request_id: u32,
+    pending: HashMap<u32, OSender<Result<Packet>>>,
+    // requests beyond `max_outstanding`
+    waiting: VecDeque<(Vec<u8>, OSender<Result<Packet>>)>,
+    max_outstanding: Arc<AtomicUsize>,
+    version: u32,
+}
+
+impl Dispatcher {
+    async fn run(mut self) {
+        loop {
+            tokio::select! {
+                command = self.recver.recv() => match command {
+                    Some(Command::Request { packet, sender }) => self.waiting.push_back((packet, sender)),
+                    Some(Command::Close { sender }) => {
+                        self.fail_pending(&builder::ChannelClosed.build());
+                        let _ = sender.send(self.channel.into_inner().close().await);
+                        return;
+                    }
+                    // every handle was dropped, dropping the channel closes it
+                    None => return,
+                },
+                packet = Self::recv(&mut self.channel, self.version) => match packet {
+                    Ok(packet) => {
+                        // a reply nobody waits for is dropped, e.g. the caller was cancelled
+                        if let Some(sender) = self.pending.remove(&packet.id) {
+                            let _ = sender.send(Ok(packet));
+                        }
+                    }
+                    Err(e) => {
+                        self.fail_pending(&e);
+                        return;
+                    }
+                },
+            }
+
+            let max = self.max_outstanding.load(Ordering::Relaxed);
+            while self.pending.len() < max {
+                let Some((packet, sender)) = self.waiting.pop_front() else {
+                    break;
+                };
+                if let Err(e) = self.dispatch(packet, sender).await {
+                    self.fail_pending(&e);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn dispatch(
+        &mut self,
+        mut packet: Vec<u8>,
+        sender: OSender<Result<Packet>>,
+    ) -> Result<()> {
+        // uint32 length, byte type, uint32 request-id
+        if packet.len() < 9 {
+            let _ = sender.send(Err(Error::ub("Invalid sftp request")));
+            return Ok(());
+        }
+
+        let mut id = self.generate_request_id();
+        while self.pending.contains_key(&id) {
+            id = self.generate_request_id();
+        }
+        packet[5..9].copy_from_slice(&id.to_be_bytes());
+
+        self.channel.write_all(packet).await?;
+        self.pending.insert(id, sender);
+        Ok(())
+    }
+
+    /// Answer every request still waiting with an error
+    fn fail_pending(&mut self, e: &Error) {
+        let waiting = self.waiting.drain(..).map(|(_, sender)| sender);
+        for sender in self
+            .pending
+            .drain()
+            .map(|(_, sender)| sender)
+            .chain(waiting)
+        {
+            let err = match e {
+                Error::ChannelEof { .. } => builder::ChannelEof.build(),
+                Error::Disconnected { .. } => builder::Disconnected.build(),
+                _ => builder::ChannelClosed.build(),
+            };
+            let _ = sender.send(Err(err));
+        }
+    }
+
+    async fn recv(channel: &mut BufferChannel, version: u32) -> Result<Packet> {
+        let data = channel.fill(4).await?;
+
+        let len = u32::from_be_bytes(data.try_into().unwrap());
+
+        let data = channel.fill(4 + len as usize).await?;
+
+        let res = Packet::parse(data, version).context(builder::InvalidArgument {
+            tip: "Unable to parse sftp packet",
+        });
+        channel.consume(4 + len as usize);
+        res
+    }
+
+    fn generate_request_id(&mut self) -> u32 {
+        self.request_id = self.request_id.wrapping_add(1);
+        self.request_id
+    }
+}
+
+pub struct File {
+    handle: Vec<u8>,
+    pos: u64,
+}
+
+impl File {
+    fn new(handle: Vec<u8>) -> Self {
+        Self { handle, pos: 0 }
+    }
+}
+
+pub struct Dir {
+    handle: Vec<u8>,
+}
+
+impl Dir {
+    fn new(handle: Vec<u8>) -> Self {
+        Self { handle }
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Permissions: u32 {
+        const OTHER_EXEC                        = 1 << 0;
+        const OTHER_WRITE                       = 1 << 1;
+        const OTHER_READ                        = 1 << 2;
+
+        const GROUP_EXEC                        = 1 << 0 << 3;
+        const GROUP_WRITE                       = 1 << 1 << 3;
+        const GROUP_READ                        = 1 << 2 << 3;
+
+        const OWNER_EXEC                        = 1 << 0 << 6;
+        const OWNER_WRITE                       = 1 << 1 << 6;
+        const OWNER_READ                        = 1 << 2 << 6;
+    }
+}
+
+impl Permissions {
+    const MASK: u32 = !FileType::MASK;
+    pub fn p0755() -> Self {
+        Self::from_bits_retain(0o755)
+    }
+}
+
+#[derive(new, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub atime: u32,
+    pub mtime: u32,
+}
+
+#[derive(new, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+#[num_enum(error_type(name = Error, constructor = Self::invalid_file_type))]
+#[repr(u32)]
+pub enum FileType {
+    Directory = 0o40000,
+    CharacterDevice = 0o20000,
+    BlockDevice = 0o60000,
+    RegularFile = 0o100000,
+    FIFO = 0o10000,
+    SymbolicLink = 0o120000,
+    Socket = 0o140000,
+}
+
+impl FileType {
+    const MASK: u32 = 0o170000;
+    fn invalid_file_type(value: u32) -> Error {
+        builder::Protocol {
+            tip: format!("Invalid SFtp file type({})", value),
+        }
+        .build()
+    }
+}
+
+impl FileType {
+    // the type byte from version 4 on
+    fn from_byte(value: u8) -> Option<Self> {
+        Some(match value {
+            SSH_FILEXFER_TYPE_REGULAR => Self::RegularFile,
+            SSH_FILEXFER_TYPE_DIRECTORY => Self::Directory,
+            SSH_FILEXFER_TYPE_SYMLINK => Self::SymbolicLink,
+            SSH_FILEXFER_TYPE_SOCKET => Self::Socket,
+            SSH_FILEXFER_TYPE_CHAR_DEVICE => Self::CharacterDevice,
+            SSH_FILEXFER_TYPE_BLOCK_DEVICE => Self::BlockDevice,
+            SSH_FILEXFER_TYPE_FIFO => Self::FIFO,
+            _ => return None,
+        })
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::RegularFile => SSH_FILEXFER_TYPE_REGULAR,
+            Self::Directory => SSH_FILEXFER_TYPE_DIRECTORY,
+            Self::SymbolicLink => SSH_FILEXFER_TYPE_SYMLINK,
+            Self::Socket => SSH_FILEXFER_TYPE_SOCKET,
+            Self::CharacterDevice => SSH_FILEXFER_TYPE_CHAR_DEVICE,
+            Self::BlockDevice => SSH_FILEXFER_TYPE_BLOCK_DEVICE,
+            Self::FIFO => SSH_FILEXFER_TYPE_FIFO,
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        matches!(self, Self::Directory)
+    }
+
+    pub fn is_character_device(&self) -> bool {
+        matches!(self, Self::CharacterDevice)
+    }
+
+    pub fn is_block_device(&self) -> bool {
+        matches!(self, Self::BlockDevice)
+    }
+
+    pub fn is_regular_file(&self) -> bool {
+        matches!(self, Self::RegularFile)
+    }
+
+    pub fn is_fifo(&self) -> bool {
+        matches!(self, Self::FIFO)
+    }
+
+    pub fn is_symbolic_link(&self) -> bool {
+        matches!(self, Self::SymbolicLink)
+    }
+
+    pub fn is_socket(&self) -> bool {
+        matches!(self, Self::Socket)
+    }
+}
+
+#[derive(new, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Property {
+    pub permissions: Permissions,
+    pub file_type: FileType,
+}
+
+impl TryFrom<u32> for Property {
+    type Error = Error;
+
+    fn try_from(value: u32) -> std::result::Result<Self, Self::Error> {
+        Ok(Self::new(
+            Permissions::from_bits_truncate(value & Permissions::MASK),
+            (value & FileType::MASK).try_into()?,
+        ))
+    }
+}
+
+impl Property {
+    pub fn bits(&self) -> u32 {
+        self.permissions.bits() | self.file_type as u32
+    }
+}
+
+/// Seconds since the epoch, version 4 on
+#[derive(new, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileTime {
+    pub seconds: i64,
+    pub nanoseconds: u32,
+}
+
+impl FileTime {
+    fn parse(buffer: &Buffer<Cell<&[u8]>>, subsecond: bool) -> Option<Self> {
+        let seconds = buffer.take_u64()? as i64;
+        let nanoseconds = match subsecond {
+            true => buffer.take_u32()?,
+            false => 0,
+        };
+        Some(Self::new(seconds, nanoseconds))
+    }
+}
+
+/// The owner and group by name, version 4 on replaces `User`
+#[derive(new, Debug, Clone, PartialEq, Eq)]
+pub struct OwnerGroup {
+    pub owner: String,
+    pub group: String,
+}
+
+/// An access control entry, laid out as in NFSv4
+#[derive(new, Debug, Clone, PartialEq, Eq)]
+pub struct Ace {
+    pub ace_type: u32,
+    pub flags: u32,
+    pub mask: u32,
+    pub who: String,
+}
+
+/// Attribute bits like hidden or read only, version 5 on
+#[derive(new, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttribBits {
+    pub bits: u32,
+    /// Which of `bits` the server knows, version 6 only
+    pub valid: u32,
+}
+
+/// The attributes of a file, fields the negotiated version has no room for are
+/// skipped when sending and `None` when received.
+///
+/// `user` and `time` are the version 3 fields, later versions send `owner_group` and
+/// the `*_time` fields instead. `time` is still filled in from them when received
+/// and sent when they are `None`
+#[derive(new, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes {
+    pub size: Option<u64>,
+    pub user: Option<User>,
+    pub property: Option<Property>,
+    // atime mtime
+    pub time: Option<Timestamp>,
+    // extended_count: Option<u32>,
+    pub extend: HashMap<String, Vec<u8>>,
+    /// From the permission bits on version 3, sent as a byte of its own from version 4
+    #[new(default)]
+    pub file_type: Option<FileType>,
+    #[new(default)]
+    pub owner_group: Option<OwnerGroup>,
+    #[new(default)]
+    pub access_time: Option<FileTime>,
+    #[new(default)]
+    pub create_time: Option<FileTime>,
+    #[new(default)]
+    pub modify_time: Option<FileTime>,
+    /// When the attributes changed, version 6 only
+    #[new(default)]
+    pub change_time: Option<FileTime>,
+    #[new(default)]
+    pub acl: Option<Vec<Ace>>,
+    #[new(default)]
+    pub bits: Option<AttribBits>,
+    /// Version 6 only
+    #[new(default)]
+    pub allocation_size: Option<u64>,
+    /// Version 6 only
+    #[new(default)]
+    pub text_hint: Option<u8>,
+    /// Version 6 only
+    #[new(default)]
+    pub mime_type: Option<String>,
+    /// Version 6 only
+    #[new(default)]
+    pub link_count: Option<u32>,
+    /// The name as the server stores it when it isn't valid utf8, version 6 only
+    #[new(default)]
+    pub untranslated_name: Option<Vec<u8>>,
+}
+
+impl Attributes {
+    fn to_buffer(&self, version: u32) -> Buffer<Vec<u8>> {
+        let mut buffer = Buffer::new();
+        self.to_bytes(&mut buffer, version);
+        buffer
+    }
+
+    fn to_bytes(&self, buffer: &mut Buffer<Vec<u8>>, version: u32) {
+        let mut flags = 0;
+        let mut tmp = Buffer::new();
+
+        if version >= 4 {
+            let file_type = self.file_type.or(self.property.map(|p| p.file_type));
+            tmp.put_u8(file_type.map_or(SSH_FILEXFER_TYPE_UNKNOWN, FileType::to_byte));
+        }
+
+        if let Some(size) = self.size {
+            flags |= SSH_FILEXFER_ATTR_SIZE;
+            tmp.put_u64(size);
+        }
+
+        match version {
+            ..=3 => {
+                if let Some(user) = self.user {
+                    flags |= SSH_FILEXFER_ATTR_UIDGID;
+                    tmp.put_u32(user.uid);
+                    tmp.put_u32(user.gid);
+                }
+
+                if let Some(permissions) = self.property {
+                    flags |= SSH_FILEXFER_ATTR_PERMISSIONS;
+                    tmp.put_u32(permissions.bits());
+                }
+
+                let time = self.time.or_else(|| {
+                    let seconds = |time: Option<FileTime>| time.map(|t| t.seconds as u32);
+                    let (atime, mtime) = (seconds(self.access_time), seconds(self.modify_time));
+                    (atime.is_some() || mtime.is_some()).then(|| {
+                        Timestamp::new(atime.unwrap_or_default(), mtime.unwrap_or_default())
+                    })
+                });
+                if let Some(time) = time {
+                    flags |= SSH_FILEXFER_ATTR_ACMODTIME;
+                    tmp.put_u32(time.atime);
+                    tmp.put_u32(time.mtime);
+                }
+            }
+            _ => self.to_bytes_v4(&mut flags, &mut tmp, version),
+        }
+
+        if !self.extend.is_empty() {
+            flags |= SSH_FILEXFER_ATTR_EXTENDED;
+            tmp.put_u32(self.extend.len() as u32);
+
+            for (k, v) in &self.extend {
+                tmp.put_one(k);
+                tmp.put_one(v);
+            }
+        }
+        buffer.put_u32(flags);
+        buffer.put_bytes(tmp);
+    }
+
+    // the fields between the size and the extended pairs from version 4 on
+    fn to_bytes_v4(&self, flags: &mut u32, tmp: &mut Buffer<Vec<u8>>, version: u32) {
+        if let Some(size) = self.allocation_size.filter(|_| version >= 6) {
+            *flags |= SSH_FILEXFER_ATTR_ALLOCATION_SIZE;
+            tmp.put_u64(size);
+        }
+
+        if let Some(OwnerGroup { owner, group }) = &self.owner_group {
+            *flags |= SSH_FILEXFER_ATTR_OWNERGROUP;
+            tmp.put_one(owner);
+            tmp.put_one(group);
+        }
+
+        if let Some(property) = self.property {
+            *flags |= SSH_FILEXFER_ATTR_PERMISSIONS;
+            tmp.put_u32(property.permissions.bits());
+        }
+
+        let time = |seconds: fn(&Timestamp) -> u32| {
+            self.time.map(|t| FileTime::new(seconds(&t).into(), 0))
+        };
+        let times = [
+            (
+                SSH_FILEXFER_ATTR_ACCESSTIME,
+                self.access_time.or(time(|t| t.atime)),
+            ),
+            (SSH_FILEXFER_ATTR_CREATETIME, self.create_time),
+            (
+                SSH_FILEXFER_ATTR_MODIFYTIME,
+                self.modify_time.or(time(|t| t.mtime)),
+            ),
+            (
+                SSH_FILEXFER_ATTR_CTIME,
+                self.change_time.filter(|_| version >= 6),
+            ),
+        ];
+        let subsecond = times
+            .iter()
+            .any(|(_, time)| time.is_some_and(|t| t.nanoseconds != 0));
+        if subsecond {
+            *flags |= SSH_FILEXFER_ATTR_SUBSECOND_TIMES;
+        }
+        for (flag, time) in times {
+            if let Some(time) = time {
+                *flags |= flag;
+                tmp.put_u64(time.seconds as u64);
+                if subsecond {
+                    tmp.put_u32(time.nanoseconds);
+                }
+            }
+        }
+
+        if let Some(acl) = &self.acl {
+            *flags |= SSH_FILEXFER_ATTR_ACL;
+            let mut aces = Buffer::new();
+            if version >= 6 {
+                // acl-flags
+                aces.put_u32(0);
+            }
+            aces.put_u32(acl.len() as u32);
+            for ace in acl {
+                aces.put_u32(ace.ace_type);
+                aces.put_u32(ace.flags);
+                aces.put_u32(ace.mask);
+                aces.put_one(&ace.who);
+            }
+            tmp.put_one(aces);
+        }
+
+        if let Some(bits) = self.bits.filter(|_| version >= 5) {
+            *flags |= SSH_FILEXFER_ATTR_BITS;
+            tmp.put_u32(bits.bits);
+            if version >= 6 {
+                tmp.put_u32(bits.valid);
+            }
+        }
+
+        if version < 6 {
+            return;
+        }
+
+        if let Some(hint) = self.text_hint {
+            *flags |= SSH_FILEXFER_ATTR_TEXT_HINT;
+            tmp.put_u8(hint);
+        }
+
+        if let Some(mime_type) = &self.mime_type {
+            *flags |= SSH_FILEXFER_ATTR_MIME_TYPE;
+            tmp.put_one(mime_type);
+        }
+
+        if let Some(count) = self.link_count {
+            *flags |= SSH_FILEXFER_ATTR_LINK_COUNT;
+            tmp.put_u32(count);
+        }
+
+        if let Some(name) = &self.untranslated_name {
+            *flags |= SSH_FILEXFER_ATTR_UNTRANSLATED_NAME;
+            tmp.put_one(name);
+        }
+    }
+
+    fn parse(buffer: &Buffer<Cell<&[u8]>>, version: u32) -> Option<Self> {
+        let flags = buffer.take_u32()?;
+
+        let mut attrs = Self::default();
+
+        if version >= 4 {
+            attrs.file_type = FileType::from_byte(buffer.take_u8()?);
+        }
+
+        if flags & SSH_FILEXFER_ATTR_SIZE != 0 {
+            attrs.size = Some(buffer.take_u64()?)
+        }
+
+        if version < 4 {
+            if flags & SSH_FILEXFER_ATTR_UIDGID != 0 {
+                let uid = buffer.take_u32()?;
+                let gid = buffer.take_u32()?;
+                attrs.user = Some(User::new(uid, gid))
+            }
+
+            if flags & SSH_FILEXFER_ATTR_PERMISSIONS != 0 {
+                let per = buffer.take_u32()?;
+                attrs.property = match per & FileType::MASK {
+                    // requests leave out the type, `file_type` stays `None` then
+                    0 => Some(Property::new(
+                        Permissions::from_bits_truncate(per),
+                        FileType::RegularFile,
+                    )),
+                    _ => {
+                        let property = Property::try_from(per).ok();
+                        attrs.file_type = property.map(|p| p.file_type);
+                        property
+                    }
+                };
+            }
+
+            if flags & SSH_FILEXFER_ATTR_ACMODTIME != 0 {
+                let atime = buffer.take_u32()?;
+                let mtime = buffer.take_u32()?;
+
+                attrs.time = Some(Timestamp::new(atime, mtime))
+            }
+        } else {
+            attrs.parse_v4(buffer, flags, version)?;
+        }
+
+        if flags & SSH_FILEXFER_ATTR_EXTENDED != 0 {
+            let ecount = buffer.take_u32()?;
+
+            for _ in 0..ecount {
+                let (_, key) = buffer.take_one()?;
+                let (_, value) = buffer.take_one()?;
+
+                attrs
+                    .extend
+                    .insert(std::str::from_utf8(key).ok()?.to_string(), value.to_vec());
+            }
+        }
+
+        Some(attrs)
+    }
+
+    fn parse_v4(&mut self, buffer: &Buffer<Cell<&[u8]>>, flags: u32, version: u32) -> Option<()> {
+        let string = |buffer: &Buffer<Cell<&[u8]>>| {
+            let (_, value) = buffer.take_one()?;
+            std::str::from_utf8(value).ok().map(String::from)
+        };
+
+        if version >= 6 && flags & SSH_FILEXFER_ATTR_ALLOCATION_SIZE != 0 {
+            self.allocation_size = Some(buffer.take_u64()?);
+        }
+
+        if flags & SSH_FILEXFER_ATTR_OWNERGROUP != 0 {
+            let owner = string(buffer)?;
+            let group = string(buffer)?;
+            self.owner_group = Some(OwnerGroup::new(owner, group));
+        }
+
+        if flags & SSH_FILEXFER_ATTR_PERMISSIONS != 0 {
+            let per = buffer.take_u32()?;
+            let permissions = Permissions::from_bits_truncate(per & Permissions::MASK);
+            self.property = self.file_type.map(|t| Property::new(permissions, t));
+        }
+
+        let subsecond = flags & SSH_FILEXFER_ATTR_SUBSECOND_TIMES != 0;
+        let time = |flag| match flags & flag != 0 {
+            true => FileTime::parse(buffer, subsecond).map(Some),
+            false => Some(None),
+        };
+        self.access_time = time(SSH_FILEXFER_ATTR_ACCESSTIME)?;
+        self.create_time = time(SSH_FILEXFER_ATTR_CREATETIME)?;
+        self.modify_time = time(SSH_FILEXFER_ATTR_MODIFYTIME)?;
+        if version >= 6 {
+            self.change_time = time(SSH_FILEXFER_ATTR_CTIME)?;
+        }
+        if self.access_time.is_some() || self.modify_time.is_some() {
+            let seconds = |time: Option<FileTime>| time.map_or(0, |t| t.seconds as u32);
+            self.time = Some(Timestamp::new(
+                seconds(self.access_time),
+                seconds(self.modify_time),
+            ));
+        }
+
+        if flags & SSH_FILEXFER_ATTR_ACL != 0 {
+            let (_, acl) = buffer.take_one()?;
+            let acl = Buffer::from_slice(acl);
+            if version >= 6 {
+                let _acl_flags = acl.take_u32()?;
+            }
+            let count = acl.take_u32()?;
+            let mut aces = Vec::with_capacity(count.min(64) as usize);
+            for _ in 0..count {
+                let ace_type = acl.take_u32()?;
+                let ace_flags = acl.take_u32()?;
+                let mask = acl.take_u32()?;
+                aces.push(Ace::new(ace_type, ace_flags, mask, string(&acl)?));
+            }
+            self.acl = Some(aces);
+        }
+
+        if version >= 5 && flags & SSH_FILEXFER_ATTR_BITS != 0 {
+            let bits = buffer.take_u32()?;
+            let valid = match version {
+                5 => u32::MAX,
+                _ => buffer.take_u32()?,
+            };
+            self.bits = Some(AttribBits::new(bits, valid));
+        }
+
+        if version < 6 {
+            return Some(());
+        }
+
+        if flags & SSH_FILEXFER_ATTR_TEXT_HINT != 0 {
+            self.text_hint = Some(buffer.take_u8()?);
+        }
+
+        if flags & SSH_FILEXFER_ATTR_MIME_TYPE != 0 {
+            self.mime_type = Some(string(buffer)?);
+        }
+
+        if flags & SSH_FILEXFER_ATTR_LINK_COUNT != 0 {
+            self.link_count = Some(buffer.take_u32()?);
+        }
+
+        if flags & SSH_FILEXFER_ATTR_UNTRANSLATED_NAME != 0 {
+            self.untranslated_name = Some(buffer.take_one()?.1.to_vec());
+        }
+
+        Some(())
+    }
+}
+
+#[derive(new, Debug, Clone)]
+pub struct FileInfo {
+    pub filename: String,
+    /// Empty from version 4 on
+    pub longname: String,
+    pub attrs: Attributes,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_packet_len: u64,
+    pub max_read_len: u64,
+    pub max_write_len: u64,
+    pub max_open_handles: u64,
+}
+
+impl Limits {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let buffer = Buffer::from_slice(data);
+
+        Some(Self {
+            max_packet_len: buffer.take_u64()?,
+            max_read_len: buffer.take_u64()?,
+            max_write_len: buffer.take_u64()?,
+            max_open_handles: buffer.take_u64()?,
+        })
+    }
+    fn to_bytes(self, buffer: &mut Buffer<Vec<u8>>) {
+        buffer.put_u64(self.max_packet_len);
+        buffer.put_u64(self.max_read_len);
+        buffer.put_u64(self.max_write_len);
+        buffer.put_u64(self.max_open_handles);
+    }
+}
+
+#[derive(custom_debug_derive::Debug)]
+struct Packet {
+    id: u32,
+    msg: Message,
+}
+
+impl Packet {
+    fn parse(data: &[u8], version: u32) -> Option<Packet> {
+        let data = Buffer::from_slice(data);
+        let (_, data) = data.take_one()?;
+
+        let data = Buffer::from_slice(data);
+
+        let code = data.take_u8()?;
+        let id = data.take_u32()?;
+
+        let msg = match code {
+            SSH_FXP_HANDLE => {
+                let (_, handle) = data.take_one()?;
+
+                Message::FileHandle(handle.to_vec())
+            }
+            SSH_FXP_STATUS => {
+                let status = data.take_u32()?;
+
+                let (_, msg) = data.take_one()?;
+
+                let (_, tag) = data.take_one()?;
+
+                let msg = std::str::from_utf8(msg).ok()?.to_string();
+
+                let _tag = std::str::from_utf8(tag).ok()?.to_string();
+
+                let status = Status::from_status(status);
+                Message::Status { status, msg, _tag }
+            }
+            SSH_FXP_DATA => Message::Data(data.take_one()?.1.to_vec()),
+            SSH_FXP_NAME => {
+                let count = data.take_u32()?;
+                let mut res = Vec::with_capacity(count as usize);
+
+                for _ in 0..count {
+                    let (_, filename) = data.take_one()?;
+
+                    let filename = std::str::from_utf8(filename).ok()?.to_string();
+
+                    // there is no longname from version 4 on
+                    let longname = match version {
+                        ..=3 => std::str::from_utf8(data.take_one()?.1).ok()?.to_string(),
+                        _ => String::new(),
+                    };
+
+                    res.push(FileInfo::new(
+                        filename,
+                        longname,
+                        Attributes::parse(&data, version)?,
+                    ));
+                }
+                Message::Name(res)
+            }
+            SSH_FXP_ATTRS => Message::Attributes(Box::new(Attributes::parse(&data, version)?)),
+            SSH_FXP_EXTENDED_REPLY => Message::ExtendReply(data.to_vec()),
+            _ => return None,
+        };
+
+        Some(Packet { id, msg })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(u32)]
+enum Status {
+    OK = SSH_FX_OK,
+    Eof = SSH_FX_EOF,
+    NoSuchFile = SSH_FX_NO_SUCH_FILE,
+    PermissionDenied = SSH_FX_PERMISSION_DENIED,
+    Failure = SSH_FX_FAILURE,
+    BadMessage = SSH_FX_BAD_MESSAGE,
+    NoConnection = SSH_FX_NO_CONNECTION,
+    ConnectionLost = SSH_FX_CONNECTION_LOST,
+    OpUnsupported = SSH_FX_OP_UNSUPPORTED,
+    // the codes added from version 4 on, e.g. SSH_FX_FILE_ALREADY_EXISTS
+    Other(u32),
+}
+
+impl Status {
+    fn from_status(code: u32) -> Self {
+        match code {
+            SSH_FX_OK => Self::OK,
+            SSH_FX_EOF => Self::Eof,
+            SSH_FX_NO_SUCH_FILE | SSH_FX_NO_SUCH_PATH => Self::NoSuchFile,
+            SSH_FX_PERMISSION_DENIED => Self::PermissionDenied,
+            SSH_FX_FAILURE => Self::Failure,
+            SSH_FX_BAD_MESSAGE => Self::BadMessage,
+            SSH_FX_NO_CONNECTION => Self::NoConnection,
+            SSH_FX_CONNECTION_LOST => Self::ConnectionLost,
+            SSH_FX_OP_UNSUPPORTED => Self::OpUnsupported,
+            code => Self::Other(code),
+        }
+    }
+
+    fn to_result<T: Default>(&self, msg: String) -> Result<T> {
+        match self {
+            Status::OK => Ok(Default::default()),
+            Status::Eof => Ok(Default::default()),
+            _ => Err(self.to_error(msg)),
+        }
+    }
+
+    fn no_ok_and_eof<T>(&self, msg: String) -> Result<T> {
+        match self {
+            Status::OK => builder::Protocol {
+                tip: "Unexpected Ok status received",
+            }
+            .fail(),
+            Status::Eof => builder::Protocol {
+                tip: "Unexpected EOF status received",
+            }
+            .fail(),
+            _ => Err(self.to_error(msg)),
+        }
+    }
+
+    fn no_eof<T: Default>(&self, msg: String) -> Result<T> {
+        match self {
+            Status::OK => Ok(Default::default()),
+            Status::Eof => builder::Protocol {
+                tip: "Unexpected EOF status received",
+            }
+            .fail(),
+            _ => Err(self.to_error(msg)),
+        }
+    }
+
+    fn no_ok<T: Default>(&self, msg: String) -> Result<T> {
+        match self {
+            Status::OK => builder::Protocol {
+                tip: "Unexpected Ok status received",
+            }
+            .fail(),
+            Status::Eof => Ok(Default::default()),
+            _ => Err(self.to_error(msg)),
+        }
+    }
+
+    // the error for every status but OK and EOF
+    fn to_error(&self, msg: String) -> Error {
+        match self {
+            Status::OK | Status::Eof => Error::ub("Not an error status"),
+            Status::NoSuchFile => builder::NoSuchFile { tip: msg }.build(),
+            Status::PermissionDenied => builder::PermissionDenied { tip: msg }.build(),
+            Status::Failure => builder::SFtpFailure { tip: msg }.build(),
+            Status::BadMessage => builder::BadMessage { tip: msg }.build(),
+            Status::NoConnection => builder::NoConnection { tip: msg }.build(),
+            Status::ConnectionLost => builder::NoConnection { tip: msg }.build(),
+            Status::OpUnsupported => builder::OpUnsupported { tip: msg }.build(),
+            Status::Other(code) => builder::SFtpStatus {
+                code: *code,
+                tip: msg,
+            }
+            .build(),
+        }
+    }
+}
+
+#[derive(custom_debug_derive::Debug)]
+enum Message {
+    FileHandle(Vec<u8>),
+    Status {
+        status: Status,
+        msg: String,
+        _tag: String,
+    },
+    Data(#[debug(skip)] Vec<u8>),
+    Name(Vec<FileInfo>),
+    Attributes(Box<Attributes>),
+    ExtendReply(#[debug(skip)] Vec<u8>),
+}
+
+// struct Stream<'a> {
+//     sftp: &'a mut SFtp,
+// }
+
+impl SFtp {
+    const MAX_SFTP_PACKET: usize = 32000;
+    /// The request id is filled in by the dispatcher
+    const UNASSIGNED_ID: u32 = 0;
+
+    pub fn extend(&self, key: &str) -> Option<&[u8]> {
+        self.ext.get(key).map(|v| v.as_ref())
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub async fn from_channel(channel: Channel) -> Result<Self> {
+        let (sender, recver) = o_channel();
+
+        let session = channel.session()?;
+        let request = Request::SFtpFromChannel { channel, sender };
+
+        session
+            .send(request)
+            .map_err(|_| builder::Disconnected.build())?;
+
+        recver.await?
+    }
+
+    /// Close the channel, it's closed for every clone of the handle
+    pub async fn close(self) -> Result<()> {
+        let (sender, recver) = o_channel();
+        self.sender
+            .send(Command::Close { sender })
+            .map_err(|_| builder::ChannelClosed.build())?;
+        recver.await?
+    }
+
+    // pub async fn flush(&self) -> Result<()> {
+    //     self.channel.flush().await?;
+    //     Ok(())
+    // }
+
+    // pub async fn flush(&self) -> Result<()> {
+    //     self.channel.inner().inner().flush().await
+    //}
+
+    pub fn support_posix_rename(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_POSIX_RENAME)
+    }
+
+    pub async fn posix_rename(&self, oldpath: &str, newpath: &str) -> Result<()> {
+        debug_assert!(
+            self.support_posix_rename(),
+            "Server doesn't support posix rename"
+        );
+        // let mut buffer = Buffer::new();
+
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_POSIX_RENAME.0);
+        // buffer.put_one(oldpath);
+        // buffer.put_one(newpath);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_POSIX_RENAME.0,
+            one: oldpath,
+            one: newpath,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub fn support_fstatvfs(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_FSTATVFS)
+    }
+
+    pub async fn fstatvfs(&self, file: &File) -> Result<Statvfs> {
+        debug_assert!(self.support_fstatvfs(), "Server doesn't support fstatvfs");
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_FSTATVFS.0);
+        // buffer.put_one(&file.handle);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_FSTATVFS.0,
+            one: &file.handle,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, _tag } => status.no_ok_and_eof(msg),
+            Message::ExtendReply(data) => Statvfs::parse(&data).context(builder::Protocol {
+                tip: "Invalid Statvfs Message",
+            }),
+            _ => builder::Protocol {
+                tip: "Unexpected SFtp Message",
+            }
+            .fail(), // _ => Err(Error::ProtocolError("Unexpected SFtp Message".to_string())),
+        }
+    }
+
+    pub fn support_statvfs(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_STATVFS)
+    }
+
+    pub async fn statvfs(&self, path: &str) -> Result<Statvfs> {
+        debug_assert!(self.support_fstatvfs(), "Server doesn't support statvfs");
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_STATVFS.0);
+        // buffer.put_one(path);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_STATVFS.0,
+            one: path,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, _tag } => status.no_ok_and_eof(msg),
+            Message::ExtendReply(data) => Statvfs::parse(&data).context(builder::Protocol {
+                tip: "Invalid Statvfs Message",
+            }),
+            _ => builder::Protocol {
+                tip: "Unexpected SFtp Message",
+            }
+            .fail(), // _ => Err(Error::ProtocolError("Unexpected SFtp Message".to_string())),
+        }
+    }
+
+    pub fn support_hardlink(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_HARDLINK)
+    }
+
+    pub async fn hardlink(&self, oldpath: &str, newpath: &str) -> Result<()> {
+        debug_assert!(self.support_hardlink(), "Server doesn't support hardlink");
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_HARDLINK.0);
+        // buffer.put_one(oldpath);
+        // buffer.put_one(newpath);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_HARDLINK.0,
+            one: oldpath,
+            one: newpath,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub fn support_fsync(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_FSYNC)
+    }
+
+    pub async fn fsync(&self, file: &File) -> Result<()> {
+        debug_assert!(self.support_fsync(), "Server doesn't support fsync");
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_FSYNC.0);
+        // buffer.put_one(&file.handle);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_FSYNC.0,
+            one: &file.handle,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub fn support_lsetstat(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_LSETSTAT)
+    }
+
+    pub async fn lsetstat(&self, path: &str, attrs: &Attributes) -> Result<()> {
+        debug_assert!(self.support_lsetstat(), "Server doesn't lsetstat");
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_LSETSTAT.0);
+        // buffer.put_one(path);
+        // attrs.to_bytes(&mut buffer);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let attrs = attrs.to_buffer(self.version);
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_LSETSTAT.0,
+            one: path,
+            bytes: attrs,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub fn support_limits(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_LIMITS)
+    }
+
+    pub async fn limits(&self) -> Result<Limits> {
+        debug_assert!(self.support_limits(), "Server doesn't support limits");
+        // let mut buffer = Buffer::new();
+        // buffer.put_one(OPENSSH_SFTP_EXT_LIMITS.0);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_LIMITS.0
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::ExtendReply(data) => Limits::parse(&data).context(builder::Protocol {
+                tip: "Invalid packet format",
+            }),
+            _ => builder::Protocol {
+                tip: "Unexpected SFtp Message",
+            }
+            .fail(),
+        }
+    }
+
+    pub fn support_expand_path(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_EXPAND_PATH)
+    }
+
+    pub async fn expand_path(&self, path: &str) -> Result<String> {
+        debug_assert!(
+            self.support_expand_path(),
+            "Server doesn't support expand path"
+        );
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_EXPAND_PATH.0);
+        // buffer.put_one(path);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_EXPAND_PATH.0,
+            one: path
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+            Message::Name(infos) if infos.len() == 1 => Ok(infos[0].filename.clone()),
+            // _ => Err(Error::ProtocolError("Unknown msg".to_string())),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(),
+        }
+    }
+
+    pub fn support_copy_data(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_COPY_DATA)
+    }
+
+    pub async fn copy_data(&self, read: &mut File, len: u64, write: &mut File) -> Result<()> {
+        debug_assert!(self.support_copy_data(), "Server doesn't support copy data");
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_COPY_DATA.0);
+        // buffer.put_one(&read.handle);
+        // buffer.put_u64(read.pos);
+        // buffer.put_u64(len);
+        // buffer.put_one(&write.handle);
+        // buffer.put_u64(write.pos);
+
+        // self.send(SSH_FXP_EXTENDED, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_COPY_DATA.0,
+            one: &read.handle,
+            u64: read.pos,
+            u64: len,
+            one: &write.handle,
+            u64: write.pos,
+        };
+
+        let status = self.request_status(buffer, Status::to_result).await;
+
+        if status.is_ok() {
+            read.pos += len;
+            write.pos += len;
+        }
+
+        status
+    }
+
+    pub fn support_home_directory(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_HOME_DIRECTORY)
+    }
+
+    pub async fn home_directory(&self, username: &str) -> Result<String> {
+        debug_assert!(
+            self.support_home_directory(),
+            "Server doesn't support home directory"
+        );
+
+        // cap: 4 + 1 + 4 + 4 + xx.len() + 4 + username.len()
+        // let mut buffer = Buffer::with_capacity(
+        //     4 + 1 + 4 + 4 + OPENSSH_SFTP_EXT_HOME_DIRECTORY.0.len() + 4 + username.len(),
+        // );
+        // buffer.put_u32(
+        //     (4 + 1 + 4 + 4 + OPENSSH_SFTP_EXT_HOME_DIRECTORY.0.len() + 4 + username.len()) as u32,
+        // );
+        // buffer.put_u8(SSH_FXP_EXTENDED);
+        // buffer.put_u32(request_id);
+        // buffer.put_one(OPENSSH_SFTP_EXT_HOME_DIRECTORY.0);
+        // buffer.put_one(username);
+
+        // self.write(buffer).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: OPENSSH_SFTP_EXT_HOME_DIRECTORY.0,
+            one: username
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, _tag } => status.no_ok_and_eof(msg),
+            Message::Name(infos) if infos.len() == 1 => Ok(infos[0].filename.clone()),
+            // _ => Err(Error::ProtocolError("Unexpected message".to_string())),
+            _ => builder::Protocol {
+                tip: "Unexpected message",
+            }
+            .fail(),
+        }
+    }
+
+    pub fn support_users_groups_by_id(&self) -> bool {
+        self.support(OPENSSH_SFTP_EXT_USERS_GROUPS_BY_ID)
+    }
+
+    pub async fn users_groups_by_id(
+        &self,
+        users: &[u32],
+        groups: &[u32],
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let cap = 4 // buffer len
+            + 1 // ssh code
+            + 4 // request id
+            + 4 // extend string len
+            + OPENSSH_SFTP_EXT_USERS_GROUPS_BY_ID.0.len() // extend string content
+            + 4 // users id block len
+            + users.len() * 4 // users id
+            + 4 // groups id block len
+            + groups.len() * 4; // groups id
+        let mut buffer = Buffer::with_capacity(cap);
+        buffer.put_u32((cap - 4) as u32);
+        buffer.put_u8(SSH_FXP_EXTENDED);
+        buffer.put_u32(Self::UNASSIGNED_ID);
+        buffer.put_one(OPENSSH_SFTP_EXT_USERS_GROUPS_BY_ID.0);
+
+        buffer.put_u32((users.len() * 4) as u32);
+
+        users.iter().for_each(|v| {
+            buffer.put_u32(*v);
+        });
+
+        buffer.put_u32((groups.len() * 4) as u32);
+
+        groups.iter().for_each(|v| {
+            buffer.put_u32(*v);
+        });
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::ExtendReply(data) => {
+                let buffer = Buffer::from_slice(&data);
+                let usernames = buffer
+                    .take_one()
+                    .context(builder::Protocol {
+                        tip: "Invalid sftp packet format",
+                    })?
+                    .1;
+                let usernames = Buffer::from_slice(usernames);
+
+                let groupnames = buffer
+                    .take_one()
+                    .context(builder::Protocol {
+                        tip: "Invalid sftp packet format",
+                    })?
+                    .1;
+                let groupnames = Buffer::from_slice(groupnames);
+
+                let mut unames = vec![];
+                while let Some(user) = usernames.take_one() {
+                    // unames.push(String::from_utf8(user.1).map_err(|e| e.utf8_error())?);
+                    unames.push(std::str::from_utf8(user.1)?.to_string())
+                }
+
+                let mut gnames = vec![];
+
+                while let Some(group) = groupnames.take_one() {
+                    // gnames.push(String::from_utf8(group.1).map_err(|e| e.utf8_error())?)
+                    gnames.push(std::str::from_utf8(group.1)?.to_string());
+                }
+
+                Ok((unames, gnames))
+            }
+            _ => builder::Protocol {
+                tip: "Unexpected message",
+            }
+            .fail(), //Err(Error::ProtocolError("Unexpected message".to_string())),
+        }
+    }
+
+    /// The value is the hash algorithms the server knows, whatever the version
+    pub fn support_check_file(&self) -> bool {
+        self.ext.contains_key(SFTP_EXT_CHECK_FILE)
+    }
+
+    /// Hash `len` bytes of the file from `offset` on the server, 0 means to the end.
+    /// `algorithms` is a comma separated list in order of preference, e.g. `sha256,md5`,
+    /// returns the one the server picked and the hash
+    pub async fn check_file(
+        &self,
+        file: &File,
+        algorithms: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<(String, Vec<u8>)> {
+        debug_assert!(
+            self.support_check_file(),
+            "Server doesn't support check file"
+        );
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_EXTENDED,
+            u32: Self::UNASSIGNED_ID,
+            one: SFTP_EXT_CHECK_FILE_HANDLE,
+            one: &file.handle,
+            one: algorithms,
+            u64: offset,
+            u64: len,
+            // a single hash of the whole range
+            u32: 0,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+            Message::ExtendReply(data) => {
+                let invalid = || {
+                    builder::Protocol {
+                        tip: "Invalid check file reply",
+                    }
+                    .build()
+                };
+                let buffer = Buffer::from_slice(&data);
+                let (_, name) = buffer.take_one().ok_or_else(invalid)?;
+                if name != SFTP_EXT_CHECK_FILE.as_bytes() {
+                    return Err(invalid());
+                }
+                let algorithm = std::str::from_utf8(buffer.take_one().ok_or_else(invalid)?.1)?;
+                let hash = buffer.take_bytes(buffer.len()).ok_or_else(invalid)?;
+
+                Ok((algorithm.to_string(), hash.to_vec()))
+            }
+            _ => builder::Protocol {
+                tip: "Unexpected message",
+            }
+            .fail(),
+        }
+    }
+
+    fn support(&self, (e, v): (&str, &[u8])) -> bool {
+        self.ext.get(e).map(|v| v.as_ref()) == Some(v)
+    }
+
+    // pub async fn close(mut self) -> Result<()> {
+    //     self.closed = true;
+    //     let (sender, recver) = async_channel::bounded(1);
+    //     self.session
+    //         .send(Request::ChannelDrop {
+    //             id: self.id,
+    //             sender: Some(sender),
+    //         })
+    //         .await
+    //         .map_err(|_| Error::Disconnect)?;
+    //     recver.recv().await.map_err(|_| Error::Disconnect)?
+    // }
+
+    pub fn seek_file(&self, file: &mut File, pos: u64) {
+        file.pos = pos;
+    }
+
+    pub async fn close_file(&self, file: File) -> Result<()> {
+        // let mut buffer = Buffer::new();
+
+        // buffer.put_u32(request_id);
+        // buffer.put_one(file.handle);
+
+        // self.send(SSH_FXP_CLOSE, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_CLOSE,
+            u32: Self::UNASSIGNED_ID,
+            one: file.handle,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn read_file_buf(&self, file: &mut File, max: u32) -> Result<Vec<Vec<u8>>> {
+        let base = 255 * 1024;
+
+        let mut times = max / base;
+        if times == 0 {
+            times = 1;
+        }
+
+        let mut replies = Vec::with_capacity(times as usize);
+
+        let mut datas = Vec::with_capacity(times as usize);
+
+        let mut pos = file.pos;
+        for _ in 0..times {
+            let buffer = make_buffer! {
+                u8: SSH_FXP_READ,
+                u32: Self::UNASSIGNED_ID,
+                one: &file.handle,
+                u64: pos,
+                u32: base
+            };
+
+            replies.push(self.send(buffer)?);
+
+            pos += base as u64;
+        }
+
+        for reply in replies {
+            let packet = Self::wait_for_packet(reply).await?;
+
+            match packet.msg {
+                Message::Data(data) => {
+                    file.pos += data.len() as u64;
+                    datas.push(data);
+                }
+                Message::Status {
+                    status: Status::Eof,
+                    ..
+                } => {
+                    datas.push(vec![]);
+                }
+                Message::Status { status, msg, .. } => return status.no_ok(msg),
+                _ => return builder::Protocol { tip: "Unknown msg" }.fail(), // return Err(Error::ProtocolError("Unknown msg".to_string())),
+            }
+        }
+
+        Ok(datas)
+    }
+
+    pub async fn read_file(&self, file: &mut File, max: u32) -> Result<Vec<u8>> {
+        let buffer = make_buffer! {
+            u8: SSH_FXP_READ,
+            u32: Self::UNASSIGNED_ID,
+            one: &file.handle,
+            u64: file.pos,
+            u32: max
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Data(data) => {
+                file.pos += data.len() as u64;
+                Ok(data)
+            }
+            Message::Status { status, msg, .. } => status.no_ok(msg),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    pub async fn write_file_buf(&self, file: &mut File, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let max = Self::MAX_SFTP_PACKET;
+        let mut replies = vec![];
+        for i in (0..data.len()).step_by(max) {
+            let left = data.len() - i;
+
+            let min = min(left, max);
+
+            let buffer = make_buffer! {
+                u8: SSH_FXP_WRITE,
+                u32: Self::UNASSIGNED_ID,
+                one: &file.handle,
+                u64: file.pos,
+                one: &data[i..i + min],
+            };
+
+            replies.push(self.send(buffer)?);
+            file.pos += min as u64;
+        }
+
+        for reply in replies {
+            // suppress warning in edition 2021, maybe error in edition 2024
+            let _: () = Self::wait_for_status(reply, Status::no_eof).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn write_file(&self, file: &mut File, data: &[u8]) -> Result<()> {
+        let max = Self::MAX_SFTP_PACKET;
+        for i in (0..data.len()).step_by(max) {
+            let left = data.len() - i;
+
+            let min = min(left, max);
+
+            self.write_file_unchecked(file, &data[i..i + min]).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_file_unchecked(&self, file: &mut File, data: &[u8]) -> Result<()> {
+        // ssh最大数据包检查
+        // cap: 4 + 1 + 4 + file.handle.len() + 8 + 4 + data.len()
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_WRITE,
+            u32: Self::UNASSIGNED_ID,
+            one: &file.handle,
+            u64: file.pos,
+            one: data
+        };
+
+        let res = self.request_status(buffer, Status::no_eof).await;
+        if res.is_ok() {
+            file.pos += data.len() as u64;
+        }
+        res
+    }
+
+    async fn request_status<T, B>(&self, packet: Buffer<Vec<u8>>, f: T) -> Result<B>
+    where
+        T: FnOnce(&Status, String) -> Result<B>,
+    {
+        Self::wait_for_status(self.send(packet)?, f).await
+    }
+
+    async fn wait_for_status<T, B>(reply: OReceiver<Result<Packet>>, f: T) -> Result<B>
+    where
+        T: FnOnce(&Status, String) -> Result<B>,
+    {
+        let packet = Self::wait_for_packet(reply).await?;
+
+        match packet.msg {
+            // Message::Status { status, .. } if status == Status::OK => Ok(()),
+            Message::Status { status, msg, .. } => f(&status, msg),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+                                                                  // _ => Err(Error::ProtocolError("Unknown msg received".to_string())),
+        }
+    }
+
+    pub async fn mkdir(&self, path: &str, permissions: Permissions) -> Result<()> {
+        let attrs = self.mode_attrs(SSH_FILEXFER_TYPE_DIRECTORY, Some(permissions));
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_MKDIR,
+            u32: Self::UNASSIGNED_ID,
+            one: path,
+            bytes: attrs,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn rmdir(&self, path: &str) -> Result<()> {
+        let buffer = make_buffer! {
+            u8: SSH_FXP_RMDIR,
+            u32: Self::UNASSIGNED_ID,
+            one: path,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn open_dir(&self, path: &str) -> Result<Dir> {
+        let buffer = make_buffer! {
+            u8: SSH_FXP_OPENDIR,
+            u32: Self::UNASSIGNED_ID,
+            one: path,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::FileHandle(handle) => Ok(Dir::new(handle)),
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    pub async fn close_dir(&self, dir: Dir) -> Result<()> {
+        // let mut buffer = Buffer::new();
+
+        // buffer.put_u32(request_id);
+        // buffer.put_one(dir.handle);
+
+        // self.send(SSH_FXP_CLOSE, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_CLOSE,
+            u32: Self::UNASSIGNED_ID,
+            one: dir.handle,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn read_dir(&self, dir: &Dir) -> Result<Vec<FileInfo>> {
+        let buffer = make_buffer! {
+            u8: SSH_FXP_READDIR,
+            u32: Self::UNASSIGNED_ID,
+            one: &dir.handle,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, .. } => status.no_ok(msg),
+            Message::Name(infos) => Ok(infos),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+                                                                  // _ => Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<Attributes> {
+        let flags = self.attr_flags();
+        let buffer = make_buffer! {
+            u8: SSH_FXP_STAT,
+            u32: Self::UNASSIGNED_ID,
+            one: path,
+            bytes: flags,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+            Message::Attributes(attrs) => Ok(*attrs),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+                                                                  // _ => Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    pub async fn lstat(&self, path: &str) -> Result<Attributes> {
+        let flags = self.attr_flags();
+        let buffer = make_buffer! {
+            u8: SSH_FXP_LSTAT,
+            u32: Self::UNASSIGNED_ID,
+            one: path,
+            bytes: flags,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+            Message::Attributes(attrs) => Ok(*attrs),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+                                                                  // _ => Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    pub async fn fstat(&self, file: &File) -> Result<Attributes> {
+        let flags = self.attr_flags();
+        let buffer = make_buffer! {
+            u8: SSH_FXP_FSTAT,
+            u32: Self::UNASSIGNED_ID,
+            one: &file.handle,
+            bytes: flags,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+            Message::Attributes(attrs) => Ok(*attrs),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+                                                                  // _ => Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    pub async fn setstat(&self, path: &str, attrs: &Attributes) -> Result<()> {
+        let attrs = attrs.to_buffer(self.version);
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(path);
+
+        // attrs.to_bytes(&mut buffer);
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_SETSTAT,
+            u32: Self::UNASSIGNED_ID,
+            one: path,
+            bytes: attrs,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn setfstat(&self, file: &File, attrs: &Attributes) -> Result<()> {
+        let attrs = attrs.to_buffer(self.version);
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(&file.handle);
+
+        // attrs.to_bytes(&mut buffer);
+
+        // self.send(SSH_FXP_FSETSTAT, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_FSETSTAT,
+            u32: Self::UNASSIGNED_ID,
+            one: &file.handle,
+            bytes: attrs,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn readlink(&self, path: &str) -> Result<FileInfo> {
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(path);
+
+        // self.send(SSH_FXP_READLINK, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_READLINK,
+            u32: Self::UNASSIGNED_ID,
+            one: path,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+            Message::Name(mut infos) if infos.len() == 1 => Ok(infos.remove(0)),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+                                                                  // _ => Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    pub async fn symlink(&self, linkpath: &str, targetpath: &str) -> Result<()> {
+        // SSH_FXP_SYMLINK was replaced by SSH_FXP_LINK in version 6
+        if self.version >= 6 {
+            return self.link(linkpath, targetpath, true).await;
+        }
+
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(linkpath);
+        // buffer.put_one(targetpath);
+
+        // self.send(SSH_FXP_SYMLINK, buffer.as_ref()).await?;
+
+        // OpenSSH swapped the arguments of version 3 and every server followed it
+        let (first, second) = match self.version {
+            3 => (targetpath, linkpath),
+            _ => (linkpath, targetpath),
+        };
+        let buffer = make_buffer! {
+            u8: SSH_FXP_SYMLINK,
+            u32: Self::UNASSIGNED_ID,
+            one: first,
+            one: second,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    /// Create `new_link` pointing to `existing`, a hard link unless `symbolic`, version 6 only
+    pub async fn link(&self, new_link: &str, existing: &str, symbolic: bool) -> Result<()> {
+        self.require_version(6, "SSH_FXP_LINK")?;
+
+        let symbolic = symbolic as u8;
+        let buffer = make_buffer! {
+            u8: SSH_FXP_LINK,
+            u32: Self::UNASSIGNED_ID,
+            one: new_link,
+            one: existing,
+            u8: symbolic,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    /// Lock `len` bytes from `offset`, 0 means to the end of the file, version 6 only
+    pub async fn block(&self, file: &File, offset: u64, len: u64, mask: LockFlags) -> Result<()> {
+        self.require_version(6, "SSH_FXP_BLOCK")?;
+
+        let mask = mask.bits();
+        let buffer = make_buffer! {
+            u8: SSH_FXP_BLOCK,
+            u32: Self::UNASSIGNED_ID,
+            one: &file.handle,
+            u64: offset,
+            u64: len,
+            u32: mask,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    /// Release a lock taken by `block` with the same range, version 6 only
+    pub async fn unblock(&self, file: &File, offset: u64, len: u64) -> Result<()> {
+        self.require_version(6, "SSH_FXP_UNBLOCK")?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_UNBLOCK,
+            u32: Self::UNASSIGNED_ID,
+            one: &file.handle,
+            u64: offset,
+            u64: len,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn realpath(&self, path: &str) -> Result<String> {
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(path);
+
+        // self.send(SSH_FXP_REALPATH, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_REALPATH,
+            u32: Self::UNASSIGNED_ID,
+            one: path,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+            Message::Name(infos) if infos.len() == 1 => Ok(infos[0].filename.clone()),
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+                                                                  // _ => Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    pub async fn rename_file_or_dir(&self, old: &str, new: &str) -> Result<()> {
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(old);
+        // buffer.put_one(new);
+
+        // self.send(SSH_FXP_RENAME, buffer.as_ref()).await?;
+
+        self.rename_with_flags(old, new, RenameFlags::empty()).await
+    }
+
+    /// Rename with the flags of version 5 on, older versions only take no flags,
+    /// see `posix_rename` to overwrite there
+    pub async fn rename_with_flags(&self, old: &str, new: &str, flags: RenameFlags) -> Result<()> {
+        if !flags.is_empty() {
+            self.require_version(5, "Rename flags")?;
+        }
+
+        let mut tmp = Buffer::new();
+        if self.version >= 5 {
+            tmp.put_u32(flags.bits());
+        }
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_RENAME,
+            u32: Self::UNASSIGNED_ID,
+            one: old,
+            one: new,
+            bytes: tmp,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn remove_file(&self, file: &str) -> Result<()> {
+        // let mut buffer = Buffer::new();
+        // buffer.put_u32(request_id);
+        // buffer.put_one(file);
+
+        // self.send(SSH_FXP_REMOVE, buffer.as_ref()).await?;
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_REMOVE,
+            u32: Self::UNASSIGNED_ID,
+            one: file,
+        };
+
+        self.request_status(buffer, Status::no_eof).await
+    }
+
+    pub async fn open_file(
+        &self,
+        filename: &str,
+        flags: OpenFlags,
+        permissions: Option<Permissions>,
+    ) -> Result<File> {
+        let attrs = self.mode_attrs(SSH_FILEXFER_TYPE_REGULAR, permissions);
+
+        // let mut buffer = Buffer::new();
+
+        // buffer.put_u32(request_id);
+        // buffer.put_one(filename);
+        // buffer.put_u32(flags.bits());
+
+        // buffer.put_u32(flag);
+        // buffer.put_bytes(tmp);
+
+        // self.send(SSH_FXP_OPEN, buffer.as_ref()).await?;
+
+        let mut tmp = Buffer::new();
+        match self.version {
+            ..=3 => tmp.put_u32((flags - OpenFlags::TEXT).bits()),
+            4 => tmp.put_u32(flags.bits()),
+            _ => {
+                let (access, flags) = Self::access_and_disposition(flags);
+                tmp.put_u32(access);
+                tmp.put_u32(flags);
+            }
+        }
+        tmp.put_bytes(attrs);
+
+        let buffer = make_buffer! {
+            u8: SSH_FXP_OPEN,
+            u32: Self::UNASSIGNED_ID,
+            one: filename,
+            bytes: tmp,
+        };
+
+        let packet = self.request(buffer).await?;
+
+        match packet.msg {
+            Message::FileHandle(handle) => Ok(File::new(handle)),
+            Message::Status { status, msg, .. } => status.no_ok_and_eof(msg),
+
+            _ => builder::Protocol { tip: "Unknown msg" }.fail(), //Err(Error::ProtocolError("Unknown msg".to_string())),
+                                                                  // _ => Err(Error::ProtocolError("Unknown msg".to_string())),
+        }
+    }
+
+    // version 5 on asks for ACE4 access bits and says what to do with an existing
+    // file instead of the open flags
+    fn access_and_disposition(flags: OpenFlags) -> (u32, u32) {
+        let mut access = 0;
+        let mut disposition = match (
+            flags.contains(OpenFlags::CREAT),
+            flags.contains(OpenFlags::EXCL),
+            flags.contains(OpenFlags::TRUNC),
+        ) {
+            (true, true, _) => SSH_FXF_CREATE_NEW,
+            (true, false, true) => SSH_FXF_CREATE_TRUNCATE,
+            (true, false, false) => SSH_FXF_OPEN_OR_CREATE,
+            (false, _, true) => SSH_FXF_TRUNCATE_EXISTING,
+            (false, _, false) => SSH_FXF_OPEN_EXISTING,
+        };
+
+        if flags.contains(OpenFlags::READ) {
+            access |= ACE4_READ_DATA | ACE4_READ_ATTRIBUTES;
+        }
+        if flags.contains(OpenFlags::WRITE) {
+            access |= ACE4_WRITE_DATA | ACE4_WRITE_ATTRIBUTES;
+        }
+        if flags.contains(OpenFlags::APPEND) {
+            access |= ACE4_APPEND_DATA;
+            disposition |= SSH_FXF_APPEND_DATA;
+        }
+        if flags.contains(OpenFlags::TEXT) {
+            disposition |= SSH_FXF_TEXT_MODE;
+        }
+        (access, disposition)
+    }
+
+    // the attributes of mkdir and open, the type byte is only sent from version 4 on
+    fn mode_attrs(&self, file_type: u8, permissions: Option<Permissions>) -> Buffer<Vec<u8>> {
+        let mut attrs = Buffer::new();
+        attrs.put_u32(match permissions {
+            Some(_) => SSH_FILEXFER_ATTR_PERMISSIONS,
+            None => 0,
+        });
+        if self.version >= 4 {
+            attrs.put_u8(file_type);
+        }
+        if let Some(permissions) = permissions {
+            attrs.put_u32(permissions.bits());
+        }
+        attrs
+    }
+
+    // from version 4 on stat asks for the attributes wanted
+    fn attr_flags(&self) -> Buffer<Vec<u8>> {
+        let mut buffer = Buffer::new();
+        let mut flags = match self.version {
+            ..=3 => return buffer,
+            _ => {
+                SSH_FILEXFER_ATTR_SIZE
+                    | SSH_FILEXFER_ATTR_PERMISSIONS
+                    | SSH_FILEXFER_ATTR_ACCESSTIME
+                    | SSH_FILEXFER_ATTR_CREATETIME
+                    | SSH_FILEXFER_ATTR_MODIFYTIME
+                    | SSH_FILEXFER_ATTR_ACL
+                    | SSH_FILEXFER_ATTR_OWNERGROUP
+                    | SSH_FILEXFER_ATTR_SUBSECOND_TIMES
+            }
+        };
+        if self.version >= 5 {
+            flags |= SSH_FILEXFER_ATTR_BITS;
+        }
+        if self.version >= 6 {
+            flags |= SSH_FILEXFER_ATTR_ALLOCATION_SIZE
+                | SSH_FILEXFER_ATTR_TEXT_HINT
+                | SSH_FILEXFER_ATTR_MIME_TYPE
+                | SSH_FILEXFER_ATTR_LINK_COUNT
+                | SSH_FILEXFER_ATTR_UNTRANSLATED_NAME
+                | SSH_FILEXFER_ATTR_CTIME;
+        }
+        buffer.put_u32(flags);
+        buffer
+    }
+
+    fn require_version(&self, version: u32, what: &str) -> Result<()> {
+        if self.version < version {
+            return builder::OpUnsupported {
+                tip: format!(
+                    "{what} needs SFtp version {version}, negotiated {}",
+                    self.version
+                ),
+            }
+            .fail();
+        }
+        Ok(())
+    }
+
+    async fn request(&self, packet: Buffer<Vec<u8>>) -> Result<Packet> {
+        Self::wait_for_packet(self.send(packet)?).await
+    }
+
+    /// Queue a request for the dispatcher without waiting for the reply,
+    /// keep the receiver to get the reply later
+    fn send(&self, packet: Buffer<Vec<u8>>) -> Result<OReceiver<Result<Packet>>> {
+        let (sender, recver) = o_channel();
+        self.sender
+            .send(Command::Request {
+                packet: packet.into_vec(),
+                sender,
+            })
+            .map_err(|_| builder::ChannelClosed.build())?;
+        Ok(recver)
+    }
+
+    async fn wait_for_packet(reply: OReceiver<Result<Packet>>) -> Result<Packet> {
+        reply.await.map_err(|_| builder::ChannelClosed.build())?
+    }
+
+    // async fn write(&self, data: impl AsRef<[u8]>) -> Result<()> {
+    //     if !self.channel.write(data.as_ref()).await? {
+    //         self.channel.flush().await?;
+    //     }
+    //     Ok(())
+    // }
+}
@@ -0,0 +1,113 @@
+//! Progress of a transfer, see `Transfer::progress`
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+/// How long the current rate is averaged over
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Told how a transfer goes, called from the task doing it so it should return quickly
+pub trait Progress: Send + Sync {
+    /// A file starts, `status.done` isn't 0 when a transfer is resumed
+    fn start(&self, _status: &ProgressStatus) {}
+
+    /// More of the file is done
+    fn update(&self, _status: &ProgressStatus) {}
+
+    /// The file is done, or failed with `error`
+    fn finish(&self, _status: &ProgressStatus, _error: Option<&Error>) {}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressStatus {
+    /// The remote path
+    pub path: String,
+    pub done: u64,
+    /// `None` if the size isn't known, e.g. uploading from a stream
+    pub total: Option<u64>,
+    /// Bytes a second over the last two seconds
+    pub rate: f64,
+    pub elapsed: Duration,
+}
+
+impl ProgressStatus {
+    /// The time left at the current rate
+    pub fn eta(&self) -> Option<Duration> {
+        let left = self.total?.saturating_sub(self.done);
+        if left == 0 {
+            return Some(Duration::ZERO);
+        }
+        (self.rate > 0.0).then(|| Duration::from_secs_f64(left as f64 / self.rate))
+    }
+}
+
+/// Keeps the status of one file and reports it to the observer, if there is one
+pub(super) struct Tracker {
+    progress: Option<Arc<dyn Progress>>,
+    status: ProgressStatus,
+    started: Instant,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Tracker {
+    pub(super) fn new(
+        progress: Option<Arc<dyn Progress>>,
+        path: &str,
+        done: u64,
+        total: Option<u64>,
+    ) -> Self {
+        let started = Instant::now();
+        let tracker = Self {
+            progress,
+            status: ProgressStatus {
+                path: path.to_string(),
+                done,
+                total,
+                rate: 0.0,
+                elapsed: Duration::ZERO,
+            },
+            started,
+            samples: VecDeque::from([(started, done)]),
+        };
+
+        if let Some(progress) = &tracker.progress {
+            progress.start(&tracker.status);
+        }
+        tracker
+    }
+
+    /// Reports nothing, for the reads that only check a file
+    pub(super) fn none() -> Self {
+        Self::new(None, "", 0, None)
+    }
+
+    pub(super) fn add(&mut self, len: u64) {
+        let now = Instant::now();
+        self.status.done += len;
+        self.status.elapsed = now - self.started;
+
+        self.samples.push_back((now, self.status.done));
+        while self.samples.len() > 2 && now - self.samples[1].0 >= RATE_WINDOW {
+            self.samples.pop_front();
+        }
+        let (since, done) = self.samples[0];
+        let secs = (now - since).as_secs_f64();
+        if secs > 0.0 {
+            self.status.rate = (self.status.done - done) as f64 / secs;
+        }
+
+        if let Some(progress) = &self.progress {
+            progress.update(&self.status);
+        }
+    }
+
+    pub(super) fn finish<T>(mut self, res: &crate::error::Result<T>) {
+        self.status.elapsed = self.started.elapsed();
+        if let Some(progress) = &self.progress {
+            progress.finish(&self.status, res.as_ref().err());
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use super::{FileSystem, ServerFile};
+use crate::error::{builder, Result};
+use crate::sftp::{
+    Attributes, FileInfo, FileType, OpenFlags, Permissions, Property, Timestamp, User,
+};
+
+/// A directory of the local disk served as `/`.
+///
+/// Paths are checked to stay inside it after the symbolic links are resolved, a link
+/// leading out can't be followed. Absolute link targets are stored relative to the
+/// link, so they lead to the same place inside the root
+#[derive(Debug, Clone)]
+pub struct LocalFs {
+    root: PathBuf,
+}
+
+impl LocalFs {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            root: std::fs::canonicalize(root)?,
+        })
+    }
+
+    /// The local path of `path`, the last link is followed only if `follow`
+    async fn local(&self, path: &str, follow: bool) -> Result<PathBuf> {
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let dir = tokio::fs::canonicalize(self.root.join(dir.trim_start_matches('/'))).await?;
+        self.check(&dir, path)?;
+
+        let local = match name {
+            "" => dir,
+            name => dir.join(name),
+        };
+        if !follow {
+            return Ok(local);
+        }
+        match tokio::fs::canonicalize(&local).await {
+            Ok(local) => {
+                self.check(&local, path)?;
+                Ok(local)
+            }
+            // a dangling link may lead anywhere once created through
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match tokio::fs::symlink_metadata(&local).await {
+                    Ok(_) => Err(e.into()),
+                    Err(_) => Ok(local),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn check(&self, local: &Path, path: &str) -> Result<()> {
+        if !local.starts_with(&self.root) {
+            return builder::PermissionDenied {
+                tip: format!("{path} leads outside of the root"),
+            }
+            .fail();
+        }
+        Ok(())
+    }
+
+    /// The served path of a local one inside the root
+    fn served(&self, local: &Path) -> Result<String> {
+        let relative = local.strip_prefix(&self.root).unwrap_or(local);
+        let relative = relative.to_str().ok_or_else(|| {
+            builder::InvalidArgument {
+                tip: "The path isn't valid utf8",
+            }
+            .build()
+        })?;
+        Ok(format!("/{}", relative.replace('\\', "/")))
+    }
+}
+
+fn attributes(metadata: &std::fs::Metadata) -> Attributes {
+    let file_type = metadata.file_type();
+    let file_type = if file_type.is_dir() {
+        FileType::Directory
+    } else if file_type.is_symlink() {
+        FileType::SymbolicLink
+    } else {
+        FileType::RegularFile
+    };
+
+    #[cfg(unix)]
+    let (permissions, user) = {
+        use std::os::unix::fs::MetadataExt;
+        (
+            Permissions::from_bits_truncate(metadata.mode()),
+            Some(User::new(metadata.uid(), metadata.gid())),
+        )
+    };
+    #[cfg(not(unix))]
+    let (permissions, user) = match metadata.permissions().readonly() {
+        true => (Permissions::from_bits_retain(0o555), None),
+        false => (Permissions::from_bits_retain(0o755), None),
+    };
+
+    let unix_time = |time: io::Result<SystemTime>| {
+        time.ok()
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|time| time.as_secs() as u32)
+            .unwrap_or_default()
+    };
+
+    let mut attrs = Attributes::new(
+        Some(metadata.len()),
+        user,
+        Some(Property::new(permissions, file_type)),
+        Some(Timestamp::new(
+            unix_time(metadata.accessed()),
+            unix_time(metadata.modified()),
+        )),
+        HashMap::new(),
+    );
+    attrs.file_type = Some(file_type);
+    attrs
+}
+
+/// Everything but the size, which needs the file opened for writing
+async fn set_attributes(local: &Path, file: &std::fs::File, attrs: &Attributes) -> Result<()> {
+    if let Some(property) = attrs.property {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(property.permissions.bits());
+            tokio::fs::set_permissions(local, permissions).await?;
+        }
+        #[cfg(not(unix))]
+        let _ = (local, property);
+    }
+
+    #[cfg(unix)]
+    if let Some(user) = attrs.user {
+        std::os::unix::fs::chown(local, Some(user.uid), Some(user.gid))?;
+    }
+
+    if let Some(Timestamp { atime, mtime }) = attrs.time {
+        let at = |secs: u32| SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64);
+        let times = std::fs::FileTimes::new()
+            .set_accessed(at(atime))
+            .set_modified(at(mtime));
+        file.set_times(times)?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl FileSystem for LocalFs {
+    async fn open(
+        &self,
+        path: &str,
+        flags: OpenFlags,
+        attrs: &Attributes,
+    ) -> Result<Box<dyn ServerFile>> {
+        let local = self.local(path, true).await?;
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options
+            .read(flags.contains(OpenFlags::READ))
+            .write(flags.contains(OpenFlags::WRITE))
+            .append(flags.contains(OpenFlags::APPEND))
+            .truncate(flags.contains(OpenFlags::TRUNC));
+        match flags.contains(OpenFlags::EXCL) {
+            true => options.create_new(flags.contains(OpenFlags::CREAT)),
+            false => options.create(flags.contains(OpenFlags::CREAT)),
+        };
+        #[cfg(unix)]
+        if let Some(property) = attrs.property {
+            options.mode(property.permissions.bits());
+        }
+        #[cfg(not(unix))]
+        let _ = attrs;
+
+        let file = options.open(&local).await?;
+        if file.metadata().await?.is_dir() {
+            return builder::SFtpFailure {
+                tip: format!("{path} is a directory"),
+            }
+            .fail();
+        }
+        Ok(Box::new(LocalFile { local, file }))
+    }
+
+    async fn read_dir(&self, path: &str) -> Result<Vec<FileInfo>> {
+        let local = self.local(path, true).await?;
+        let mut entries = tokio::fs::read_dir(local).await?;
+
+        let mut infos = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(name) = entry.file_name().to_str().map(String::from) else {
+                continue;
+            };
+            let metadata = tokio::fs::symlink_metadata(entry.path()).await?;
+            infos.push(FileInfo::new(name, String::new(), attributes(&metadata)));
+        }
+        infos.sort_by(|a, b| a.filename.cmp(&b.filename));
+        Ok(infos)
+    }
+
+    async fn stat(&self, path: &str) -> Result<Attributes> {
+        let local = self.local(path, true).await?;
+        Ok(attributes(&tokio::fs::metadata(local).await?))
+    }
+
+    async fn lstat(&self, path: &str) -> Result<Attributes> {
+        let local = self.local(path, false).await?;
+        Ok(attributes(&tokio::fs::symlink_metadata(local).await?))
+    }
+
+    async fn setstat(&self, path: &str, attrs: &Attributes) -> Result<()> {
+        let local = self.local(path, true).await?;
+        let file = match attrs.size {
+            Some(size) => {
+                let file = std::fs::OpenOptions::new().write(true).open(&local)?;
+                file.set_len(size)?;
+                file
+            }
+            None => std::fs::File::open(&local)?,
+        };
+        set_attributes(&local, &file, attrs).await
+    }
+
+    async fn mkdir(&self, path: &str, attrs: &Attributes) -> Result<()> {
+        let local = self.local(path, false).await?;
+        let mut builder = tokio::fs::DirBuilder::new();
+        #[cfg(unix)]
+        if let Some(property) = attrs.property {
+            builder.mode(property.permissions.bits());
+        }
+        #[cfg(not(unix))]
+        let _ = attrs;
+        builder.create(local).await?;
+        Ok(())
+    }
+
+    async fn rmdir(&self, path: &str) -> Result<()> {
+        let local = self.local(path, false).await?;
+        if local == self.root {
+            return builder::PermissionDenied {
+                tip: "The root can't be removed",
+            }
+            .fail();
+        }
+        tokio::fs::remove_dir(local).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, path: &str) -> Result<()> {
+        let local = self.local(path, false).await?;
+        tokio::fs::remove_file(local).await?;
+        Ok(())
+    }
+
+    async fn rename(&self, old: &str, new: &str, overwrite: bool) -> Result<()> {
+        let old = self.local(old, false).await?;
+        let new_local = self.local(new, false).await?;
+        if !overwrite && tokio::fs::symlink_metadata(&new_local).await.is_ok() {
+            return builder::SFtpFailure {
+                tip: format!("{new} already exists"),
+            }
+            .fail();
+        }
+        tokio::fs::rename(old, new_local).await?;
+        Ok(())
+    }
+
+    async fn readlink(&self, path: &str) -> Result<String> {
+        let local = self.local(path, false).await?;
+        let target = tokio::fs::read_link(local).await?;
+        Ok(target.to_string_lossy().to_string())
+    }
+
+    #[cfg(unix)]
+    async fn symlink(&self, path: &str, target: &str) -> Result<()> {
+        let local = self.local(path, false).await?;
+        // `/a` from `/x/y/link` is `../../a`
+        let target = match target.strip_prefix('/') {
+            Some(target) => {
+                let depth = path.trim_start_matches('/').matches('/').count();
+                format!("{}{target}", "../".repeat(depth))
+            }
+            None => target.to_string(),
+        };
+        tokio::fs::symlink(target, local).await?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    async fn symlink(&self, _path: &str, _target: &str) -> Result<()> {
+        super::unsupported("symlink")
+    }
+
+    async fn realpath(&self, path: &str) -> Result<String> {
+        let local = self.local(path, true).await?;
+        self.served(&local)
+    }
+
+    async fn hardlink(&self, old: &str, new: &str) -> Result<()> {
+        let old = self.local(old, false).await?;
+        let new = self.local(new, false).await?;
+        tokio::fs::hard_link(old, new).await?;
+        Ok(())
+    }
+}
+
+struct LocalFile {
+    local: PathBuf,
+    file: tokio::fs::File,
+}
+
+#[async_trait]
+impl ServerFile for LocalFile {
+    async fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(offset)).await?;
+        let mut data = Vec::with_capacity(len);
+        (&mut self.file)
+            .take(len as u64)
+            .read_to_end(&mut data)
+            .await?;
+        Ok(data)
+    }
+
+    async fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(offset)).await?;
+        self.file.write_all(data).await?;
+        // tokio holds the data back until flushed, dropping the file loses it
+        self.file.flush().await?;
+        Ok(())
+    }
+
+    async fn stat(&mut self) -> Result<Attributes> {
+        Ok(attributes(&self.file.metadata().await?))
+    }
+
+    async fn setstat(&mut self, attrs: &Attributes) -> Result<()> {
+        self.file.flush().await?;
+        if let Some(size) = attrs.size {
+            self.file.set_len(size).await?;
+        }
+        let file = self.file.try_clone().await?.into_std().await;
+        set_attributes(&self.local, &file, attrs).await
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        self.file.sync_all().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,552 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use super::{normalize, FileSystem, ServerFile};
+use crate::error::{builder, Result};
+use crate::sftp::{
+    Attributes, FileInfo, FileType, OpenFlags, Permissions, Property, Statvfs, Timestamp, User,
+};
+
+/// Links followed while resolving one path
+const MAX_LINKS: usize = 32;
+
+/// The largest a file may grow, a client could otherwise have any amount allocated
+const MAX_FILE_SIZE: u64 = 1 << 30;
+
+/// Files kept in memory, for tests and small virtual trees.
+///
+/// Clones share the files, so they can be looked at while a server serves them
+#[derive(Clone)]
+pub struct MemoryFs {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    // path to inode, hard links share the inode
+    paths: HashMap<String, u64>,
+    inodes: HashMap<u64, Inode>,
+    next: u64,
+}
+
+struct Inode {
+    kind: Kind,
+    permissions: Permissions,
+    user: User,
+    time: Timestamp,
+    links: u32,
+}
+
+enum Kind {
+    Dir,
+    File(Vec<u8>),
+    Link(String),
+}
+
+fn no_such_file<T>(path: &str) -> Result<T> {
+    builder::NoSuchFile {
+        tip: format!("No such file: {path}"),
+    }
+    .fail()
+}
+
+fn failure<T>(tip: String) -> Result<T> {
+    builder::SFtpFailure { tip }.fail()
+}
+
+/// The size of a file holding `len` bytes from `offset`, if it's allowed to be that large
+fn file_size(offset: u64, len: usize) -> Result<usize> {
+    match offset.checked_add(len as u64) {
+        Some(size) if size <= MAX_FILE_SIZE => Ok(size as usize),
+        _ => failure(format!("A file can't be larger than {MAX_FILE_SIZE} bytes")),
+    }
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "/" => format!("/{name}"),
+        dir => format!("{dir}/{name}"),
+    }
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFs {
+    /// An empty tree, only `/` is there
+    pub fn new() -> Self {
+        let mut state = State {
+            paths: HashMap::new(),
+            inodes: HashMap::new(),
+            next: 0,
+        };
+        state.create("/".to_string(), Kind::Dir, Permissions::p0755());
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Write a whole file, the directories leading to it are created
+    pub fn write(&self, path: &str, data: impl Into<Vec<u8>>) {
+        let path = normalize(path);
+        let mut state = self.state.lock().unwrap();
+        let mut dir = "/".to_string();
+        for name in parent(&path).split('/').filter(|name| !name.is_empty()) {
+            dir = join(&dir, name);
+            if !state.paths.contains_key(&dir) {
+                state.create(dir.clone(), Kind::Dir, Permissions::p0755());
+            }
+        }
+        let data = Kind::File(data.into());
+        match state.paths.get(&path).copied() {
+            Some(inode) => state.inodes.get_mut(&inode).unwrap().kind = data,
+            None => state.create(path, data, Permissions::from_bits_retain(0o644)),
+        }
+    }
+
+    /// The content of a file, `None` if it isn't one
+    pub fn read(&self, path: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        let path = state.resolve(&normalize(path), true).ok()?;
+        match &state.inode(&path).ok()?.1.kind {
+            Kind::File(data) => Some(data.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl State {
+    fn create(&mut self, path: String, kind: Kind, permissions: Permissions) {
+        let inode = self.next;
+        self.next += 1;
+        let time = Timestamp::new(now(), now());
+        self.inodes.insert(
+            inode,
+            Inode {
+                kind,
+                permissions,
+                user: User::new(0, 0),
+                time,
+                links: 1,
+            },
+        );
+        self.paths.insert(path, inode);
+    }
+
+    fn inode(&self, path: &str) -> Result<(u64, &Inode)> {
+        match self.paths.get(path) {
+            Some(inode) => Ok((*inode, &self.inodes[inode])),
+            None => no_such_file(path),
+        }
+    }
+
+    fn inode_mut(&mut self, path: &str) -> Result<&mut Inode> {
+        match self.paths.get(path) {
+            Some(inode) => Ok(self.inodes.get_mut(inode).unwrap()),
+            None => no_such_file(path),
+        }
+    }
+
+    /// Follow the links in `path`, the last one only if `follow`. The path
+    /// itself needn't exist, the directories leading to it must
+    fn resolve(&self, path: &str, follow: bool) -> Result<String> {
+        let mut resolved = "/".to_string();
+        let mut rest: VecDeque<String> = path
+            .split('/')
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+        let mut links = 0;
+        while let Some(name) = rest.pop_front() {
+            let next = join(&resolved, &name);
+            match self.paths.get(&next).map(|inode| &self.inodes[inode].kind) {
+                Some(Kind::Link(target)) if follow || !rest.is_empty() => {
+                    links += 1;
+                    if links > MAX_LINKS {
+                        return failure(format!("Too many links: {path}"));
+                    }
+                    let target = match target.starts_with('/') {
+                        true => normalize(target),
+                        false => normalize(&join(&resolved, target)),
+                    };
+                    for name in target.rsplit('/').filter(|name| !name.is_empty()) {
+                        rest.push_front(name.to_string());
+                    }
+                    resolved = "/".to_string();
+                }
+                Some(Kind::Dir) | Some(Kind::Link(_)) => resolved = next,
+                Some(Kind::File(_)) if rest.is_empty() => resolved = next,
+                Some(Kind::File(_)) => return failure(format!("Not a directory: {next}")),
+                None if rest.is_empty() => resolved = next,
+                None => return no_such_file(&next),
+            }
+        }
+        Ok(resolved)
+    }
+
+    // the parent of a new entry must be a directory
+    fn check_new(&self, path: &str) -> Result<()> {
+        if self.paths.contains_key(path) {
+            return failure(format!("Already exists: {path}"));
+        }
+        match self.inode(parent(path))?.1.kind {
+            Kind::Dir => Ok(()),
+            _ => failure(format!("Not a directory: {}", parent(path))),
+        }
+    }
+
+    fn unlink(&mut self, path: &str) {
+        if let Some(inode) = self.paths.remove(path) {
+            let node = self.inodes.get_mut(&inode).unwrap();
+            node.links -= 1;
+            if node.links == 0 {
+                self.inodes.remove(&inode);
+            }
+        }
+    }
+
+    fn children(&self, dir: &str) -> Vec<String> {
+        let mut children: Vec<_> = self
+            .paths
+            .keys()
+            .filter(|path| path.as_str() != "/" && parent(path) == dir)
+            .cloned()
+            .collect();
+        children.sort();
+        children
+    }
+}
+
+impl Inode {
+    fn attributes(&self) -> Attributes {
+        let (file_type, size) = match &self.kind {
+            Kind::Dir => (FileType::Directory, 0),
+            Kind::File(data) => (FileType::RegularFile, data.len()),
+            Kind::Link(target) => (FileType::SymbolicLink, target.len()),
+        };
+        let mut attrs = Attributes::new(
+            Some(size as u64),
+            Some(self.user),
+            Some(Property::new(self.permissions, file_type)),
+            Some(self.time),
+            HashMap::new(),
+        );
+        attrs.file_type = Some(file_type);
+        attrs.link_count = Some(self.links);
+        attrs
+    }
+
+    fn set_attributes(&mut self, attrs: &Attributes) -> Result<()> {
+        if let Some(size) = attrs.size {
+            match &mut self.kind {
+                Kind::File(data) => data.resize(file_size(size, 0)?, 0),
+                _ => return failure("Only a file has a size to set".to_string()),
+            }
+        }
+        if let Some(property) = attrs.property {
+            self.permissions = property.permissions;
+        }
+        if let Some(user) = attrs.user {
+            self.user = user;
+        }
+        if let Some(time) = attrs.time {
+            self.time = time;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FileSystem for MemoryFs {
+    async fn open(
+        &self,
+        path: &str,
+        flags: OpenFlags,
+        attrs: &Attributes,
+    ) -> Result<Box<dyn ServerFile>> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.resolve(path, true)?;
+        if !state.paths.contains_key(&path) {
+            if !flags.contains(OpenFlags::CREAT) {
+                return no_such_file(&path);
+            }
+            state.check_new(&path)?;
+            let permissions = attrs
+                .property
+                .map(|p| p.permissions)
+                .unwrap_or(Permissions::from_bits_retain(0o644));
+            state.create(path.clone(), Kind::File(vec![]), permissions);
+        } else if flags.contains(OpenFlags::CREAT | OpenFlags::EXCL) {
+            return failure(format!("Already exists: {path}"));
+        }
+
+        let inode = state.paths[&path];
+        let node = state.inodes.get_mut(&inode).unwrap();
+        match &mut node.kind {
+            Kind::File(data) if flags.contains(OpenFlags::TRUNC) => data.clear(),
+            Kind::File(_) => {}
+            _ => return failure(format!("Not a file: {path}")),
+        }
+
+        Ok(Box::new(MemoryFile {
+            state: self.state.clone(),
+            inode,
+            append: flags.contains(OpenFlags::APPEND),
+        }))
+    }
+
+    async fn read_dir(&self, path: &str) -> Result<Vec<FileInfo>> {
+        let state = self.state.lock().unwrap();
+        let path = state.resolve(path, true)?;
+        if !matches!(state.inode(&path)?.1.kind, Kind::Dir) {
+            return failure(format!("Not a directory: {path}"));
+        }
+        state
+            .children(&path)
+            .into_iter()
+            .map(|child| {
+                let attrs = state.inode(&child)?.1.attributes();
+                let name = child[child.rfind('/').unwrap() + 1..].to_string();
+                Ok(FileInfo::new(name, String::new(), attrs))
+            })
+            .collect()
+    }
+
+    async fn stat(&self, path: &str) -> Result<Attributes> {
+        let state = self.state.lock().unwrap();
+        let path = state.resolve(path, true)?;
+        Ok(state.inode(&path)?.1.attributes())
+    }
+
+    async fn lstat(&self, path: &str) -> Result<Attributes> {
+        let state = self.state.lock().unwrap();
+        let path = state.resolve(path, false)?;
+        Ok(state.inode(&path)?.1.attributes())
+    }
+
+    async fn setstat(&self, path: &str, attrs: &Attributes) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.resolve(path, true)?;
+        state.inode_mut(&path)?.set_attributes(attrs)
+    }
+
+    async fn lsetstat(&self, path: &str, attrs: &Attributes) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.resolve(path, false)?;
+        state.inode_mut(&path)?.set_attributes(attrs)
+    }
+
+    async fn mkdir(&self, path: &str, attrs: &Attributes) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.resolve(path, false)?;
+        state.check_new(&path)?;
+        let permissions = attrs
+            .property
+            .map(|p| p.permissions)
+            .unwrap_or(Permissions::p0755());
+        state.create(path, Kind::Dir, permissions);
+        Ok(())
+    }
+
+    async fn rmdir(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.resolve(path, false)?;
+        if !matches!(state.inode(&path)?.1.kind, Kind::Dir) || path == "/" {
+            return failure(format!("Not a directory: {path}"));
+        }
+        if !state.children(&path).is_empty() {
+            return failure(format!("Directory not empty: {path}"));
+        }
+        state.unlink(&path);
+        Ok(())
+    }
+
+    async fn remove(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.resolve(path, false)?;
+        if matches!(state.inode(&path)?.1.kind, Kind::Dir) {
+            return failure(format!("Is a directory: {path}"));
+        }
+        state.unlink(&path);
+        Ok(())
+    }
+
+    async fn rename(&self, old: &str, new: &str, overwrite: bool) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let old = state.resolve(old, false)?;
+        let new = state.resolve(new, false)?;
+        state.inode(&old)?;
+        if old == new {
+            return Ok(());
+        }
+        if new.starts_with(&format!("{old}/")) {
+            return failure(format!("Can't move {old} into itself"));
+        }
+        if state.paths.contains_key(&new) {
+            if !overwrite {
+                return failure(format!("Already exists: {new}"));
+            }
+            if !state.children(&new).is_empty() {
+                return failure(format!("Directory not empty: {new}"));
+            }
+            state.unlink(&new);
+        }
+        state.check_new(&new)?;
+
+        // a directory takes everything under it along
+        let prefix = format!("{old}/");
+        let moved: Vec<_> = state
+            .paths
+            .keys()
+            .filter(|path| **path == old || path.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for path in moved {
+            let inode = state.paths.remove(&path).unwrap();
+            state
+                .paths
+                .insert(format!("{new}{}", &path[old.len()..]), inode);
+        }
+        Ok(())
+    }
+
+    async fn readlink(&self, path: &str) -> Result<String> {
+        let state = self.state.lock().unwrap();
+        let path = state.resolve(path, false)?;
+        match &state.inode(&path)?.1.kind {
+            Kind::Link(target) => Ok(target.clone()),
+            _ => failure(format!("Not a link: {path}")),
+        }
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.resolve(path, false)?;
+        state.check_new(&path)?;
+        let permissions = Permissions::from_bits_retain(0o777);
+        state.create(path, Kind::Link(target.to_string()), permissions);
+        Ok(())
+    }
+
+    async fn realpath(&self, path: &str) -> Result<String> {
+        self.state.lock().unwrap().resolve(path, true)
+    }
+
+    async fn hardlink(&self, old: &str, new: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let old = state.resolve(old, false)?;
+        let new = state.resolve(new, false)?;
+        let (inode, node) = state.inode(&old)?;
+        if matches!(node.kind, Kind::Dir) {
+            return failure(format!("Is a directory: {old}"));
+        }
+        state.check_new(&new)?;
+        state.inodes.get_mut(&inode).unwrap().links += 1;
+        state.paths.insert(new, inode);
+        Ok(())
+    }
+
+    async fn statvfs(&self, _path: &str) -> Result<Statvfs> {
+        let state = self.state.lock().unwrap();
+        let used: usize = state
+            .inodes
+            .values()
+            .map(|node| match &node.kind {
+                Kind::File(data) => data.len(),
+                _ => 0,
+            })
+            .sum();
+        let files = state.inodes.len() as u64;
+        Ok(Statvfs {
+            bsize: 4096,
+            frsize: 4096,
+            blocks: (used as u64).div_ceil(4096),
+            bfree: 0,
+            bavail: 0,
+            files,
+            ffree: 0,
+            favail: 0,
+            fsid: 0,
+            flag: 0,
+            namemax: 255,
+        })
+    }
+}
+
+/// Follows the inode, so it still works after the file is renamed
+struct MemoryFile {
+    state: Arc<Mutex<State>>,
+    inode: u64,
+    append: bool,
+}
+
+impl MemoryFile {
+    fn with<T>(&self, f: impl FnOnce(&mut Inode) -> Result<T>) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.inodes.get_mut(&self.inode) {
+            Some(node) => f(node),
+            None => failure("The file was removed".to_string()),
+        }
+    }
+
+    fn data(node: &mut Inode) -> &mut Vec<u8> {
+        match &mut node.kind {
+            Kind::File(data) => data,
+            _ => unreachable!("only a file is opened"),
+        }
+    }
+}
+
+#[async_trait]
+impl ServerFile for MemoryFile {
+    async fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.with(|node| {
+            let data = Self::data(node);
+            let start = (offset as usize).min(data.len());
+            let end = start.saturating_add(len).min(data.len());
+            Ok(data[start..end].to_vec())
+        })
+    }
+
+    async fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let append = self.append;
+        self.with(|node| {
+            let file = Self::data(node);
+            let offset = if append { file.len() as u64 } else { offset };
+            let end = file_size(offset, data.len())?;
+            if file.len() < end {
+                file.resize(end, 0);
+            }
+            file[end - data.len()..end].copy_from_slice(data);
+            node.time.mtime = now();
+            Ok(())
+        })
+    }
+
+    async fn stat(&mut self) -> Result<Attributes> {
+        self.with(|node| Ok(node.attributes()))
+    }
+
+    async fn setstat(&mut self, attrs: &Attributes) -> Result<()> {
+        self.with(|node| node.set_attributes(attrs))
+    }
+}
@@ -0,0 +1,713 @@
+//! A version 3 sftp server over any stream, the files come from a `FileSystem`
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{Attributes, FileInfo, FileType, Limits, OpenFlags, Statvfs};
+use crate::error::{builder, Error, Result};
+use crate::ssh::buffer::Buffer;
+use crate::ssh::common::code::*;
+use crate::ssh::common::*;
+
+mod local;
+mod memory;
+
+pub use local::LocalFs;
+pub use memory::MemoryFs;
+
+/// The largest packet taken, larger ones close the session
+const MAX_PACKET: usize = 256 * 1024;
+/// Later versions asked for by a client are answered with this one
+const VERSION: u32 = 3;
+/// The most data read or written by one request
+const MAX_DATA: usize = MAX_PACKET - 1024;
+const MAX_HANDLES: usize = 512;
+/// Entries sent by one `SSH_FXP_READDIR`
+const READDIR_BATCH: usize = 100;
+
+/// The files served, every path is absolute and already normalized by the server,
+/// so `..` never climbs above `/`.
+///
+/// The extension methods fail with `OpUnsupported` unless overridden
+#[async_trait]
+pub trait FileSystem: Send + Sync {
+    async fn open(
+        &self,
+        path: &str,
+        flags: OpenFlags,
+        attrs: &Attributes,
+    ) -> Result<Box<dyn ServerFile>>;
+
+    /// Every entry of the directory, the server hands them out in batches
+    async fn read_dir(&self, path: &str) -> Result<Vec<FileInfo>>;
+
+    async fn stat(&self, path: &str) -> Result<Attributes>;
+
+    async fn lstat(&self, path: &str) -> Result<Attributes>;
+
+    async fn setstat(&self, path: &str, attrs: &Attributes) -> Result<()>;
+
+    async fn mkdir(&self, path: &str, attrs: &Attributes) -> Result<()>;
+
+    async fn rmdir(&self, path: &str) -> Result<()>;
+
+    async fn remove(&self, path: &str) -> Result<()>;
+
+    /// Fails if `new` exists unless `overwrite`, which `posix-rename@openssh.com` asks for
+    async fn rename(&self, old: &str, new: &str, overwrite: bool) -> Result<()>;
+
+    async fn readlink(&self, path: &str) -> Result<String>;
+
+    async fn symlink(&self, path: &str, target: &str) -> Result<()>;
+
+    /// The path with the symbolic links resolved, the path itself by default
+    async fn realpath(&self, path: &str) -> Result<String> {
+        Ok(path.to_string())
+    }
+
+    async fn hardlink(&self, _old: &str, _new: &str) -> Result<()> {
+        unsupported("hardlink")
+    }
+
+    /// Set the attributes of a symbolic link itself
+    async fn lsetstat(&self, _path: &str, _attrs: &Attributes) -> Result<()> {
+        unsupported("lsetstat")
+    }
+
+    async fn statvfs(&self, _path: &str) -> Result<Statvfs> {
+        unsupported("statvfs")
+    }
+
+    /// Where `~` and `~user` lead, `/` by default
+    async fn home_directory(&self, _user: &str) -> Result<String> {
+        Ok("/".to_string())
+    }
+
+    /// For `users-groups-by-id@openssh.com`, unknown ids are sent as empty names
+    fn user_name(&self, _uid: u32) -> Option<String> {
+        None
+    }
+
+    fn group_name(&self, _gid: u32) -> Option<String> {
+        None
+    }
+}
+
+/// A file opened by `FileSystem::open`, closed when dropped
+#[async_trait]
+pub trait ServerFile: Send + Sync {
+    /// At most `len` bytes from `offset`, empty at the end of the file
+    async fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>>;
+
+    async fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()>;
+
+    async fn stat(&mut self) -> Result<Attributes>;
+
+    async fn setstat(&mut self, attrs: &Attributes) -> Result<()>;
+
+    async fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn unsupported<T>(op: &str) -> Result<T> {
+    builder::OpUnsupported {
+        tip: format!("{op} isn't supported"),
+    }
+    .fail()
+}
+
+/// Serves the files of a `FileSystem` to sftp clients, with the OpenSSH extensions
+/// the client in this crate knows.
+///
+/// Run it as the `sftp` subsystem of a server, or over stdin and stdout joined with
+/// `tokio::io::join`. Requests of one session are answered in order
+#[derive(Clone)]
+pub struct SFtpServer {
+    fs: Arc<dyn FileSystem>,
+}
+
+enum Handle {
+    File {
+        path: String,
+        file: Box<dyn ServerFile>,
+    },
+    // the entries not sent yet
+    Dir(std::vec::IntoIter<FileInfo>),
+}
+
+/// The state of one session
+#[derive(Default)]
+struct Handles {
+    handles: HashMap<u32, Handle>,
+    next: u32,
+}
+
+impl Handles {
+    fn insert(&mut self, handle: Handle) -> Result<Vec<u8>> {
+        if self.handles.len() >= MAX_HANDLES {
+            return builder::SFtpFailure {
+                tip: "Too many open handles",
+            }
+            .fail();
+        }
+        while self.handles.contains_key(&self.next) {
+            self.next = self.next.wrapping_add(1);
+        }
+        let id = self.next;
+        self.handles.insert(id, handle);
+        Ok(id.to_be_bytes().to_vec())
+    }
+
+    fn id(handle: &[u8]) -> Result<u32> {
+        let handle = handle.try_into().map_err(|_| invalid_handle())?;
+        Ok(u32::from_be_bytes(handle))
+    }
+
+    fn get(&mut self, handle: &[u8]) -> Result<&mut Handle> {
+        self.handles
+            .get_mut(&Self::id(handle)?)
+            .ok_or_else(invalid_handle)
+    }
+
+    fn file(&mut self, handle: &[u8]) -> Result<(&str, &mut Box<dyn ServerFile>)> {
+        match self.get(handle)? {
+            Handle::File { path, file } => Ok((path, file)),
+            Handle::Dir(_) => Err(invalid_handle()),
+        }
+    }
+
+    fn remove(&mut self, handle: &[u8]) -> Result<Handle> {
+        self.handles
+            .remove(&Self::id(handle)?)
+            .ok_or_else(invalid_handle)
+    }
+}
+
+fn invalid_handle() -> Error {
+    builder::SFtpFailure {
+        tip: "Invalid handle",
+    }
+    .build()
+}
+
+/// A request parsed out of its packet, so nothing borrows the packet across an await
+enum Op {
+    Open(String, OpenFlags, Attributes),
+    Close(Vec<u8>),
+    Read(Vec<u8>, u64, u32),
+    Write(Vec<u8>, u64, Vec<u8>),
+    Lstat(String),
+    Fstat(Vec<u8>),
+    Setstat(String, Attributes),
+    Fsetstat(Vec<u8>, Attributes),
+    Opendir(String),
+    Readdir(Vec<u8>),
+    Remove(String),
+    Mkdir(String, Attributes),
+    Rmdir(String),
+    Realpath(String),
+    Stat(String),
+    Rename(String, String, bool),
+    Readlink(String),
+    Symlink(String, String),
+    Hardlink(String, String),
+    Statvfs(String),
+    Fstatvfs(Vec<u8>),
+    Fsync(Vec<u8>),
+    Lsetstat(String, Attributes),
+    Limits,
+    ExpandPath(String),
+    CopyData {
+        read: Vec<u8>,
+        offset: u64,
+        len: u64,
+        write: Vec<u8>,
+        write_offset: u64,
+    },
+    HomeDirectory(String),
+    UsersGroupsById(Vec<u32>, Vec<u32>),
+    Unknown,
+}
+
+impl Op {
+    fn parse(code: u8, packet: &Buffer<std::cell::Cell<&[u8]>>) -> Option<Self> {
+        let bytes = || packet.take_one().map(|(_, v)| v.to_vec());
+        let string = || {
+            let (_, v) = packet.take_one()?;
+            std::str::from_utf8(v).ok().map(String::from)
+        };
+        // a relative path starts at the home directory
+        let path = || string().map(|path| normalize(&path));
+        let attrs = || Attributes::parse(packet, VERSION);
+        let ids = || {
+            let (_, ids) = packet.take_one()?;
+            let ids = Buffer::from_slice(ids);
+            let mut res = vec![];
+            while ids.len() > 0 {
+                res.push(ids.take_u32()?);
+            }
+            Some(res)
+        };
+
+        Some(match code {
+            SSH_FXP_OPEN => Op::Open(
+                path()?,
+                OpenFlags::from_bits_truncate(packet.take_u32()?),
+                attrs()?,
+            ),
+            SSH_FXP_CLOSE => Op::Close(bytes()?),
+            SSH_FXP_READ => Op::Read(bytes()?, packet.take_u64()?, packet.take_u32()?),
+            SSH_FXP_WRITE => Op::Write(bytes()?, packet.take_u64()?, bytes()?),
+            SSH_FXP_LSTAT => Op::Lstat(path()?),
+            SSH_FXP_FSTAT => Op::Fstat(bytes()?),
+            SSH_FXP_SETSTAT => Op::Setstat(path()?, attrs()?),
+            SSH_FXP_FSETSTAT => Op::Fsetstat(bytes()?, attrs()?),
+            SSH_FXP_OPENDIR => Op::Opendir(path()?),
+            SSH_FXP_READDIR => Op::Readdir(bytes()?),
+            SSH_FXP_REMOVE => Op::Remove(path()?),
+            SSH_FXP_MKDIR => Op::Mkdir(path()?, attrs()?),
+            SSH_FXP_RMDIR => Op::Rmdir(path()?),
+            // the path is kept as sent, `.` is the home directory
+            SSH_FXP_REALPATH => Op::Realpath(string()?),
+            SSH_FXP_STAT => Op::Stat(path()?),
+            SSH_FXP_RENAME => Op::Rename(path()?, path()?, false),
+            SSH_FXP_READLINK => Op::Readlink(path()?),
+            // targetpath then linkpath as OpenSSH sends them, the draft has them the other
+            // way round. The target is stored as sent
+            SSH_FXP_SYMLINK => {
+                let target = string()?;
+                Op::Symlink(path()?, target)
+            }
+            SSH_FXP_EXTENDED => match string()?.as_str() {
+                name if name == OPENSSH_SFTP_EXT_POSIX_RENAME.0 => {
+                    Op::Rename(path()?, path()?, true)
+                }
+                name if name == OPENSSH_SFTP_EXT_HARDLINK.0 => Op::Hardlink(path()?, path()?),
+                name if name == OPENSSH_SFTP_EXT_STATVFS.0 => Op::Statvfs(path()?),
+                name if name == OPENSSH_SFTP_EXT_FSTATVFS.0 => Op::Fstatvfs(bytes()?),
+                name if name == OPENSSH_SFTP_EXT_FSYNC.0 => Op::Fsync(bytes()?),
+                name if name == OPENSSH_SFTP_EXT_LSETSTAT.0 => Op::Lsetstat(path()?, attrs()?),
+                name if name == OPENSSH_SFTP_EXT_LIMITS.0 => Op::Limits,
+                name if name == OPENSSH_SFTP_EXT_EXPAND_PATH.0 => Op::ExpandPath(string()?),
+                name if name == OPENSSH_SFTP_EXT_COPY_DATA.0 => Op::CopyData {
+                    read: bytes()?,
+                    offset: packet.take_u64()?,
+                    len: packet.take_u64()?,
+                    write: bytes()?,
+                    write_offset: packet.take_u64()?,
+                },
+                name if name == OPENSSH_SFTP_EXT_HOME_DIRECTORY.0 => Op::HomeDirectory(string()?),
+                name if name == OPENSSH_SFTP_EXT_USERS_GROUPS_BY_ID.0 => {
+                    Op::UsersGroupsById(ids()?, ids()?)
+                }
+                _ => Op::Unknown,
+            },
+            _ => Op::Unknown,
+        })
+    }
+}
+
+impl SFtpServer {
+    pub fn new(fs: impl FileSystem + 'static) -> Self {
+        Self { fs: Arc::new(fs) }
+    }
+
+    /// Serve one session until the client closes the stream
+    pub async fn serve<T>(&self, mut stream: T) -> Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let Some(init) = read_packet(&mut stream).await? else {
+            return Ok(());
+        };
+        let init = Buffer::from_slice(&init);
+        if init.take_u8() != Some(SSH_FXP_INIT) {
+            return builder::Protocol {
+                tip: "Expected SSH_FXP_INIT",
+            }
+            .fail();
+        }
+        match init.take_u32() {
+            Some(version) if version >= VERSION => {}
+            _ => {
+                return builder::Protocol {
+                    tip: "Unsupported SFtp version",
+                }
+                .fail()
+            }
+        }
+
+        let mut ext = Buffer::new();
+        for (name, version) in [
+            OPENSSH_SFTP_EXT_POSIX_RENAME,
+            OPENSSH_SFTP_EXT_STATVFS,
+            OPENSSH_SFTP_EXT_FSTATVFS,
+            OPENSSH_SFTP_EXT_HARDLINK,
+            OPENSSH_SFTP_EXT_FSYNC,
+            OPENSSH_SFTP_EXT_LSETSTAT,
+            OPENSSH_SFTP_EXT_LIMITS,
+            OPENSSH_SFTP_EXT_EXPAND_PATH,
+            OPENSSH_SFTP_EXT_COPY_DATA,
+            OPENSSH_SFTP_EXT_HOME_DIRECTORY,
+            OPENSSH_SFTP_EXT_USERS_GROUPS_BY_ID,
+        ] {
+            ext.put_one(name);
+            ext.put_one(version);
+        }
+        let version = make_buffer! {
+            u8: SSH_FXP_VERSION,
+            u32: VERSION,
+            bytes: ext,
+        };
+        stream.write_all(&version).await?;
+        stream.flush().await?;
+
+        let mut handles = Handles::default();
+        while let Some(packet) = read_packet(&mut stream).await? {
+            let reply = self.handle(&packet, &mut handles).await?;
+            stream.write_all(&reply).await?;
+            stream.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, packet: &[u8], handles: &mut Handles) -> Result<Buffer<Vec<u8>>> {
+        let (id, op) = {
+            let packet = Buffer::from_slice(packet);
+            let invalid_format = || Error::invalid_format("Invalid sftp request");
+            let code = packet.take_u8().ok_or_else(invalid_format)?;
+            let id = packet.take_u32().ok_or_else(invalid_format)?;
+            (id, Op::parse(code, &packet))
+        };
+
+        let Some(op) = op else {
+            return Ok(status(id, SSH_FX_BAD_MESSAGE, "Invalid sftp request"));
+        };
+        Ok(self
+            .execute(id, op, handles)
+            .await
+            .unwrap_or_else(|e| error_status(id, &e)))
+    }
+
+    async fn execute(&self, id: u32, op: Op, handles: &mut Handles) -> Result<Buffer<Vec<u8>>> {
+        let fs = &self.fs;
+        let ok = || Ok(status(id, SSH_FX_OK, ""));
+        match op {
+            Op::Open(path, flags, attrs) => {
+                let file = fs.open(&path, flags, &attrs).await?;
+                let handle = handles.insert(Handle::File { path, file })?;
+                Ok(make_buffer! {
+                    u8: SSH_FXP_HANDLE,
+                    u32: id,
+                    one: handle,
+                })
+            }
+            Op::Opendir(path) => {
+                let entries = fs.read_dir(&path).await?;
+                let handle = handles.insert(Handle::Dir(entries.into_iter()))?;
+                Ok(make_buffer! {
+                    u8: SSH_FXP_HANDLE,
+                    u32: id,
+                    one: handle,
+                })
+            }
+            Op::Close(handle) => {
+                handles.remove(&handle)?;
+                ok()
+            }
+            Op::Read(handle, offset, len) => {
+                let (_, file) = handles.file(&handle)?;
+                let data = file.read_at(offset, (len as usize).min(MAX_DATA)).await?;
+                if data.is_empty() {
+                    return Ok(status(id, SSH_FX_EOF, "End of file"));
+                }
+                Ok(make_buffer! {
+                    u8: SSH_FXP_DATA,
+                    u32: id,
+                    one: data,
+                })
+            }
+            Op::Write(handle, offset, data) => {
+                let (_, file) = handles.file(&handle)?;
+                file.write_at(offset, &data).await?;
+                ok()
+            }
+            Op::Readdir(handle) => {
+                let Handle::Dir(entries) = handles.get(&handle)? else {
+                    return Err(invalid_handle());
+                };
+                let mut batch: Vec<_> = entries.by_ref().take(READDIR_BATCH).collect();
+                if batch.is_empty() {
+                    return Ok(status(id, SSH_FX_EOF, "End of directory"));
+                }
+                for info in batch.iter_mut().filter(|info| info.longname.is_empty()) {
+                    info.longname = self.longname(info);
+                }
+                Ok(name(id, &batch))
+            }
+            Op::Stat(path) => Ok(attrs(id, &fs.stat(&path).await?)),
+            Op::Lstat(path) => Ok(attrs(id, &fs.lstat(&path).await?)),
+            Op::Fstat(handle) => {
+                let (_, file) = handles.file(&handle)?;
+                Ok(attrs(id, &file.stat().await?))
+            }
+            Op::Setstat(path, attrs) => fs.setstat(&path, &attrs).await.and_then(|_| ok()),
+            Op::Lsetstat(path, attrs) => fs.lsetstat(&path, &attrs).await.and_then(|_| ok()),
+            Op::Fsetstat(handle, attrs) => {
+                let (_, file) = handles.file(&handle)?;
+                file.setstat(&attrs).await?;
+                ok()
+            }
+            Op::Fsync(handle) => {
+                let (_, file) = handles.file(&handle)?;
+                file.sync().await?;
+                ok()
+            }
+            Op::Remove(path) => fs.remove(&path).await.and_then(|_| ok()),
+            Op::Mkdir(path, attrs) => fs.mkdir(&path, &attrs).await.and_then(|_| ok()),
+            Op::Rmdir(path) => fs.rmdir(&path).await.and_then(|_| ok()),
+            Op::Rename(old, new, overwrite) => {
+                fs.rename(&old, &new, overwrite).await.and_then(|_| ok())
+            }
+            Op::Symlink(path, target) => fs.symlink(&path, &target).await.and_then(|_| ok()),
+            Op::Hardlink(old, new) => fs.hardlink(&old, &new).await.and_then(|_| ok()),
+            Op::Readlink(path) => Ok(name_one(id, &fs.readlink(&path).await?)),
+            Op::Realpath(path) => Ok(name_one(id, &fs.realpath(&normalize(&path)).await?)),
+            Op::ExpandPath(path) => {
+                let path = match path.strip_prefix('~') {
+                    Some(rest) => {
+                        let (user, rest) = rest.split_once('/').unwrap_or((rest, ""));
+                        let home = fs.home_directory(user).await?;
+                        format!("{home}/{rest}")
+                    }
+                    None => path,
+                };
+                Ok(name_one(id, &fs.realpath(&normalize(&path)).await?))
+            }
+            Op::HomeDirectory(user) => Ok(name_one(id, &fs.home_directory(&user).await?)),
+            Op::Statvfs(path) => Ok(statvfs(id, &fs.statvfs(&path).await?)),
+            Op::Fstatvfs(handle) => {
+                let (path, _) = handles.file(&handle)?;
+                let path = path.to_string();
+                Ok(statvfs(id, &fs.statvfs(&path).await?))
+            }
+            Op::Limits => {
+                let limits = Limits {
+                    max_packet_len: MAX_PACKET as u64,
+                    max_read_len: MAX_DATA as u64,
+                    max_write_len: MAX_DATA as u64,
+                    max_open_handles: MAX_HANDLES as u64,
+                };
+                let mut data = Buffer::new();
+                limits.to_bytes(&mut data);
+                Ok(make_buffer! {
+                    u8: SSH_FXP_EXTENDED_REPLY,
+                    u32: id,
+                    bytes: data,
+                })
+            }
+            Op::CopyData {
+                read,
+                mut offset,
+                len,
+                write,
+                mut write_offset,
+            } => {
+                // 0 copies to the end of the file
+                let mut left = if len == 0 { u64::MAX } else { len };
+                while left > 0 {
+                    let (_, file) = handles.file(&read)?;
+                    let data = file
+                        .read_at(offset, left.min(MAX_DATA as u64) as usize)
+                        .await?;
+                    if data.is_empty() {
+                        break;
+                    }
+                    let (_, file) = handles.file(&write)?;
+                    file.write_at(write_offset, &data).await?;
+                    offset += data.len() as u64;
+                    write_offset += data.len() as u64;
+                    left -= data.len() as u64;
+                }
+                ok()
+            }
+            Op::UsersGroupsById(uids, gids) => {
+                let names = |names: Vec<Option<String>>| {
+                    let mut buffer = Buffer::new();
+                    for name in names {
+                        buffer.put_one(name.unwrap_or_default());
+                    }
+                    buffer
+                };
+                let users = names(uids.into_iter().map(|id| fs.user_name(id)).collect());
+                let groups = names(gids.into_iter().map(|id| fs.group_name(id)).collect());
+                Ok(make_buffer! {
+                    u8: SSH_FXP_EXTENDED_REPLY,
+                    u32: id,
+                    one: users,
+                    one: groups,
+                })
+            }
+            Op::Unknown => Ok(status(id, SSH_FX_OP_UNSUPPORTED, "Unsupported request")),
+        }
+    }
+
+    /// The `ls -l` line of an entry, as OpenSSH sends it
+    fn longname(&self, info: &FileInfo) -> String {
+        let attrs = &info.attrs;
+        let kind = match attrs.file_type.or(attrs.property.map(|p| p.file_type)) {
+            Some(FileType::Directory) => 'd',
+            Some(FileType::SymbolicLink) => 'l',
+            Some(FileType::CharacterDevice) => 'c',
+            Some(FileType::BlockDevice) => 'b',
+            Some(FileType::FIFO) => 'p',
+            Some(FileType::Socket) => 's',
+            _ => '-',
+        };
+        let mode = attrs.property.map_or(0, |p| p.permissions.bits());
+        let mut perms = String::from(kind);
+        for shift in [6, 3, 0] {
+            for (bit, c) in [(4, 'r'), (2, 'w'), (1, 'x')] {
+                perms.push(if mode >> shift & bit != 0 { c } else { '-' });
+            }
+        }
+
+        let (uid, gid) = attrs.user.map_or((0, 0), |u| (u.uid, u.gid));
+        let owner = self.fs.user_name(uid).unwrap_or_else(|| uid.to_string());
+        let group = self.fs.group_name(gid).unwrap_or_else(|| gid.to_string());
+        format!(
+            "{perms} {:>4} {owner:<8} {group:<8} {:>8} {} {}",
+            attrs.link_count.unwrap_or(1),
+            attrs.size.unwrap_or_default(),
+            date(attrs.time.map_or(0, |t| t.mtime)),
+            info.filename,
+        )
+    }
+}
+
+/// `None` when the client closed the stream
+async fn read_packet<T: AsyncRead + Unpin>(stream: &mut T) -> Result<Option<Vec<u8>>> {
+    let len = match stream.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_PACKET {
+        return Err(Error::invalid_format("sftp packet too long"));
+    }
+    let mut packet = vec![0; len];
+    stream.read_exact(&mut packet).await?;
+    Ok(Some(packet))
+}
+
+/// Resolve `.` and `..` without leaving `/`, a relative path starts at `/`
+pub(super) fn normalize(path: &str) -> String {
+    let mut parts = vec![];
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+fn status(id: u32, code: u32, msg: &str) -> Buffer<Vec<u8>> {
+    make_buffer! {
+        u8: SSH_FXP_STATUS,
+        u32: id,
+        u32: code,
+        one: msg,
+        one: "",
+    }
+}
+
+fn error_status(id: u32, e: &Error) -> Buffer<Vec<u8>> {
+    let code = match e {
+        Error::NoSuchFile { .. } => SSH_FX_NO_SUCH_FILE,
+        Error::PermissionDenied { .. } => SSH_FX_PERMISSION_DENIED,
+        Error::OpUnsupported { .. } => SSH_FX_OP_UNSUPPORTED,
+        Error::BadMessage { .. } | Error::InvalidFormat { .. } => SSH_FX_BAD_MESSAGE,
+        Error::IOError { source, .. } => match source.kind() {
+            std::io::ErrorKind::NotFound => SSH_FX_NO_SUCH_FILE,
+            std::io::ErrorKind::PermissionDenied => SSH_FX_PERMISSION_DENIED,
+            std::io::ErrorKind::Unsupported => SSH_FX_OP_UNSUPPORTED,
+            _ => SSH_FX_FAILURE,
+        },
+        _ => SSH_FX_FAILURE,
+    };
+    status(id, code, &e.to_string())
+}
+
+fn attrs(id: u32, attrs: &Attributes) -> Buffer<Vec<u8>> {
+    let attrs = attrs.to_buffer(VERSION);
+    make_buffer! {
+        u8: SSH_FXP_ATTRS,
+        u32: id,
+        bytes: attrs,
+    }
+}
+
+fn name(id: u32, infos: &[FileInfo]) -> Buffer<Vec<u8>> {
+    let mut names = Buffer::new();
+    names.put_u32(infos.len() as u32);
+    for info in infos {
+        names.put_one(&info.filename);
+        names.put_one(&info.longname);
+        info.attrs.to_bytes(&mut names, VERSION);
+    }
+    make_buffer! {
+        u8: SSH_FXP_NAME,
+        u32: id,
+        bytes: names,
+    }
+}
+
+// the reply of realpath and the like, a path without attributes
+fn name_one(id: u32, path: &str) -> Buffer<Vec<u8>> {
+    let info = FileInfo::new(path.to_string(), path.to_string(), Attributes::default());
+    name(id, &[info])
+}
+
+fn statvfs(id: u32, statvfs: &Statvfs) -> Buffer<Vec<u8>> {
+    let mut data = Buffer::new();
+    statvfs.to_bytes(&mut data);
+    make_buffer! {
+        u8: SSH_FXP_EXTENDED_REPLY,
+        u32: id,
+        bytes: data,
+    }
+}
+
+/// `Mon DD HH:MM` of a unix time in UTC
+fn date(secs: u32) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    format!(
+        "{} {:>2} {:02}:{:02}",
+        MONTHS[month as usize - 1],
+        day,
+        secs / 3600,
+        secs % 3600 / 60
+    )
+}
@@ -0,0 +1,650 @@
+//! Pipelined file transfer, keeps a window of READ/WRITE requests in flight like openssh `sftp`
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::fs::FileTimes;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use openssl::hash::{Hasher, MessageDigest};
+use snafu::OptionExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use super::progress::{Progress, Tracker};
+use super::{
+    Attributes, File, FileType, Message, OpenFlags, Packet, Permissions, Property, SFtp, Status,
+    SymlinkPolicy, Timestamp,
+};
+use crate::error::{builder, Result};
+use crate::rate::RateLimiter;
+use crate::ssh::buffer::Buffer;
+use crate::ssh::common::code::*;
+use crate::OReceiver;
+
+/// The request length when the server doesn't announce its limits, the same as openssh
+const DEFAULT_CHUNK: u64 = 32768;
+
+/// Asked for with `check-file`, in order of preference
+const CHECK_FILE_ALGORITHMS: &str = "sha256,sha512,sha384,sha224,sha1,md5";
+
+/// The options of a transfer, see `SFtp::transfer`
+#[derive(Clone)]
+pub struct Transfer<'a> {
+    pub(super) sftp: &'a SFtp,
+    pub(super) preserve: bool,
+    window: Option<usize>,
+    chunk: Option<u32>,
+    resume: bool,
+    verify: bool,
+    progress: Option<Arc<dyn Progress>>,
+    limiter: Option<RateLimiter>,
+    // only for directories, see `tree.rs`
+    pub(super) include: Vec<String>,
+    pub(super) exclude: Vec<String>,
+    pub(super) symlinks: SymlinkPolicy,
+    pub(super) mirror: bool,
+    pub(super) delete: bool,
+    pub(super) dry_run: bool,
+}
+
+impl SFtp {
+    pub fn transfer(&self) -> Transfer<'_> {
+        Transfer {
+            sftp: self,
+            preserve: false,
+            window: None,
+            chunk: None,
+            resume: false,
+            verify: false,
+            progress: None,
+            limiter: None,
+            include: vec![],
+            exclude: vec![],
+            symlinks: SymlinkPolicy::Preserve,
+            mirror: false,
+            delete: false,
+            dry_run: false,
+        }
+    }
+
+    /// Download `remote` into the local file `local`, returns the bytes transferred
+    pub async fn download(&self, remote: &str, local: impl AsRef<Path>) -> Result<u64> {
+        self.transfer().download(remote, local).await
+    }
+
+    /// Upload the local file `local` to `remote`, returns the bytes transferred without
+    /// the ones already there when resuming
+    pub async fn upload(&self, local: impl AsRef<Path>, remote: &str) -> Result<u64> {
+        self.transfer().upload(local, remote).await
+    }
+
+    pub async fn download_to<W>(&self, remote: &str, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        self.transfer().download_to(remote, writer).await
+    }
+
+    pub async fn upload_from<R>(&self, reader: &mut R, remote: &str) -> Result<u64>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        self.transfer().upload_from(reader, remote).await
+    }
+
+    pub(super) fn read_at(
+        &self,
+        file: &File,
+        offset: u64,
+        len: u32,
+    ) -> Result<OReceiver<Result<Packet>>> {
+        let buffer = make_buffer! {
+            u8: SSH_FXP_READ,
+            u32: Self::UNASSIGNED_ID,
+            one: &file.handle,
+            u64: offset,
+            u32: len,
+        };
+
+        self.send(buffer)
+    }
+
+    pub(super) fn write_at(
+        &self,
+        file: &File,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<OReceiver<Result<Packet>>> {
+        let buffer = make_buffer! {
+            u8: SSH_FXP_WRITE,
+            u32: Self::UNASSIGNED_ID,
+            one: &file.handle,
+            u64: offset,
+            one: data,
+        };
+
+        self.send(buffer)
+    }
+}
+
+impl Debug for Transfer<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transfer")
+            .field("preserve", &self.preserve)
+            .field("window", &self.window)
+            .field("chunk", &self.chunk)
+            .field("resume", &self.resume)
+            .field("verify", &self.verify)
+            .field("progress", &self.progress.is_some())
+            .field("limiter", &self.limiter)
+            .field("include", &self.include)
+            .field("exclude", &self.exclude)
+            .field("symlinks", &self.symlinks)
+            .field("mirror", &self.mirror)
+            .field("delete", &self.delete)
+            .field("dry_run", &self.dry_run)
+            .finish()
+    }
+}
+
+impl<'a> Transfer<'a> {
+    /// Copy the permissions and the access/modification times to the destination
+    pub fn preserve(mut self, preserve: bool) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// The most READ/WRITE requests in flight, `SFtp::max_outstanding` by default
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = Some(window.max(1));
+        self
+    }
+
+    /// The length of every READ/WRITE request, taken from `SFtp::limits` by default
+    pub fn chunk(mut self, len: u32) -> Self {
+        self.chunk = Some(len.max(1));
+        self
+    }
+
+    /// Continue from the end of a partial destination left by an earlier transfer,
+    /// one longer than the source is transferred again from the start
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// When resuming, compare the hash of what is already in the destination with the source
+    /// and start over if they differ. The server hashes its side with the `check-file` extension
+    /// if it has it, otherwise that part is read back and hashed locally
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Report the progress of every file to `progress`
+    pub fn progress(mut self, progress: Arc<dyn Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Cap the bandwidth of the transfer, in both directions. Share a limiter between transfers
+    /// to cap them together, the limit of the session still applies to uploads
+    pub fn rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Returns the bytes transferred, without the ones already there when resuming
+    pub async fn download(&self, remote: &str, local: impl AsRef<Path>) -> Result<u64> {
+        let file = self.sftp.open_file(remote, OpenFlags::READ, None).await?;
+
+        let res: Result<u64> = async {
+            let attrs = match self.preserve || self.resume || self.progress.is_some() {
+                true => Some(self.sftp.fstat(&file).await?),
+                false => None,
+            };
+
+            let mut local = tokio::fs::File::options()
+                .write(true)
+                .read(self.resume)
+                .create(true)
+                .truncate(!self.resume)
+                .open(local)
+                .await?;
+
+            let mut offset = 0;
+            if self.resume {
+                let partial = local.metadata().await?.len();
+                let size = attrs.as_ref().and_then(|attrs| attrs.size);
+                if size.is_some_and(|size| partial <= size)
+                    && self.same_prefix(&file, &mut local, partial).await?
+                {
+                    offset = partial;
+                } else {
+                    local.set_len(0).await?;
+                }
+                local.seek(SeekFrom::Start(offset)).await?;
+            }
+
+            let size = attrs.as_ref().and_then(|attrs| attrs.size);
+            let mut tracker = self.tracker(remote, offset, size);
+            let res = self
+                .download_file(&file, &mut local, offset, None, &mut tracker)
+                .await;
+            tracker.finish(&res);
+            let total = res?;
+
+            if let Some(attrs) = attrs.filter(|_| self.preserve) {
+                set_local_attributes(&local.into_std().await, &attrs)?;
+            }
+            Ok(total)
+        }
+        .await;
+
+        let close = self.sftp.close_file(file).await;
+        let total = res?;
+        close?;
+        Ok(total)
+    }
+
+    pub async fn download_to<W>(&self, remote: &str, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let file = self.sftp.open_file(remote, OpenFlags::READ, None).await?;
+
+        let res = async {
+            let size = match self.progress.is_some() {
+                true => self.sftp.fstat(&file).await?.size,
+                false => None,
+            };
+
+            let mut tracker = self.tracker(remote, 0, size);
+            let res = self
+                .download_file(&file, writer, 0, None, &mut tracker)
+                .await;
+            tracker.finish(&res);
+            res
+        }
+        .await;
+
+        let close = self.sftp.close_file(file).await;
+        let total = res?;
+        close?;
+        Ok(total)
+    }
+
+    /// Returns the bytes transferred, without the ones already there when resuming
+    pub async fn upload(&self, local: impl AsRef<Path>, remote: &str) -> Result<u64> {
+        let mut local = tokio::fs::File::open(local).await?;
+
+        let attrs = match self.preserve {
+            true => Some(local_attributes(&local.metadata().await?)),
+            false => None,
+        };
+        let permissions = attrs
+            .as_ref()
+            .and_then(|attrs| attrs.property)
+            .map(|property| property.permissions);
+
+        // the data is written at explicit offsets, the same as APPEND when resuming
+        let mut flags = OpenFlags::WRITE | OpenFlags::CREAT;
+        match self.resume {
+            true => flags |= OpenFlags::READ,
+            false => flags |= OpenFlags::TRUNC,
+        }
+        let file = self.sftp.open_file(remote, flags, permissions).await?;
+
+        let res: Result<u64> = async {
+            let mut offset = 0;
+            if self.resume {
+                let partial = self.sftp.fstat(&file).await?.size.unwrap_or_default();
+                let size = local.metadata().await?.len();
+                if partial <= size && self.same_prefix(&file, &mut local, partial).await? {
+                    offset = partial;
+                } else {
+                    let truncate = Attributes::new(Some(0), None, None, None, HashMap::new());
+                    self.sftp.setfstat(&file, &truncate).await?;
+                }
+                local.seek(SeekFrom::Start(offset)).await?;
+            }
+
+            let size = local.metadata().await?.len();
+            let mut tracker = self.tracker(remote, offset, Some(size));
+            let res = self
+                .upload_file(&mut local, &file, offset, &mut tracker)
+                .await;
+            tracker.finish(&res);
+            let total = res?;
+
+            if let Some(attrs) = attrs {
+                self.sftp.setfstat(&file, &attrs).await?;
+            }
+            Ok(total)
+        }
+        .await;
+
+        let close = self.sftp.close_file(file).await;
+        let total = res?;
+        close?;
+        Ok(total)
+    }
+
+    pub async fn upload_from<R>(&self, reader: &mut R, remote: &str) -> Result<u64>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let flags = OpenFlags::WRITE | OpenFlags::CREAT | OpenFlags::TRUNC;
+        let file = self.sftp.open_file(remote, flags, None).await?;
+
+        let mut tracker = self.tracker(remote, 0, None);
+        let res = self.upload_file(reader, &file, 0, &mut tracker).await;
+        tracker.finish(&res);
+
+        let close = self.sftp.close_file(file).await;
+        let total = res?;
+        close?;
+        Ok(total)
+    }
+
+    /// Read from `offset` until the end of the file, or only `len` bytes
+    async fn download_file<W>(
+        &self,
+        file: &File,
+        writer: &mut W,
+        mut offset: u64,
+        len: Option<u64>,
+        tracker: &mut Tracker,
+    ) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let chunk = self.chunk_len(true).await?;
+        let window = self.window_len();
+        let end = len.map(|len| offset + len);
+
+        // the replies are taken in the order of the file, whatever order the server answers in
+        let mut requests = VecDeque::with_capacity(window);
+        let mut total = 0;
+        let mut eof = false;
+
+        loop {
+            while !eof && requests.len() < window {
+                let len = match end {
+                    Some(end) => (end.saturating_sub(offset)).min(chunk as u64) as u32,
+                    None => chunk,
+                };
+                if len == 0 {
+                    break;
+                }
+                if let Some(limiter) = &self.limiter {
+                    limiter.acquire(len as usize).await;
+                }
+                requests.push_back((offset, len, self.sftp.read_at(file, offset, len)?));
+                offset += len as u64;
+            }
+
+            let Some((pos, len, reply)) = requests.pop_front() else {
+                break;
+            };
+
+            match SFtp::wait_for_packet(reply).await?.msg {
+                // anything after the end is dropped, the file may grow meanwhile
+                Message::Data(data) if !eof && !data.is_empty() => {
+                    if data.len() > len as usize {
+                        return builder::Protocol {
+                            tip: "Too much data received",
+                        }
+                        .fail();
+                    }
+
+                    writer.write_all(&data).await?;
+                    total += data.len() as u64;
+                    tracker.add(data.len() as u64);
+
+                    // a short read, the rest is asked for before anything after it
+                    let read = data.len() as u32;
+                    if read < len {
+                        let pos = pos + read as u64;
+                        let reply = self.sftp.read_at(file, pos, len - read)?;
+                        requests.push_front((pos, len - read, reply));
+                    }
+                }
+                Message::Data(_) => eof = true,
+                Message::Status { status, msg, .. } => match status {
+                    Status::Eof => eof = true,
+                    status => return status.no_ok_and_eof(msg),
+                },
+                _ => return builder::Protocol { tip: "Unknown msg" }.fail(),
+            }
+        }
+
+        writer.flush().await?;
+        Ok(total)
+    }
+
+    /// Write what is left in `reader` from `offset` on
+    async fn upload_file<R>(
+        &self,
+        reader: &mut R,
+        file: &File,
+        mut offset: u64,
+        tracker: &mut Tracker,
+    ) -> Result<u64>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let chunk = self.chunk_len(false).await?;
+        let window = self.window_len();
+
+        let mut requests = VecDeque::with_capacity(window);
+        let mut buf = vec![0; chunk as usize];
+        let mut total = 0;
+
+        loop {
+            let len = read_full(reader, &mut buf).await?;
+            if len == 0 {
+                break;
+            }
+
+            if requests.len() >= window {
+                if let Some((len, reply)) = requests.pop_front() {
+                    let _: () = SFtp::wait_for_status(reply, Status::no_eof).await?;
+                    tracker.add(len);
+                }
+            }
+
+            if let Some(limiter) = &self.limiter {
+                limiter.acquire(len).await;
+            }
+            let reply = self.sftp.write_at(file, offset, &buf[..len])?;
+            requests.push_back((len as u64, reply));
+            offset += len as u64;
+            total += len as u64;
+        }
+
+        for (len, reply) in requests {
+            let _: () = SFtp::wait_for_status(reply, Status::no_eof).await?;
+            tracker.add(len);
+        }
+
+        Ok(total)
+    }
+
+    /// Whether the first `len` bytes of the remote and the local file are the same,
+    /// always true without `verify`
+    async fn same_prefix(
+        &self,
+        file: &File,
+        local: &mut tokio::fs::File,
+        len: u64,
+    ) -> Result<bool> {
+        if !self.verify || len == 0 {
+            return Ok(true);
+        }
+
+        let (digest, remote) = match self.sftp.support_check_file() {
+            true => {
+                let (algorithm, hash) = self
+                    .sftp
+                    .check_file(file, CHECK_FILE_ALGORITHMS, 0, len)
+                    .await?;
+                let digest = digest(&algorithm).context(builder::Protocol {
+                    tip: format!("Unknown check file algorithm: {algorithm}"),
+                })?;
+                (digest, hash)
+            }
+            false => {
+                let mut remote = Digest::new(MessageDigest::sha256())?;
+                let read = self
+                    .download_file(file, &mut remote, 0, Some(len), &mut Tracker::none())
+                    .await?;
+                if read != len {
+                    return Ok(false);
+                }
+                (MessageDigest::sha256(), remote.finish()?)
+            }
+        };
+
+        let mut hash = Digest::new(digest)?;
+        local.seek(SeekFrom::Start(0)).await?;
+        let read = tokio::io::copy(&mut (&mut *local).take(len), &mut hash).await?;
+
+        Ok(read == len && hash.finish()? == remote)
+    }
+
+    async fn chunk_len(&self, read: bool) -> Result<u32> {
+        if let Some(len) = self.chunk {
+            return Ok(len);
+        }
+
+        let mut len = DEFAULT_CHUNK;
+        if self.sftp.support_limits() {
+            let limits = self.sftp.limits().await?;
+            let max = match read {
+                true => limits.max_read_len,
+                false => limits.max_write_len,
+            };
+            // 0 means no limit
+            if max != 0 {
+                len = max;
+            }
+        }
+        Ok(len.min(u32::MAX as u64) as u32)
+    }
+
+    fn tracker(&self, path: &str, done: u64, total: Option<u64>) -> Tracker {
+        Tracker::new(self.progress.clone(), path, done, total)
+    }
+
+    fn window_len(&self) -> usize {
+        self.window.unwrap_or_else(|| self.sftp.max_outstanding())
+    }
+}
+
+fn digest(algorithm: &str) -> Option<MessageDigest> {
+    match algorithm {
+        "md5" => Some(MessageDigest::md5()),
+        "sha1" => Some(MessageDigest::sha1()),
+        "sha224" => Some(MessageDigest::sha224()),
+        "sha256" => Some(MessageDigest::sha256()),
+        "sha384" => Some(MessageDigest::sha384()),
+        "sha512" => Some(MessageDigest::sha512()),
+        _ => None,
+    }
+}
+
+/// Hashes whatever is written to it
+struct Digest(Hasher);
+
+impl Digest {
+    fn new(digest: MessageDigest) -> Result<Self> {
+        Ok(Self(Hasher::new(digest)?))
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>> {
+        Ok(self.0.finish()?.to_vec())
+    }
+}
+
+impl AsyncWrite for Digest {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.update(buf).map_err(io::Error::other)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Read until `buf` is full or the reader is exhausted
+async fn read_full<R>(reader: &mut R, buf: &mut [u8]) -> Result<usize>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let mut len = 0;
+    while len < buf.len() {
+        let read = reader.read(&mut buf[len..]).await?;
+        if read == 0 {
+            break;
+        }
+        len += read;
+    }
+    Ok(len)
+}
+
+fn to_unix_time(time: std::io::Result<SystemTime>) -> u32 {
+    time.ok()
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|time| time.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+fn local_attributes(metadata: &std::fs::Metadata) -> Attributes {
+    #[cfg(unix)]
+    let property = {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = Permissions::from_bits_truncate(metadata.permissions().mode());
+        Some(Property::new(permissions, FileType::RegularFile))
+    };
+    #[cfg(not(unix))]
+    let property = None;
+
+    let time = Timestamp::new(
+        to_unix_time(metadata.accessed()),
+        to_unix_time(metadata.modified()),
+    );
+
+    Attributes::new(None, None, property, Some(time), HashMap::new())
+}
+
+fn set_local_attributes(file: &std::fs::File, attrs: &Attributes) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(property) = attrs.property {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = std::fs::Permissions::from_mode(property.permissions.bits());
+        file.set_permissions(permissions)?;
+    }
+
+    if let Some(Timestamp { atime, mtime }) = attrs.time {
+        let at = |secs: u32| SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64);
+        let times = FileTimes::new()
+            .set_accessed(at(atime))
+            .set_modified(at(mtime));
+        file.set_times(times)?;
+    }
+    Ok(())
+}
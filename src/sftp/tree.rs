@@ -0,0 +1,561 @@
+//! Recursive transfer of a directory, optionally as a mirror of the source
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+
+use snafu::OptionExt;
+
+use super::{Attributes, FileType, Permissions, SFtp, Transfer};
+use crate::error::{builder, Error, Result};
+
+/// What happens to a symbolic link in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Transfer what it points to, a link to a directory is walked into
+    Follow,
+    /// Recreate the link with the same target
+    #[default]
+    Preserve,
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeAction {
+    /// The directory doesn't exist in the destination
+    CreateDir,
+    /// `size` is the size of the source
+    Copy {
+        size: u64,
+    },
+    /// The size and the modification time are the same, only in mirror mode
+    Unchanged,
+    Symlink {
+        target: String,
+    },
+    /// Not in the source, only in mirror mode with `delete`
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    /// Relative to the roots, separated by `/`, empty for the root itself
+    pub path: String,
+    pub action: TreeAction,
+}
+
+/// What was done, or only would be done with `dry_run`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeReport {
+    pub dry_run: bool,
+    pub entries: Vec<TreeEntry>,
+}
+
+impl TreeReport {
+    /// The bytes copied
+    pub fn bytes(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| match entry.action {
+                TreeAction::Copy { size } => size,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl SFtp {
+    /// Upload the local directory `local` into `remote` recursively
+    pub async fn upload_dir(&self, local: impl AsRef<Path>, remote: &str) -> Result<TreeReport> {
+        self.transfer().upload_dir(local, remote).await
+    }
+
+    /// Download the directory `remote` into `local` recursively
+    pub async fn download_dir(&self, remote: &str, local: impl AsRef<Path>) -> Result<TreeReport> {
+        self.transfer().download_dir(remote, local).await
+    }
+}
+
+impl<'a> Transfer<'a> {
+    /// Only transfer the files matching one of the patterns, every directory is still walked.
+    ///
+    /// `*` and `?` don't match `/`, `**` does. A pattern with a `/` is matched against the path
+    /// relative to the root, otherwise against the name
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Leave out the files and directories matching the pattern, see `include`.
+    /// In mirror mode an excluded file in the destination is not deleted either
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Only copy the files whose size or modification time differ from the destination,
+    /// the times are always preserved in this mode so the next run can compare them
+    pub fn mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// Delete what is in the destination but not in the source, only in mirror mode
+    pub fn delete(mut self, delete: bool) -> Self {
+        self.delete = delete;
+        self
+    }
+
+    /// Only report what would be done
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub async fn upload_dir(&self, local: impl AsRef<Path>, remote: &str) -> Result<TreeReport> {
+        let local = path_to_str(local.as_ref())?;
+        let remote_tree = Remote(self.sftp);
+        self.transfer_dir(&Local, local, &remote_tree, remote, true)
+            .await
+    }
+
+    pub async fn download_dir(&self, remote: &str, local: impl AsRef<Path>) -> Result<TreeReport> {
+        let local = path_to_str(local.as_ref())?;
+        let remote_tree = Remote(self.sftp);
+        self.transfer_dir(&remote_tree, remote, &Local, local, false)
+            .await
+    }
+
+    async fn transfer_dir(
+        &self,
+        src: &dyn Tree,
+        src_root: &str,
+        dst: &dyn Tree,
+        dst_root: &str,
+        upload: bool,
+    ) -> Result<TreeReport> {
+        let copy = self.clone().preserve(self.preserve || self.mirror);
+
+        let mut report = TreeReport {
+            dry_run: self.dry_run,
+            entries: vec![],
+        };
+        let mut push = |path: &str, action| {
+            report.entries.push(TreeEntry {
+                path: path.to_string(),
+                action,
+            })
+        };
+
+        // the directories already walked when following links, to not go round in circles
+        let mut visited = HashSet::new();
+        if self.symlinks == SymlinkPolicy::Follow {
+            visited.insert(src.canonicalize(src_root).await?);
+        }
+
+        // with whether the destination is known to have nothing there
+        let mut dirs = vec![(String::new(), false)];
+        while let Some((dir, absent)) = dirs.pop() {
+            let src_dir = join(src_root, &dir);
+            let dst_dir = join(dst_root, &dir);
+
+            let mut nodes = src.list(&src_dir).await?.context(builder::NoSuchFile {
+                tip: format!("No such directory: {src_dir}"),
+            })?;
+            nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let existing = match absent {
+                true => None,
+                false => dst.list(&dst_dir).await?,
+            };
+            let existing: HashMap<String, Node> = match existing {
+                Some(existing) => existing.into_iter().map(|n| (n.name.clone(), n)).collect(),
+                None => {
+                    push(&dir, TreeAction::CreateDir);
+                    if !self.dry_run {
+                        dst.mkdir(&dst_dir).await?;
+                    }
+                    HashMap::new()
+                }
+            };
+
+            for mut node in nodes.iter().cloned() {
+                let path = join(&dir, &node.name);
+                if self.excluded(&path, &node.name) {
+                    continue;
+                }
+
+                let src_path = join(&src_dir, &node.name);
+                let dst_path = join(&dst_dir, &node.name);
+                let current = existing.get(&node.name);
+
+                if node.kind == Kind::Symlink {
+                    match self.symlinks {
+                        SymlinkPolicy::Skip => continue,
+                        SymlinkPolicy::Preserve => {
+                            if !self.included(&path, &node.name) {
+                                continue;
+                            }
+                            let target = src.readlink(&src_path).await?;
+                            if let Some(current) = current {
+                                if self.mirror
+                                    && current.kind == Kind::Symlink
+                                    && dst.readlink(&dst_path).await? == target
+                                {
+                                    push(&path, TreeAction::Unchanged);
+                                    continue;
+                                }
+                                if !self.dry_run {
+                                    dst.remove(&dst_path, current.kind).await?;
+                                }
+                            }
+                            if !self.dry_run {
+                                dst.symlink(&dst_path, &target).await?;
+                            }
+                            push(&path, TreeAction::Symlink { target });
+                            continue;
+                        }
+                        SymlinkPolicy::Follow => match src.stat(&src_path).await {
+                            Ok(Some(target)) => node.kind_of(target),
+                            // a dangling link
+                            Ok(None) => continue,
+                            Err(e) => return Err(e),
+                        },
+                    }
+                }
+
+                match node.kind {
+                    Kind::Dir => {
+                        if self.symlinks == SymlinkPolicy::Follow
+                            && !visited.insert(src.canonicalize(&src_path).await?)
+                        {
+                            continue;
+                        }
+                        // a file or a link in the way makes room for the directory
+                        let replaced = current.filter(|c| c.kind != Kind::Dir);
+                        if let Some(current) = replaced {
+                            if !self.dry_run {
+                                dst.remove(&dst_path, current.kind).await?;
+                            }
+                        }
+                        dirs.push((path, replaced.is_some()));
+                    }
+                    Kind::File => {
+                        if !self.included(&path, &node.name) {
+                            continue;
+                        }
+                        let unchanged = current.is_some_and(|current| {
+                            current.kind == Kind::File
+                                && current.size == node.size
+                                && current.mtime == node.mtime
+                        });
+                        if self.mirror && unchanged {
+                            push(&path, TreeAction::Unchanged);
+                            continue;
+                        }
+                        if !self.dry_run {
+                            if let Some(current) = current.filter(|c| c.kind != Kind::File) {
+                                dst.remove(&dst_path, current.kind).await?;
+                            }
+                            match upload {
+                                true => copy.upload(&src_path, &dst_path).await?,
+                                false => copy.download(&src_path, &dst_path).await?,
+                            };
+                        }
+                        push(&path, TreeAction::Copy { size: node.size });
+                    }
+                    Kind::Symlink | Kind::Other => {}
+                }
+            }
+
+            if !(self.mirror && self.delete) {
+                continue;
+            }
+
+            let names: HashSet<&str> = nodes.iter().map(|n| n.name.as_str()).collect();
+            let mut extraneous: Vec<&Node> = existing
+                .values()
+                .filter(|n| !names.contains(n.name.as_str()))
+                .filter(|n| !self.excluded(&join(&dir, &n.name), &n.name))
+                .collect();
+            extraneous.sort_by(|a, b| a.name.cmp(&b.name));
+
+            for node in extraneous {
+                if !self.dry_run {
+                    dst.remove(&join(&dst_dir, &node.name), node.kind).await?;
+                }
+                push(&join(&dir, &node.name), TreeAction::Delete);
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn included(&self, path: &str, name: &str) -> bool {
+        self.include.is_empty() || self.include.iter().any(|p| matches(p, path, name))
+    }
+
+    fn excluded(&self, path: &str, name: &str) -> bool {
+        self.exclude.iter().any(|p| matches(p, path, name))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    name: String,
+    kind: Kind,
+    size: u64,
+    mtime: u32,
+}
+
+impl Node {
+    fn from_attributes(name: String, attrs: &Attributes) -> Self {
+        let kind = match attrs.file_type {
+            Some(FileType::RegularFile) => Kind::File,
+            Some(FileType::Directory) => Kind::Dir,
+            Some(FileType::SymbolicLink) => Kind::Symlink,
+            _ => Kind::Other,
+        };
+
+        Self {
+            name,
+            kind,
+            size: attrs.size.unwrap_or_default(),
+            mtime: attrs.time.map(|t| t.mtime).unwrap_or_default(),
+        }
+    }
+
+    fn from_metadata(name: String, metadata: &std::fs::Metadata) -> Self {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            Kind::Symlink
+        } else if file_type.is_dir() {
+            Kind::Dir
+        } else if file_type.is_file() {
+            Kind::File
+        } else {
+            Kind::Other
+        };
+
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|time| time.as_secs() as u32)
+            .unwrap_or_default();
+
+        Self {
+            name,
+            kind,
+            size: metadata.len(),
+            mtime,
+        }
+    }
+
+    /// Take what a followed link points to, keeping the name of the link
+    fn kind_of(&mut self, target: Node) {
+        self.kind = target.kind;
+        self.size = target.size;
+        self.mtime = target.mtime;
+    }
+}
+
+/// One side of a transfer, the local file system or the server
+#[async_trait::async_trait]
+trait Tree: Send + Sync {
+    /// The entries of a directory without `.` and `..`, `None` if it doesn't exist
+    async fn list(&self, dir: &str) -> Result<Option<Vec<Node>>>;
+
+    /// Follows links, `None` if the path doesn't exist
+    async fn stat(&self, path: &str) -> Result<Option<Node>>;
+
+    async fn canonicalize(&self, path: &str) -> Result<String>;
+
+    async fn readlink(&self, path: &str) -> Result<String>;
+
+    async fn symlink(&self, path: &str, target: &str) -> Result<()>;
+
+    async fn mkdir(&self, path: &str) -> Result<()>;
+
+    /// A directory is removed with everything in it
+    async fn remove(&self, path: &str, kind: Kind) -> Result<()>;
+}
+
+struct Local;
+
+#[async_trait::async_trait]
+impl Tree for Local {
+    async fn list(&self, dir: &str) -> Result<Option<Vec<Node>>> {
+        let mut entries = match tokio::fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut nodes = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let name = path_to_str(Path::new(&entry.file_name()))?.to_string();
+            let metadata = tokio::fs::symlink_metadata(entry.path()).await?;
+            nodes.push(Node::from_metadata(name, &metadata));
+        }
+        Ok(Some(nodes))
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<Node>> {
+        match tokio::fs::metadata(path).await {
+            Ok(metadata) => Ok(Some(Node::from_metadata(String::new(), &metadata))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn canonicalize(&self, path: &str) -> Result<String> {
+        let path = tokio::fs::canonicalize(path).await?;
+        Ok(path_to_str(&path)?.to_string())
+    }
+
+    async fn readlink(&self, path: &str) -> Result<String> {
+        let target = tokio::fs::read_link(path).await?;
+        Ok(path_to_str(&target)?.to_string())
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> Result<()> {
+        #[cfg(unix)]
+        {
+            tokio::fs::symlink(target, path).await?;
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (path, target);
+            builder::OpUnsupported {
+                tip: "Symbolic links are only supported on unix",
+            }
+            .fail()
+        }
+    }
+
+    async fn mkdir(&self, path: &str) -> Result<()> {
+        tokio::fs::create_dir(path).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, path: &str, kind: Kind) -> Result<()> {
+        match kind {
+            Kind::Dir => tokio::fs::remove_dir_all(path).await?,
+            _ => tokio::fs::remove_file(path).await?,
+        }
+        Ok(())
+    }
+}
+
+struct Remote<'a>(&'a SFtp);
+
+#[async_trait::async_trait]
+impl Tree for Remote<'_> {
+    async fn list(&self, dir: &str) -> Result<Option<Vec<Node>>> {
+        let infos = match self.0.list(dir).await {
+            Ok(infos) => infos,
+            Err(Error::NoSuchFile { .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let nodes = infos
+            .into_iter()
+            .map(|info| Node::from_attributes(info.filename, &info.attrs))
+            .collect();
+        Ok(Some(nodes))
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<Node>> {
+        match self.0.stat(path).await {
+            Ok(attrs) => Ok(Some(Node::from_attributes(String::new(), &attrs))),
+            Err(Error::NoSuchFile { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn canonicalize(&self, path: &str) -> Result<String> {
+        self.0.realpath(path).await
+    }
+
+    async fn readlink(&self, path: &str) -> Result<String> {
+        Ok(self.0.readlink(path).await?.filename)
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> Result<()> {
+        self.0.symlink(path, target).await
+    }
+
+    async fn mkdir(&self, path: &str) -> Result<()> {
+        self.0.mkdir(path, Permissions::p0755()).await
+    }
+
+    async fn remove(&self, path: &str, kind: Kind) -> Result<()> {
+        if kind != Kind::Dir {
+            return self.0.remove_file(path).await;
+        }
+
+        self.0.remove_dir_all(path).await
+    }
+}
+
+fn path_to_str(path: &Path) -> Result<&str> {
+    path.to_str().context(builder::InvalidArgument {
+        tip: format!("Not an utf8 path: {}", path.display()),
+    })
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if name.is_empty() {
+        dir.to_string()
+    } else if dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+fn matches(pattern: &str, path: &str, name: &str) -> bool {
+    match pattern.contains('/') {
+        true => glob(pattern.as_bytes(), path.as_bytes()),
+        false => glob(pattern.as_bytes(), name.as_bytes()),
+    }
+}
+
+pub(super) fn glob(pattern: &[u8], path: &[u8]) -> bool {
+    match (pattern, path.first()) {
+        ([], None) => true,
+        // `**/` matches no directory too, otherwise whole ones
+        ([b'*', b'*', b'/', rest @ ..], _) => {
+            glob(rest, path)
+                || path
+                    .iter()
+                    .position(|c| *c == b'/')
+                    .is_some_and(|pos| glob(pattern, &path[pos + 1..]))
+        }
+        ([b'*', b'*', rest @ ..], _) => {
+            glob(rest, path) || (!path.is_empty() && glob(pattern, &path[1..]))
+        }
+        ([b'*', rest @ ..], c) => {
+            glob(rest, path) || (c.is_some_and(|c| *c != b'/') && glob(pattern, &path[1..]))
+        }
+        ([b'?', rest @ ..], Some(c)) if *c != b'/' => glob(rest, &path[1..]),
+        ([p, rest @ ..], Some(c)) if p == c => glob(rest, &path[1..]),
+        _ => false,
+    }
+}
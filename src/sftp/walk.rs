@@ -0,0 +1,257 @@
+//! Recursive listing of a remote tree, optionally filtered by glob patterns
+
+use std::collections::VecDeque;
+
+use super::tree::glob;
+use super::{Attributes, FileInfo, FileType, ReadDir, SFtp};
+use crate::error::Result;
+
+/// A recursive listing of a directory, see `SFtp::walk`.
+///
+/// The directory itself isn't listed, its entries are at depth 1. A directory is
+/// listed before the entries in it, one that can't be read makes `next_entry` fail
+/// once and the walk goes on with the rest
+pub struct Walk<'a> {
+    sftp: &'a SFtp,
+    root: String,
+    min_depth: usize,
+    max_depth: usize,
+    follow_links: bool,
+    sort: bool,
+    patterns: Vec<String>,
+    started: bool,
+    stack: Vec<Frame>,
+}
+
+/// An entry of a walk
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    /// The walked directory joined with the names down to the entry
+    pub path: String,
+    pub depth: usize,
+    /// Of the link itself unless links are followed
+    pub attrs: Attributes,
+}
+
+impl WalkEntry {
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or_default()
+    }
+
+    pub fn file_type(&self) -> Option<FileType> {
+        self.attrs.file_type
+    }
+}
+
+/// A directory being listed
+struct Frame {
+    dir: String,
+    // the path with the links resolved, only kept when links are followed
+    real: Option<String>,
+    // of the entries in it
+    depth: usize,
+    // `None` until opened
+    entries: Option<Entries>,
+}
+
+enum Entries {
+    Stream(ReadDir),
+    Sorted(VecDeque<FileInfo>),
+}
+
+impl SFtp {
+    pub fn walk(&self, root: &str) -> Walk<'_> {
+        Walk {
+            sftp: self,
+            root: root.to_string(),
+            min_depth: 1,
+            max_depth: usize::MAX,
+            follow_links: false,
+            sort: false,
+            patterns: vec![],
+            started: false,
+            stack: vec![],
+        }
+    }
+
+    /// Walk the paths matching `pattern`, `*` and `?` stay in a name and `**` crosses
+    /// directories, e.g. `/var/log/**/*.gz`. The walk starts at the part before the
+    /// first wildcard and stops as deep as the pattern goes without `**`
+    pub fn glob(&self, pattern: &str) -> Walk<'_> {
+        let names: Vec<_> = pattern.split('/').collect();
+        let literal = names
+            .iter()
+            .position(|name| name.contains(['*', '?']))
+            .unwrap_or(names.len().saturating_sub(1));
+
+        let root = match names[..literal].join("/") {
+            root if root.is_empty() && pattern.starts_with('/') => "/".to_string(),
+            root => root,
+        };
+        let mut walk = self.walk(&root).pattern(pattern);
+        if !pattern.contains("**") {
+            walk.max_depth = names.len() - literal;
+        }
+        walk
+    }
+}
+
+impl<'a> Walk<'a> {
+    /// Entries above `depth` aren't returned, the walk still goes through them
+    pub fn min_depth(mut self, depth: usize) -> Self {
+        self.min_depth = depth;
+        self
+    }
+
+    /// Directories at `depth` aren't entered
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Enter the directories symbolic links point to, a link back to a directory
+    /// being walked is returned but not entered
+    pub fn follow_links(mut self, follow: bool) -> Self {
+        self.follow_links = follow;
+        self
+    }
+
+    /// Every directory is read whole and its entries returned by name
+    pub fn sort(mut self, sort: bool) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Only return the paths matching one of the patterns, see `SFtp::glob`.
+    /// A pattern is matched against `WalkEntry::path`
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// `None` when the walk is over
+    pub async fn next_entry(&mut self) -> Result<Option<WalkEntry>> {
+        if !self.started {
+            self.started = true;
+            let real = match self.follow_links {
+                true => Some(self.sftp.realpath(Self::open_path(&self.root)).await?),
+                false => None,
+            };
+            self.stack.push(Frame {
+                dir: self.root.clone(),
+                real,
+                depth: 1,
+                entries: None,
+            });
+        }
+
+        loop {
+            let Some(frame) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+
+            let entries = match &mut frame.entries {
+                Some(entries) => entries,
+                None => match Self::open(self.sftp, &frame.dir, self.sort).await {
+                    Ok(entries) => frame.entries.insert(entries),
+                    Err(e) => {
+                        self.stack.pop();
+                        return Err(e);
+                    }
+                },
+            };
+            let info = match entries {
+                Entries::Stream(read_dir) => read_dir.next_entry().await,
+                Entries::Sorted(infos) => Ok(infos.pop_front()),
+            };
+            let info = match info {
+                Ok(Some(info)) => info,
+                Ok(None) => {
+                    self.stack.pop();
+                    continue;
+                }
+                Err(e) => {
+                    self.stack.pop();
+                    return Err(e);
+                }
+            };
+
+            let path = join(&frame.dir, &info.filename);
+            let depth = frame.depth;
+            let mut real = frame.real.as_ref().map(|real| join(real, &info.filename));
+            let mut attrs = info.attrs;
+
+            let mut enter = attrs.file_type == Some(FileType::Directory);
+            if self.follow_links && attrs.file_type == Some(FileType::SymbolicLink) {
+                // a dangling link stays a link
+                if let Ok(target) = self.sftp.stat(&path).await {
+                    if target.file_type == Some(FileType::Directory) {
+                        let target_real = self.sftp.realpath(&path).await?;
+                        enter = !self
+                            .stack
+                            .iter()
+                            .any(|frame| frame.real.as_ref() == Some(&target_real));
+                        real = Some(target_real);
+                    }
+                    attrs = target;
+                }
+            }
+
+            if enter && depth < self.max_depth {
+                self.stack.push(Frame {
+                    dir: path.clone(),
+                    real,
+                    depth: depth + 1,
+                    entries: None,
+                });
+            }
+
+            if depth >= self.min_depth && self.matches(&path) {
+                return Ok(Some(WalkEntry { path, depth, attrs }));
+            }
+        }
+    }
+
+    /// Every entry left
+    pub async fn collect(mut self) -> Result<Vec<WalkEntry>> {
+        let mut entries = vec![];
+        while let Some(entry) = self.next_entry().await? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    async fn open(sftp: &SFtp, dir: &str, sort: bool) -> Result<Entries> {
+        let dir = Self::open_path(dir);
+        if !sort {
+            return Ok(Entries::Stream(sftp.list_dir(dir).await?));
+        }
+        let mut infos = sftp.list(dir).await?;
+        infos.sort_by(|a, b| a.filename.cmp(&b.filename));
+        Ok(Entries::Sorted(infos.into()))
+    }
+
+    // the paths under `` are returned without a leading `./`
+    fn open_path(dir: &str) -> &str {
+        match dir {
+            "" => ".",
+            dir => dir,
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.patterns.is_empty()
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| glob(pattern.as_bytes(), path.as_bytes()))
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "" => name.to_string(),
+        dir if dir.ends_with('/') => format!("{dir}{name}"),
+        dir => format!("{dir}/{name}"),
+    }
+}
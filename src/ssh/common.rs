@@ -105,9 +105,10 @@ pub mod code {
     pub const SSH_FXP_RENAME: u8 = 18;
     pub const SSH_FXP_READLINK: u8 = 19;
     pub const SSH_FXP_SYMLINK: u8 = 20;
-    // pub const SSH_FXP_LINK: u8 = 21;
-    // pub const SSH_FXP_BLOCK: u8 = 22;
-    // pub const SSH_FXP_UNBLOCK: u8 = 23;
+    // version 6
+    pub const SSH_FXP_LINK: u8 = 21;
+    pub const SSH_FXP_BLOCK: u8 = 22;
+    pub const SSH_FXP_UNBLOCK: u8 = 23;
 
     pub const SSH_FXP_STATUS: u8 = 101;
     pub const SSH_FXP_HANDLE: u8 = 102;
@@ -118,25 +119,26 @@ pub mod code {
     pub const SSH_FXP_EXTENDED: u8 = 200;
     pub const SSH_FXP_EXTENDED_REPLY: u8 = 201;
 
-    // pub const SSH_FXF_ACCESS_DISPOSITION: u32 = 0x00000007;
-    // pub const SSH_FXF_CREATE_NEW: u32 = 0x00000000;
-    // pub const SSH_FXF_CREATE_TRUNCATE: u32 = 0x00000001;
-    // pub const SSH_FXF_OPEN_EXISTING: u32 = 0x00000002;
-    // pub const SSH_FXF_OPEN_OR_CREATE: u32 = 0x00000003;
-    // pub const SSH_FXF_TRUNCATE_EXISTING: u32 = 0x00000004;
-    // pub const SSH_FXF_APPEND_DATA: u32 = 0x00000008;
-    // pub const SSH_FXF_APPEND_DATA_ATOMIC: u32 = 0x00000010;
-    // pub const SSH_FXF_TEXT_MODE: u32 = 0x00000020;
-    // pub const SSH_FXF_BLOCK_READ: u32 = 0x00000040;
-    // pub const SSH_FXF_BLOCK_WRITE: u32 = 0x00000080;
-    // pub const SSH_FXF_BLOCK_DELETE: u32 = 0x00000100;
-    // pub const SSH_FXF_BLOCK_ADVISORY: u32 = 0x00000200;
-    // pub const SSH_FXF_NOFOLLOW: u32 = 0x00000400;
-    // pub const SSH_FXF_DELETE_ON_CLOSE: u32 = 0x00000800;
-    // pub const SSH_FXF_ACCESS_AUDIT_ALARM_INFO: u32 = 0x00001000;
-    // pub const SSH_FXF_ACCESS_BACKUP: u32 = 0x00002000;
-    // pub const SSH_FXF_BACKUP_STREAM: u32 = 0x00004000;
-    // pub const SSH_FXF_OVERRIDE_OWNER: u32 = 0x00008000;
+    // https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-13#section-8.1.1.3, version 5 on
+    pub const SSH_FXF_ACCESS_DISPOSITION: u32 = 0x00000007;
+    pub const SSH_FXF_CREATE_NEW: u32 = 0x00000000;
+    pub const SSH_FXF_CREATE_TRUNCATE: u32 = 0x00000001;
+    pub const SSH_FXF_OPEN_EXISTING: u32 = 0x00000002;
+    pub const SSH_FXF_OPEN_OR_CREATE: u32 = 0x00000003;
+    pub const SSH_FXF_TRUNCATE_EXISTING: u32 = 0x00000004;
+    pub const SSH_FXF_APPEND_DATA: u32 = 0x00000008;
+    pub const SSH_FXF_APPEND_DATA_ATOMIC: u32 = 0x00000010;
+    pub const SSH_FXF_TEXT_MODE: u32 = 0x00000020;
+    pub const SSH_FXF_BLOCK_READ: u32 = 0x00000040;
+    pub const SSH_FXF_BLOCK_WRITE: u32 = 0x00000080;
+    pub const SSH_FXF_BLOCK_DELETE: u32 = 0x00000100;
+    pub const SSH_FXF_BLOCK_ADVISORY: u32 = 0x00000200;
+    pub const SSH_FXF_NOFOLLOW: u32 = 0x00000400;
+    pub const SSH_FXF_DELETE_ON_CLOSE: u32 = 0x00000800;
+    pub const SSH_FXF_ACCESS_AUDIT_ALARM_INFO: u32 = 0x00001000;
+    pub const SSH_FXF_ACCESS_BACKUP: u32 = 0x00002000;
+    pub const SSH_FXF_BACKUP_STREAM: u32 = 0x00004000;
+    pub const SSH_FXF_OVERRIDE_OWNER: u32 = 0x00008000;
 
     pub const SSH_FXF_READ: u32 = 0x00000001;
     pub const SSH_FXF_WRITE: u32 = 0x00000002;
@@ -144,6 +146,19 @@ pub mod code {
     pub const SSH_FXF_CREAT: u32 = 0x00000008;
     pub const SSH_FXF_TRUNC: u32 = 0x00000010;
     pub const SSH_FXF_EXCL: u32 = 0x00000020;
+    // version 4 only, `SSH_FXF_TEXT_MODE` after it
+    pub const SSH_FXF_TEXT: u32 = 0x00000040;
+
+    // https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-13#section-6.6
+    pub const ACE4_READ_DATA: u32 = 0x00000001;
+    pub const ACE4_WRITE_DATA: u32 = 0x00000002;
+    pub const ACE4_APPEND_DATA: u32 = 0x00000004;
+    pub const ACE4_READ_ATTRIBUTES: u32 = 0x00000080;
+    pub const ACE4_WRITE_ATTRIBUTES: u32 = 0x00000100;
+
+    pub const SSH_FXF_RENAME_OVERWRITE: u32 = 0x00000001;
+    pub const SSH_FXF_RENAME_ATOMIC: u32 = 0x00000002;
+    pub const SSH_FXF_RENAME_NATIVE: u32 = 0x00000004;
 
     pub const SSH_FILEXFER_ATTR_SIZE: u32 = 0x00000001;
     pub const SSH_FILEXFER_ATTR_UIDGID: u32 = 0x00000002;
@@ -151,6 +166,33 @@ pub mod code {
     pub const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x00000008;
     pub const SSH_FILEXFER_ATTR_EXTENDED: u32 = 0x80000000;
 
+    // https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-13#section-7.1, version 4 on
+    pub const SSH_FILEXFER_ATTR_ACCESSTIME: u32 = 0x00000008;
+    pub const SSH_FILEXFER_ATTR_CREATETIME: u32 = 0x00000010;
+    pub const SSH_FILEXFER_ATTR_MODIFYTIME: u32 = 0x00000020;
+    pub const SSH_FILEXFER_ATTR_ACL: u32 = 0x00000040;
+    pub const SSH_FILEXFER_ATTR_OWNERGROUP: u32 = 0x00000080;
+    pub const SSH_FILEXFER_ATTR_SUBSECOND_TIMES: u32 = 0x00000100;
+    // version 5 on
+    pub const SSH_FILEXFER_ATTR_BITS: u32 = 0x00000200;
+    // version 6
+    pub const SSH_FILEXFER_ATTR_ALLOCATION_SIZE: u32 = 0x00000400;
+    pub const SSH_FILEXFER_ATTR_TEXT_HINT: u32 = 0x00000800;
+    pub const SSH_FILEXFER_ATTR_MIME_TYPE: u32 = 0x00001000;
+    pub const SSH_FILEXFER_ATTR_LINK_COUNT: u32 = 0x00002000;
+    pub const SSH_FILEXFER_ATTR_UNTRANSLATED_NAME: u32 = 0x00004000;
+    pub const SSH_FILEXFER_ATTR_CTIME: u32 = 0x00008000;
+
+    pub const SSH_FILEXFER_TYPE_REGULAR: u8 = 1;
+    pub const SSH_FILEXFER_TYPE_DIRECTORY: u8 = 2;
+    pub const SSH_FILEXFER_TYPE_SYMLINK: u8 = 3;
+    pub const SSH_FILEXFER_TYPE_SPECIAL: u8 = 4;
+    pub const SSH_FILEXFER_TYPE_UNKNOWN: u8 = 5;
+    pub const SSH_FILEXFER_TYPE_SOCKET: u8 = 6;
+    pub const SSH_FILEXFER_TYPE_CHAR_DEVICE: u8 = 7;
+    pub const SSH_FILEXFER_TYPE_BLOCK_DEVICE: u8 = 8;
+    pub const SSH_FILEXFER_TYPE_FIFO: u8 = 9;
+
     pub const SSH_FX_OK: u32 = 0;
     pub const SSH_FX_EOF: u32 = 1;
     pub const SSH_FX_NO_SUCH_FILE: u32 = 2;
@@ -160,6 +202,53 @@ pub mod code {
     pub const SSH_FX_NO_CONNECTION: u32 = 6;
     pub const SSH_FX_CONNECTION_LOST: u32 = 7;
     pub const SSH_FX_OP_UNSUPPORTED: u32 = 8;
+    // https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-13#section-9.1, version 4 on
+    pub const SSH_FX_INVALID_HANDLE: u32 = 9;
+    pub const SSH_FX_NO_SUCH_PATH: u32 = 10;
+    pub const SSH_FX_FILE_ALREADY_EXISTS: u32 = 11;
+    pub const SSH_FX_WRITE_PROTECT: u32 = 12;
+    pub const SSH_FX_NO_MEDIA: u32 = 13;
+    pub const SSH_FX_NO_SPACE_ON_FILESYSTEM: u32 = 14;
+    pub const SSH_FX_QUOTA_EXCEEDED: u32 = 15;
+    pub const SSH_FX_UNKNOWN_PRINCIPAL: u32 = 16;
+    pub const SSH_FX_LOCK_CONFLICT: u32 = 17;
+    pub const SSH_FX_DIR_NOT_EMPTY: u32 = 18;
+    pub const SSH_FX_NOT_A_DIRECTORY: u32 = 19;
+    pub const SSH_FX_INVALID_FILENAME: u32 = 20;
+    pub const SSH_FX_LINK_LOOP: u32 = 21;
+    pub const SSH_FX_CANNOT_DELETE: u32 = 22;
+    pub const SSH_FX_INVALID_PARAMETER: u32 = 23;
+    pub const SSH_FX_FILE_IS_A_DIRECTORY: u32 = 24;
+    pub const SSH_FX_BYTE_RANGE_LOCK_CONFLICT: u32 = 25;
+    pub const SSH_FX_BYTE_RANGE_LOCK_REFUSED: u32 = 26;
+    pub const SSH_FX_DELETE_PENDING: u32 = 27;
+    pub const SSH_FX_FILE_CORRUPT: u32 = 28;
+    pub const SSH_FX_OWNER_INVALID: u32 = 29;
+    pub const SSH_FX_GROUP_INVALID: u32 = 30;
+    pub const SSH_FX_NO_MATCHING_BYTE_RANGE_LOCK: u32 = 31;
+
+    // https://datatracker.ietf.org/doc/html/draft-miller-ssh-agent#section-5.1
+    pub const SSH_AGENT_FAILURE: u8 = 5;
+    pub const SSH_AGENT_SUCCESS: u8 = 6;
+    pub const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+    pub const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+    pub const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+    pub const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+    pub const SSH_AGENTC_ADD_IDENTITY: u8 = 17;
+    pub const SSH_AGENTC_REMOVE_IDENTITY: u8 = 18;
+    pub const SSH_AGENTC_REMOVE_ALL_IDENTITIES: u8 = 19;
+    pub const SSH_AGENTC_LOCK: u8 = 22;
+    pub const SSH_AGENTC_UNLOCK: u8 = 23;
+    pub const SSH_AGENTC_ADD_ID_CONSTRAINED: u8 = 25;
+    pub const SSH_AGENTC_EXTENSION: u8 = 27;
+    pub const SSH_AGENT_EXTENSION_FAILURE: u8 = 28;
+
+    pub const SSH_AGENT_CONSTRAIN_LIFETIME: u8 = 1;
+    pub const SSH_AGENT_CONSTRAIN_CONFIRM: u8 = 2;
+    pub const SSH_AGENT_CONSTRAIN_EXTENSION: u8 = 255;
+
+    pub const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+    pub const SSH_AGENT_RSA_SHA2_512: u32 = 4;
 }
 
 pub const KEX_STRICT_CLIENT: &str = "kex-strict-c-v00@openssh.com";
@@ -168,7 +257,8 @@ pub const EXT_INFO_CLIENT: &str = "ext-info-c";
 pub const KEX_STRICT_SERVER: &str = "kex-strict-s-v00@openssh.com";
 pub const EXT_INFO_SERVER: &str = "ext-info-s";
 
-pub const SFTP_VERSION: u32 = 3;
+/// The highest version asked for, the server answers with the one used
+pub const SFTP_VERSION: u32 = 6;
 
 // https://datatracker.ietf.org/doc/html/rfc4253#section-6.1
 pub const PAYLOAD_MAXIMUM_SIZE: usize = 32768;
@@ -186,3 +276,10 @@ pub const OPENSSH_SFTP_EXT_COPY_DATA: (&str, &[u8]) = ("copy-data", b"1");
 pub const OPENSSH_SFTP_EXT_HOME_DIRECTORY: (&str, &[u8]) = ("home-directory", b"1");
 pub const OPENSSH_SFTP_EXT_USERS_GROUPS_BY_ID: (&str, &[u8]) =
     ("users-groups-by-id@openssh.com", b"1");
+// https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-extensions-00#section-3
+pub const SFTP_EXT_CHECK_FILE: &str = "check-file";
+pub const SFTP_EXT_CHECK_FILE_HANDLE: &str = "check-file-handle";
+
+// https://github.com/openssh/openssh-portable/blob/master/authfd.h
+pub const AGENT_MAXIMUM_SIZE: usize = 256 * 1024;
+pub const AGENT_SOCK_ENV: &str = "SSH_AUTH_SOCK";
@@ -0,0 +1,503 @@
+//! Signatures made with ssh keys over arbitrary data, as `ssh-keygen -Y sign` does, see
+//! https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.sshsig
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::base64::{decode_block, encode_block};
+use openssl::hash::{hash, MessageDigest};
+
+use crate::cipher::sign;
+use crate::error::{Error, Result};
+use crate::keys::{CertType, Certificate, PrivateKey, PublicKey};
+use crate::ssh::buffer::Buffer;
+
+const MAGIC: &[u8] = b"SSHSIG";
+const VERSION: u32 = 1;
+const BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+const END: &str = "-----END SSH SIGNATURE-----";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+        }
+    }
+
+    fn from_name(name: &[u8]) -> Result<Self> {
+        match name {
+            b"sha256" => Ok(Self::Sha256),
+            b"sha512" => Ok(Self::Sha512),
+            _ => Err(Error::invalid_format("unsupported sshsig hash algorithm")),
+        }
+    }
+
+    fn digest(&self) -> MessageDigest {
+        match self {
+            Self::Sha256 => MessageDigest::sha256(),
+            Self::Sha512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshSig {
+    /// The public key blob of the signer, may be a certificate
+    pub public_key: Vec<u8>,
+    pub namespace: String,
+    pub reserved: Vec<u8>,
+    pub hash_algorithm: HashAlgorithm,
+    /// The signature blob `string algorithm, string signature`
+    pub signature: Vec<u8>,
+}
+
+impl SshSig {
+    /// Sign `message` in `namespace`, e.g. "git" or "file", RSA keys sign with rsa-sha2-512
+    pub fn sign(
+        key: &PrivateKey,
+        namespace: &str,
+        hash_algorithm: HashAlgorithm,
+        message: &[u8],
+    ) -> Result<Self> {
+        if namespace.is_empty() {
+            return Err(Error::invalid_format("sshsig namespace is empty"));
+        }
+
+        let method = match key.key_type.as_str() {
+            "ssh-rsa" => "rsa-sha2-512",
+            key_type => key_type,
+        };
+
+        let mut algo =
+            sign::new_signature_by_name(method).ok_or(Error::ub("Unable to create cipher"))?();
+        algo.initialize(&key.private_key)?;
+
+        let data = signed_data(namespace, &[], hash_algorithm, message)?;
+        let signed = algo.signature(&data)?;
+        let signature = make_buffer_without_header! {
+            one: method,
+            one: &signed,
+        };
+
+        Ok(Self {
+            public_key: key.public_key.clone(),
+            namespace: namespace.to_string(),
+            reserved: vec![],
+            hash_algorithm,
+            signature: signature.into_vec(),
+        })
+    }
+
+    /// Check the signature over `message`, the signer is `self.public_key`,
+    /// whether it's trusted is up to the caller, see `AllowedSigners`
+    pub fn verify(&self, namespace: &str, message: &[u8]) -> Result<bool> {
+        if namespace != self.namespace {
+            return Ok(false);
+        }
+
+        let (_, method) = Buffer::from_slice(&self.signature)
+            .take_one()
+            .ok_or(Error::invalid_format("invalid sshsig signature"))?;
+        let method = std::str::from_utf8(method)?;
+
+        // sha1 is not allowed
+        if method == "ssh-rsa" {
+            return Ok(false);
+        }
+
+        let key = match self.certificate()? {
+            Some(certificate) => certificate.public_key,
+            None => self.public_key.clone(),
+        };
+
+        let Some(factory) = sign::new_verify_by_name(method) else {
+            return Ok(false);
+        };
+        let mut verify = factory();
+        verify.initialize(&key)?;
+
+        let data = signed_data(
+            &self.namespace,
+            &self.reserved,
+            self.hash_algorithm,
+            message,
+        )?;
+        verify.verify(&self.signature, &data)
+    }
+
+    /// The certificate the signer presented instead of a bare key, if any
+    pub fn certificate(&self) -> Result<Option<Certificate>> {
+        let (_, key_type) = Buffer::from_slice(&self.public_key)
+            .take_one()
+            .ok_or(Error::invalid_format("invalid sshsig public key"))?;
+
+        match Certificate::is_certificate(std::str::from_utf8(key_type)?) {
+            true => Ok(Some(Certificate::parse(&self.public_key)?)),
+            false => Ok(None),
+        }
+    }
+
+    pub fn parse(blob: &[u8]) -> Result<Self> {
+        let invalid = || Error::invalid_format("invalid sshsig format");
+        let buffer = Buffer::from_slice(blob);
+
+        if buffer.take_bytes(MAGIC.len()).ok_or_else(invalid)? != MAGIC {
+            return Err(invalid());
+        }
+        if buffer.take_u32().ok_or_else(invalid)? != VERSION {
+            return Err(Error::invalid_format("unsupported sshsig version"));
+        }
+
+        let public_key = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+        let namespace = std::str::from_utf8(buffer.take_one().ok_or_else(invalid)?.1)?;
+        let reserved = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+        let hash_algorithm = HashAlgorithm::from_name(buffer.take_one().ok_or_else(invalid)?.1)?;
+        let signature = buffer.take_one().ok_or_else(invalid)?.1.to_vec();
+
+        Ok(Self {
+            public_key,
+            namespace: namespace.to_string(),
+            reserved,
+            hash_algorithm,
+            signature,
+        })
+    }
+
+    /// Parse the `-----BEGIN SSH SIGNATURE-----` armored form
+    pub fn parse_armored(content: &str) -> Result<Self> {
+        let content = content.trim();
+        let body = content
+            .strip_prefix(BEGIN)
+            .and_then(|content| content.strip_suffix(END))
+            .ok_or(Error::invalid_format("invalid sshsig armor"))?;
+
+        let encoded: String = body.split_whitespace().collect();
+        Self::parse(&decode_block(&encoded)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        make_buffer_without_header! {
+            bytes: MAGIC,
+            u32: VERSION,
+            one: &self.public_key,
+            one: &self.namespace,
+            one: &self.reserved,
+            one: self.hash_algorithm.name(),
+            one: &self.signature,
+        }
+        .into_vec()
+    }
+
+    pub fn to_armored(&self) -> String {
+        let mut content = format!("{BEGIN}\n");
+        let encoded = encode_block(&self.to_bytes());
+        for line in encoded.as_bytes().chunks(70) {
+            content.push_str(&String::from_utf8_lossy(line));
+            content.push('\n');
+        }
+        content.push_str(END);
+        content.push('\n');
+        content
+    }
+}
+
+fn signed_data(
+    namespace: &str,
+    reserved: &[u8],
+    hash_algorithm: HashAlgorithm,
+    message: &[u8],
+) -> Result<Vec<u8>> {
+    let digest = hash(hash_algorithm.digest(), message)?;
+    Ok(make_buffer_without_header! {
+        bytes: MAGIC,
+        one: namespace,
+        one: reserved,
+        one: hash_algorithm.name(),
+        one: &*digest,
+    }
+    .into_vec())
+}
+
+/// One line of an allowed signers file, see `ALLOWED SIGNERS` in ssh-keygen(1)
+pub struct AllowedSigner {
+    /// Patterns matched against the principal, `*`, `?` and `!` negation are supported
+    pub principals: Vec<String>,
+    /// The key is a CA trusted to certify the principals
+    pub cert_authority: bool,
+    /// None means any namespace
+    pub namespaces: Option<Vec<String>>,
+    /// Seconds since the unix epoch
+    pub valid_after: Option<u64>,
+    /// Seconds since the unix epoch
+    pub valid_before: Option<u64>,
+    pub key: PublicKey,
+}
+
+pub struct AllowedSigners {
+    pub signers: Vec<AllowedSigner>,
+}
+
+impl AllowedSigners {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut signers = vec![];
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            signers.push(AllowedSigner::parse(line)?);
+        }
+        Ok(Self { signers })
+    }
+
+    /// Verify that `signature` over `message` was made in `namespace` by a key trusted for `principal`
+    pub fn verify(
+        &self,
+        principal: &str,
+        namespace: &str,
+        message: &[u8],
+        signature: &SshSig,
+    ) -> Result<bool> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let certificate = signature.certificate()?;
+        let trusted = self.signers.iter().any(|signer| {
+            signer.allows(principal, namespace, now)
+                && match &certificate {
+                    Some(certificate) => {
+                        signer.cert_authority
+                            && certificate.signature_key == signer.key.key
+                            && certificate.cert_type == CertType::User
+                            && certificate.is_valid_at(now)
+                            && certificate.principals.iter().any(|p| p == principal)
+                            && certificate.verify_signature().unwrap_or(false)
+                    }
+                    None => !signer.cert_authority && signature.public_key == signer.key.key,
+                }
+        });
+
+        Ok(trusted && signature.verify(namespace, message)?)
+    }
+}
+
+impl AllowedSigner {
+    fn parse(line: &str) -> Result<Self> {
+        let invalid = || Error::invalid_format("invalid allowed signers line");
+        let mut fields = split_fields(line).into_iter();
+
+        let principals = unquote(&fields.next().ok_or_else(invalid)?)
+            .split(',')
+            .map(|p| p.to_string())
+            .collect();
+
+        let mut signer = Self {
+            principals,
+            cert_authority: false,
+            namespaces: None,
+            valid_after: None,
+            valid_before: None,
+            key: PublicKey {
+                key_type: String::new(),
+                key: vec![],
+            },
+        };
+
+        let mut field = fields.next().ok_or_else(invalid)?;
+        // a key type never contains '=' or ',', options always do or are known words
+        if field == "cert-authority" || field.contains('=') || field.contains(',') {
+            for option in split_options(&field) {
+                let (name, value) = match option.split_once('=') {
+                    Some((name, value)) => (name.to_ascii_lowercase(), Some(unquote(value))),
+                    None => (option.to_ascii_lowercase(), None),
+                };
+                match (name.as_str(), value) {
+                    ("cert-authority", None) => signer.cert_authority = true,
+                    ("namespaces", Some(value)) => {
+                        signer.namespaces = Some(value.split(',').map(|n| n.to_string()).collect())
+                    }
+                    ("valid-after", Some(value)) => signer.valid_after = Some(parse_time(&value)?),
+                    ("valid-before", Some(value)) => {
+                        signer.valid_before = Some(parse_time(&value)?)
+                    }
+                    _ => {
+                        return Err(Error::invalid_format(format!(
+                            "unsupported allowed signers option {option}"
+                        )))
+                    }
+                }
+            }
+            field = fields.next().ok_or_else(invalid)?;
+        }
+
+        let key = fields.next().ok_or_else(invalid)?;
+        signer.key = PublicKey {
+            key_type: field,
+            key: decode_block(&key)?,
+        };
+
+        Ok(signer)
+    }
+
+    fn allows(&self, principal: &str, namespace: &str, now: u64) -> bool {
+        match_pattern_list(principal, &self.principals)
+            && self
+                .namespaces
+                .as_ref()
+                .is_none_or(|namespaces| match_pattern_list(namespace, namespaces))
+            && self.valid_after.is_none_or(|after| now >= after)
+            && self.valid_before.is_none_or(|before| now < before)
+    }
+}
+
+/// Split on whitespace outside of double quotes
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    fields.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        fields.push(current);
+    }
+    fields
+}
+
+/// Split on commas outside of double quotes
+fn split_options(field: &str) -> Vec<String> {
+    let mut options = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in field.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => options.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    options.push(current);
+    options
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+/// `YYYYMMDD[HHMM[SS]][Z]`, in the local time zone unless it ends with `Z` as OpenSSH reads it.
+/// Without a time zone to go by, i.e. not on unix, only UTC is accepted
+fn parse_time(value: &str) -> Result<u64> {
+    let invalid = || Error::invalid_format(format!("invalid time {value}"));
+    let (digits, utc) = match value.strip_suffix(['Z', 'z']) {
+        Some(digits) => (digits, true),
+        None => (value, false),
+    };
+    if !digits.bytes().all(|b| b.is_ascii_digit()) || ![8, 12, 14].contains(&digits.len()) {
+        return Err(invalid());
+    }
+
+    let number = |range: std::ops::Range<usize>| -> u64 {
+        digits.get(range).map_or(0, |s| s.parse().unwrap_or(0))
+    };
+    let (year, month, day) = (number(0..4) as i64, number(4..6), number(6..8));
+    let (hour, minute, second) = (number(8..10), number(10..12), number(12..14));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+
+    if !utc {
+        return local_time(year, [month, day, hour, minute, second]).ok_or_else(invalid);
+    }
+
+    // days from civil, http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    u64::try_from(days * 86400)
+        .map(|secs| secs + hour * 3600 + minute * 60 + second)
+        .map_err(|_| invalid())
+}
+
+/// The seconds since the epoch when the local clock shows the date and time
+#[cfg(unix)]
+fn local_time(year: i64, [month, day, hour, minute, second]: [u64; 5]) -> Option<u64> {
+    // SAFETY: an all zero `tm` is valid, mktime only reads it and the time zone of the process
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = (year - 1900) as _;
+    tm.tm_mon = (month - 1) as _;
+    tm.tm_mday = day as _;
+    tm.tm_hour = hour as _;
+    tm.tm_min = minute as _;
+    tm.tm_sec = second as _;
+    // whether daylight saving applies is up to mktime
+    tm.tm_isdst = -1;
+    let time = unsafe { libc::mktime(&mut tm) };
+    u64::try_from(time).ok()
+}
+
+#[cfg(not(unix))]
+fn local_time(_: i64, _: [u64; 5]) -> Option<u64> {
+    None
+}
+
+fn match_pattern_list(name: &str, patterns: &[String]) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(pattern) if match_pattern(name.as_bytes(), pattern.as_bytes()) => return false,
+            Some(_) => {}
+            None => matched |= match_pattern(name.as_bytes(), pattern.as_bytes()),
+        }
+    }
+    matched
+}
+
+fn match_pattern(name: &[u8], pattern: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            match_pattern(name, &pattern[1..])
+                || (!name.is_empty() && match_pattern(&name[1..], pattern))
+        }
+        (Some(b'?'), Some(_)) => match_pattern(&name[1..], &pattern[1..]),
+        (Some(p), Some(n)) if p == n => match_pattern(&name[1..], &pattern[1..]),
+        _ => false,
+    }
+}